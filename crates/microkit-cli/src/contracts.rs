@@ -0,0 +1,56 @@
+use crate::run_command;
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+/// Contracts crate of a service created by `mk new`
+const CONTRACTS_MANIFEST: &str = "crates/contracts/Cargo.toml";
+
+/// Example in the contracts crate that lists the contracts to export
+const EXPORT_EXAMPLE: &str = "export_schemas";
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Write a JSON Schema file for each contract, for consumers in other languages
+    Export {
+        /// Directory the schemas are written to
+        #[arg(short, long, default_value = "schemas")]
+        out: PathBuf,
+    },
+}
+
+pub fn export(out: &Path) -> Result<()> {
+    let package = package_name()?;
+    let out = out.to_string_lossy();
+
+    println!("Exporting contract schemas to {}", out);
+    run_command(
+        "cargo",
+        &[
+            "run",
+            "--quiet",
+            "--package",
+            &package,
+            "--features",
+            "utoipa",
+            "--example",
+            EXPORT_EXAMPLE,
+            "--",
+            &out,
+        ],
+    )
+    .context("Failed to export contract schemas")
+}
+
+/// Package name of the contracts crate, which `--prefix-crates` renames
+fn package_name() -> Result<String> {
+    let manifest = std::fs::read_to_string(CONTRACTS_MANIFEST)
+        .with_context(|| format!("Failed to read {}", CONTRACTS_MANIFEST))?;
+    let doc = manifest.parse::<DocumentMut>()?;
+
+    doc["package"]["name"]
+        .as_str()
+        .map(str::to_string)
+        .with_context(|| format!("No package name in {}", CONTRACTS_MANIFEST))
+}
@@ -18,7 +18,7 @@ pub enum Commands {
 
 pub fn entity(config: &Config) -> Result<()> {
     println!("Generating entities");
-    let (_database_url, database_name, database_with_name) = get_database_details(config)?;
+    let (_database_url, _database_name, database_with_name) = get_database_details(config)?;
     run_command(
         "sea-orm-cli",
         &[
@@ -27,7 +27,7 @@ pub fn entity(config: &Config) -> Result<()> {
             "--database-url",
             &database_with_name,
             "--database-schema",
-            database_name,
+            database_schema(config),
             // "--with-prelude",
             // "all",
             "--with-serde",
@@ -41,7 +41,7 @@ pub fn entity(config: &Config) -> Result<()> {
 
 pub fn migrate(config: &Config, name: &str) -> Result<()> {
     println!("Generating migration: {}", name);
-    let (database_url, database_name, _database_with_name) = get_database_details(config)?;
+    let (database_url, _database_name, _database_with_name) = get_database_details(config)?;
     run_command(
         "sea-orm-cli",
         &[
@@ -53,7 +53,7 @@ pub fn migrate(config: &Config, name: &str) -> Result<()> {
             "--database-url",
             database_url,
             "--database-schema",
-            database_name,
+            database_schema(config),
             name,
         ],
     )
@@ -62,7 +62,7 @@ pub fn migrate(config: &Config, name: &str) -> Result<()> {
 
 pub fn fresh(config: &Config) -> Result<()> {
     println!("Dropping all tables and re-applying migrations");
-    let (database_url, database_name, _database_with_name) = get_database_details(config)?;
+    let (_database_url, _database_name, database_with_name) = get_database_details(config)?;
     run_command(
         "sea-orm-cli",
         &[
@@ -71,14 +71,22 @@ pub fn fresh(config: &Config) -> Result<()> {
             "-d",
             "crates/migrations",
             "--database-url",
-            database_url,
+            &database_with_name,
             "--database-schema",
-            database_name,
+            database_schema(config),
         ],
     )
     .context("Failed to refresh database migrations")
 }
 
+/// Schema entities and migrations are generated for, matching the service's `search_path`
+fn database_schema(config: &Config) -> &str {
+    config
+        .database_schema
+        .as_deref()
+        .unwrap_or(microkit::database::DEFAULT_SCHEMA)
+}
+
 fn get_database_details(config: &Config) -> Result<(&str, &str, String)> {
     let database_url = match &config.database_url {
         Some(x) => x,
@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+/// MicroKit features that are always enabled in generated services
+const BASE_FEATURES: &[&str] = &["tracing", "swagger", "http-client"];
+
+/// Example endpoint written when the user endpoints are removed, so the API still has a route
+const HELLO_ENDPOINT: &str = r#"const GROUP: &str = "Hello";
+const PATH: &str = "/api/v1/hello";
+
+/// Say hello
+#[utoipa::path(
+    get,
+    path = PATH,
+    tag = GROUP,
+    responses((status = 200, description = "Greeting", body = String))
+)]
+pub async fn api_hello() -> &'static str {
+    "Hello from MicroKit"
+}
+"#;
+
+/// Optional MicroKit subsystems a service can be generated with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Feature {
+    Database,
+    Dapr,
+    Auth,
+    Otel,
+    HealthChecks,
+}
+
+impl Feature {
+    /// Every feature, the default selection
+    pub(crate) const ALL: &[Feature] = &[
+        Feature::Database,
+        Feature::Dapr,
+        Feature::Auth,
+        Feature::Otel,
+        Feature::HealthChecks,
+    ];
+
+    /// Name of the MicroKit cargo feature
+    fn cargo_feature(self) -> &'static str {
+        match self {
+            Feature::Database => "database",
+            Feature::Dapr => "dapr",
+            Feature::Auth => "auth",
+            Feature::Otel => "otel",
+            Feature::HealthChecks => "health-checks",
+        }
+    }
+
+    /// Builder calls in `main.rs` that require this feature
+    fn builder_calls(self) -> &'static [&'static str] {
+        match self {
+            Feature::Database => &[".with_database()", ".with_migrations::<"],
+            Feature::Dapr => &[".with_dapr()"],
+            Feature::Auth => &[".with_auth()"],
+            Feature::Otel => &[".with_otel()"],
+            Feature::HealthChecks => &[".with_health_checks()"],
+        }
+    }
+}
+
+/// Strip the template down to the selected features
+///
+/// Removes unused builder calls, narrows the MicroKit features, and drops the
+/// example code that depends on missing features:
+/// - without database, the entities and migrations crates and all user endpoints
+/// - without dapr, the event consumer endpoint
+/// - without auth, the user API endpoints and the website that calls them
+///
+/// When the user API endpoints are removed a hello endpoint takes their place
+pub(crate) fn apply(target_dir: &Path, features: &[Feature]) -> Result<()> {
+    let has = |feature: Feature| features.contains(&feature);
+
+    update_main(target_dir, features)?;
+    update_microkit_dependency(target_dir, features)?;
+
+    let endpoints = target_dir.join("crates/api/src/endpoints");
+
+    if !(has(Feature::Database) && has(Feature::Dapr)) {
+        remove(&endpoints.join("consumer"))?;
+        remove(&target_dir.join("dapr/subscription-users.yaml"))?;
+    }
+
+    if !(has(Feature::Database) && has(Feature::Auth)) {
+        remove(&endpoints.join("api"))?;
+        std::fs::create_dir_all(endpoints.join("api"))?;
+        std::fs::write(endpoints.join("api/hello.rs"), HELLO_ENDPOINT)
+            .context("Failed to write the example endpoint")?;
+        remove(&target_dir.join("crates/website"))?;
+        remove_dapr_apps(target_dir, &["website", "tailwind"])?;
+    }
+
+    if !has(Feature::Database) {
+        remove(&target_dir.join("crates/entities"))?;
+        remove(&target_dir.join("crates/migrations"))?;
+        remove_dependencies(&target_dir.join("Cargo.toml"), &["entities", "migrations"])?;
+        remove_dependencies(
+            &target_dir.join("crates/api/Cargo.toml"),
+            &["entities", "migrations", "sea-orm"],
+        )?;
+    }
+
+    let names: Vec<_> = features.iter().map(|f| f.cargo_feature()).collect();
+    println!("Enabled features: {}", names.join(", "));
+
+    Ok(())
+}
+
+fn update_main(target_dir: &Path, features: &[Feature]) -> Result<()> {
+    let main_path = target_dir.join("crates/api/src/main.rs");
+    let main = std::fs::read_to_string(&main_path).context("Failed to read api main.rs")?;
+
+    let removed: Vec<&str> = Feature::ALL
+        .iter()
+        .filter(|feature| !features.contains(feature))
+        .flat_map(|feature| feature.builder_calls())
+        .copied()
+        .collect();
+
+    let main: String = main
+        .lines()
+        .filter(|line| !removed.iter().any(|call| line.trim().starts_with(call)))
+        .map(|line| format!("{}\n", line))
+        .collect();
+
+    std::fs::write(&main_path, main).context("Failed to write api main.rs")
+}
+
+fn update_microkit_dependency(target_dir: &Path, features: &[Feature]) -> Result<()> {
+    let cargo_toml_path = target_dir.join("Cargo.toml");
+    let mut doc = std::fs::read_to_string(&cargo_toml_path)?.parse::<DocumentMut>()?;
+
+    if let Some(workspace) = doc["workspace"].as_table_mut()
+        && let Some(deps) = workspace["dependencies"].as_table_mut()
+        && let Some(microkit) = deps.get_mut("microkit")
+    {
+        // A bare version string becomes a table so features can be added
+        if let Some(version) = microkit.as_str() {
+            let mut table = toml_edit::InlineTable::new();
+            table.insert("version", version.into());
+            *microkit = toml_edit::value(table);
+        }
+
+        if let Some(table) = microkit.as_inline_table_mut() {
+            let mut cargo_features = toml_edit::Array::new();
+            for feature in BASE_FEATURES {
+                cargo_features.push(*feature);
+            }
+            for feature in features {
+                cargo_features.push(feature.cargo_feature());
+            }
+
+            table.insert("default-features", false.into());
+            table.insert("features", cargo_features.into());
+            table.fmt();
+        }
+    }
+
+    std::fs::write(&cargo_toml_path, doc.to_string())?;
+
+    Ok(())
+}
+
+fn remove_dependencies(manifest_path: &Path, names: &[&str]) -> Result<()> {
+    let mut doc = std::fs::read_to_string(manifest_path)?.parse::<DocumentMut>()?;
+
+    let deps = match doc.get_mut("workspace") {
+        Some(workspace) => workspace.get_mut("dependencies"),
+        None => doc.get_mut("dependencies"),
+    };
+
+    if let Some(deps) = deps.and_then(|deps| deps.as_table_mut()) {
+        for name in names {
+            deps.remove(name);
+        }
+    }
+
+    std::fs::write(manifest_path, doc.to_string())
+        .with_context(|| format!("Failed to write '{}'", manifest_path.display()))
+}
+
+fn remove_dapr_apps(target_dir: &Path, app_ids: &[&str]) -> Result<()> {
+    let dapr_path = target_dir.join("dapr.yaml");
+    if !dapr_path.exists() {
+        return Ok(());
+    }
+
+    let contents = std::fs::read_to_string(&dapr_path)?;
+    let mut dapr: serde_yaml_ng::Value =
+        serde_yaml_ng::from_str(&contents).context("Failed to parse dapr.yaml")?;
+
+    if let Some(apps) = dapr.get_mut("apps").and_then(|apps| apps.as_sequence_mut()) {
+        apps.retain(|app| {
+            app.get("appID")
+                .and_then(|id| id.as_str())
+                .is_none_or(|id| !app_ids.contains(&id))
+        });
+    }
+
+    let updated = serde_yaml_ng::to_string(&dapr).context("Failed to serialize dapr.yaml")?;
+    std::fs::write(&dapr_path, updated).context("Failed to write dapr.yaml")
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        return Ok(());
+    }
+    .with_context(|| format!("Failed to remove '{}'", path.display()))
+}
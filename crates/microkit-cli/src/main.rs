@@ -1,5 +1,9 @@
+pub(crate) mod config;
+pub(crate) mod contracts;
 pub(crate) mod database;
+pub(crate) mod features;
 pub(crate) mod new;
+pub(crate) mod ports;
 pub(crate) mod run;
 pub(crate) mod setup;
 
@@ -31,10 +35,20 @@ enum Commands {
     Run {
         /// Name of the binary to run. If not provided, dapr will execute
         name: Option<String>,
+        /// Variables to set for the run, as KEY=value lines or a YAML map of KEY: value,
+        /// repeatable (default: .env when present)
+        #[arg(short, long = "env-file")]
+        env_files: Vec<PathBuf>,
     },
     /// Database-related commands
     #[command(subcommand)]
     Db(database::Commands),
+    /// Config-related commands
+    #[command(subcommand)]
+    Config(config::Commands),
+    /// Contract-related commands
+    #[command(subcommand)]
+    Contracts(contracts::Commands),
 }
 
 #[tokio::main]
@@ -47,9 +61,9 @@ async fn main() -> Result<()> {
             cwd_check_set()?;
             setup::exec()
         }
-        Commands::Run { name } => {
+        Commands::Run { name, env_files } => {
             cwd_check_set()?;
-            run::exec(name)
+            run::exec(name, env_files)
         }
         Commands::Db(cmd) => {
             cwd_check_set()?;
@@ -60,6 +74,17 @@ async fn main() -> Result<()> {
                 database::Commands::Fresh => database::fresh(&config),
             }
         }
+        Commands::Config(config::Commands::Check { file }) => match file {
+            Some(file) => config::check(&file),
+            None => {
+                cwd_check_set()?;
+                config::check(Path::new("microkit.yml"))
+            }
+        },
+        Commands::Contracts(contracts::Commands::Export { out }) => {
+            cwd_check_set()?;
+            contracts::export(&out)
+        }
     }
 }
 
@@ -89,10 +114,20 @@ fn load_config() -> Result<Config> {
 }
 
 pub(crate) fn run_command(program: &str, args: &[&str]) -> Result<()> {
+    run_command_with_env(program, args, &[])
+}
+
+/// Run `program` like `run_command`, with `env` added to its environment
+pub(crate) fn run_command_with_env(
+    program: &str,
+    args: &[&str],
+    env: &[(String, String)],
+) -> Result<()> {
     let cmd_str = format!("{} {}", program, args.join(" "));
 
     let mut child = Command::new(program)
         .args(args)
+        .envs(env.iter().map(|(key, value)| (key, value)))
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
@@ -1,3 +1,4 @@
+use crate::features::Feature;
 use anyhow::{Context, Result, bail};
 use clap::Parser;
 use microkit::config::Config;
@@ -24,6 +25,12 @@ pub(crate) struct NewArgs {
     /// The MicroKit git tag to create the service from (default: latest version from crates.io)
     #[arg(short, long)]
     tag: Option<String>,
+    /// MicroKit features to generate the service with (default: all)
+    #[arg(short, long, value_enum, value_delimiter = ',', default_values_t = Feature::ALL.to_vec())]
+    features: Vec<Feature>,
+    /// Prefix crate and binary names with the service name (e.g. orders-api) so services can share a workspace
+    #[arg(long)]
+    prefix_crates: bool,
 }
 
 #[cfg(not(debug_assertions))]
@@ -62,6 +69,8 @@ async fn exec_impl(args: NewArgs) -> Result<()> {
         );
     }
 
+    crate::ports::check(&target_dir, &args.name, args.port_offset)?;
+
     std::fs::create_dir(&target_dir)
         .with_context(|| format!("Failed to create directory '{}'", target_dir.display()))?;
 
@@ -77,8 +86,14 @@ async fn exec_impl(args: NewArgs) -> Result<()> {
 
     update_config(&target_dir, &args.name, args.description, args.port_offset)?;
 
+    if args.prefix_crates {
+        prefix_crates(&target_dir, &args.name)?;
+    }
+
     fix_debug_cargo_paths(&target_dir)?;
 
+    crate::features::apply(&target_dir, &args.features)?;
+
     println!("Created service '{}' successfully", args.name);
 
     Ok(())
@@ -97,6 +112,8 @@ async fn exec_impl(args: NewArgs) -> Result<()> {
         );
     }
 
+    crate::ports::check(&target_dir, &args.name, args.port_offset)?;
+
     std::fs::create_dir(&target_dir)
         .with_context(|| format!("Failed to create directory '{}'", target_dir.display()))?;
 
@@ -121,9 +138,15 @@ async fn exec_impl(args: NewArgs) -> Result<()> {
 
     update_config(&target_dir, &args.name, args.description, args.port_offset)?;
 
+    if args.prefix_crates {
+        prefix_crates(&target_dir, &args.name)?;
+    }
+
     // In release mode, update to specific version tag
     update_kit_reference(&target_dir, &version)?;
 
+    crate::features::apply(&target_dir, &args.features)?;
+
     println!("Created service '{}' successfully", args.name);
 
     Ok(())
@@ -311,6 +334,104 @@ fn update_config(
     Ok(())
 }
 
+/// Rename each crate to `{service}-{crate}` while keeping the names code refers to
+///
+/// Workspace dependencies keep their keys and point at the renamed package, and
+/// library targets keep their crate names, so only package and binary names change.
+/// `dapr.yaml` and each crate's Dockerfile build the renamed binaries
+fn prefix_crates(target_dir: &Path, name: &str) -> Result<()> {
+    let prefix: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .to_lowercase();
+
+    let mut renamed = Vec::new();
+
+    for entry in std::fs::read_dir(target_dir.join("crates")).context("Failed to read crates")? {
+        let crate_dir = entry?.path();
+        let manifest_path = crate_dir.join("Cargo.toml");
+        if !manifest_path.exists() {
+            continue;
+        }
+
+        let mut doc = std::fs::read_to_string(&manifest_path)?.parse::<DocumentMut>()?;
+        let Some(crate_name) = doc["package"]["name"].as_str().map(str::to_string) else {
+            continue;
+        };
+        let package_name = format!("{}-{}", prefix, crate_name);
+
+        doc["package"]["name"] = toml_edit::value(&package_name);
+        if crate_dir.join("src/lib.rs").exists() && doc.get("lib").is_none() {
+            let mut lib = toml_edit::Table::new();
+            lib["name"] = toml_edit::value(crate_name.replace('-', "_"));
+            doc["lib"] = toml_edit::Item::Table(lib);
+        }
+
+        std::fs::write(&manifest_path, doc.to_string())
+            .with_context(|| format!("Failed to write '{}'", manifest_path.display()))?;
+
+        // The image keeps the binary's old name, only where cargo builds it changes
+        let dockerfile_path = crate_dir.join("Dockerfile");
+        if dockerfile_path.exists() {
+            let dockerfile = std::fs::read_to_string(&dockerfile_path)?
+                .replace(
+                    &format!("--bin {}\n", crate_name),
+                    &format!("--bin {}\n", package_name),
+                )
+                .replace(
+                    &format!("target/release/{} ", crate_name),
+                    &format!("target/release/{} ", package_name),
+                );
+            std::fs::write(&dockerfile_path, dockerfile)
+                .with_context(|| format!("Failed to write '{}'", dockerfile_path.display()))?;
+        }
+
+        renamed.push((crate_name, package_name));
+    }
+
+    renamed.sort();
+
+    let cargo_toml_path = target_dir.join("Cargo.toml");
+    let mut doc = std::fs::read_to_string(&cargo_toml_path)?.parse::<DocumentMut>()?;
+
+    if let Some(workspace) = doc["workspace"].as_table_mut()
+        && let Some(deps) = workspace["dependencies"].as_table_mut()
+    {
+        for (crate_name, package_name) in &renamed {
+            if let Some(dep) = deps
+                .get_mut(crate_name)
+                .and_then(|dep| dep.as_inline_table_mut())
+            {
+                dep.insert("package", package_name.as_str().into());
+                dep.fmt();
+            }
+        }
+    }
+
+    std::fs::write(&cargo_toml_path, doc.to_string())?;
+
+    let dapr_path = target_dir.join("dapr.yaml");
+    if dapr_path.exists() {
+        let mut dapr = std::fs::read_to_string(&dapr_path)?;
+        for (crate_name, package_name) in &renamed {
+            for flag in ["--bin", "--package"] {
+                dapr = dapr.replace(
+                    &format!("\"{}\", \"{}\"", flag, crate_name),
+                    &format!("\"{}\", \"{}\"", flag, package_name),
+                );
+            }
+        }
+        std::fs::write(&dapr_path, dapr).context("Failed to write dapr.yaml")?;
+    }
+
+    for (crate_name, package_name) in &renamed {
+        println!("Renamed crate '{}' to '{}'", crate_name, package_name);
+    }
+
+    Ok(())
+}
+
 #[cfg(not(debug_assertions))]
 fn update_kit_reference(target_dir: &Path, tag: &str) -> Result<()> {
     let cargo_toml_path = target_dir.join("Cargo.toml");
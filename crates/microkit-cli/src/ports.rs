@@ -0,0 +1,101 @@
+use anyhow::Result;
+use microkit::ServicePort;
+use microkit::config::Config;
+use std::path::Path;
+
+/// A neighbouring service's name and effective ports
+struct Service {
+    name: String,
+    ports: Vec<(ServicePort, u16)>,
+}
+
+/// Print the new service's port map and warn about collisions with sibling services
+///
+/// Siblings are directories next to `target_dir` that contain a `microkit.yml`
+pub(crate) fn check(target_dir: &Path, name: &str, port_offset: u16) -> Result<()> {
+    let ports = effective_ports(port_offset)?;
+
+    println!("Ports for '{}' (offset {}):", name, port_offset);
+    for (role, port) in &ports {
+        println!("  {}: {}", role, port);
+    }
+
+    let siblings = siblings(target_dir);
+    let mut collided = false;
+
+    for (role, port) in &ports {
+        for sibling in &siblings {
+            for (other_role, other_port) in &sibling.ports {
+                if port == other_port {
+                    collided = true;
+                    println!(
+                        "Warning: {} port {} collides with the {} port of '{}'",
+                        role, port, other_role, sibling.name
+                    );
+                }
+            }
+        }
+    }
+
+    if collided && let Some(offset) = free_offset(&siblings) {
+        println!(
+            "Hint: port offset {} is free, set port_offset in microkit.yml",
+            offset
+        );
+    }
+
+    Ok(())
+}
+
+fn effective_ports(port_offset: u16) -> Result<Vec<(ServicePort, u16)>> {
+    ServicePort::ROLES
+        .into_iter()
+        .map(|role| {
+            let port = role.get_with_offset(port_offset)?;
+            Ok((role, port))
+        })
+        .collect()
+}
+
+fn siblings(target_dir: &Path) -> Vec<Service> {
+    let Some(parent) = target_dir.parent() else {
+        return Vec::new();
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name() != target_dir.file_name())
+        .filter_map(|path| {
+            let contents = std::fs::read_to_string(path.join("microkit.yml")).ok()?;
+            let config: Config = serde_yaml_ng::from_str(&contents).ok()?;
+            // Services without an offset bind port 80 and can't collide here
+            let ports = effective_ports(config.port_offset?).ok()?;
+            Some(Service {
+                name: config.service_name,
+                ports,
+            })
+        })
+        .collect()
+}
+
+/// Smallest multiple of 10 that doesn't collide with any sibling
+fn free_offset(siblings: &[Service]) -> Option<u16> {
+    (0..=u16::MAX).step_by(10).find(|&offset| {
+        effective_ports(offset).is_ok_and(|ports| {
+            ports.iter().all(|(_, port)| {
+                siblings
+                    .iter()
+                    .all(|sibling| sibling.ports.iter().all(|(_, other)| other != port))
+            })
+        })
+    })
+}
@@ -1,13 +1,151 @@
-use crate::run_command;
-use anyhow::{Context, Result};
+use crate::run_command_with_env;
+use anyhow::{Context, Result, bail};
+use serde_yaml_ng::Value;
+use std::path::{Path, PathBuf};
+
+/// Loaded when present and no `--env-file` is given
+const DEFAULT_ENV_FILE: &str = ".env";
+
+pub fn exec(name: Option<String>, env_files: Vec<PathBuf>) -> Result<()> {
+    let env = load_env(&env_files)?;
 
-pub fn exec(name: Option<String>) -> Result<()> {
     if let Some(name) = name {
         println!("Running binary: {}", &name);
-        run_command("cargo", &["run", "--bin", &name])
+        run_command_with_env("cargo", &["run", "--bin", &name], &env)
             .with_context(|| format!("Failed to run binary '{}'", &name))
     } else {
         println!("Running all services");
-        run_command("dapr", &["run", "-f", "."]).context("Failed to run services with dapr")
+        run_command_with_env("dapr", &["run", "-f", "."], &env)
+            .context("Failed to run services with dapr")
+    }
+}
+
+/// Variables from `files` in order, later files overriding earlier ones, or from
+/// `.env` when none are given and it exists
+///
+/// Variables already exported in the shell win, as with other dotenv tools
+fn load_env(files: &[PathBuf]) -> Result<Vec<(String, String)>> {
+    let default = [PathBuf::from(DEFAULT_ENV_FILE)];
+    let files = match files {
+        [] if default[0].exists() => &default[..],
+        files => files,
+    };
+
+    let mut env: Vec<(String, String)> = Vec::new();
+    for file in files {
+        let vars = read_env_file(file)?;
+        println!("Loaded {} variables from {}", vars.len(), file.display());
+        for (key, value) in vars {
+            env.retain(|(existing, _)| *existing != key);
+            env.push((key, value));
+        }
+    }
+
+    let (exported, env): (Vec<_>, Vec<_>) = env
+        .into_iter()
+        .partition(|(key, _)| std::env::var_os(key).is_some());
+    if !exported.is_empty() {
+        let keys: Vec<&str> = exported.iter().map(|(key, _)| key.as_str()).collect();
+        println!("Keeping the shell's value of {}", keys.join(", "));
+    }
+
+    Ok(env)
+}
+
+/// Read `KEY=value` lines, or `KEY: value` pairs from a `.yml` or `.yaml` file
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let is_yaml = path
+        .extension()
+        .is_some_and(|extension| extension == "yml" || extension == "yaml");
+    let vars = if is_yaml {
+        parse_yaml(&contents)
+    } else {
+        parse_dotenv(&contents)
+    };
+    vars.with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Parse dotenv lines: `KEY=value`, optionally prefixed with `export`, with `#`
+/// comments and single or double quoted values
+fn parse_dotenv(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected KEY=value", index + 1);
+        };
+        let key = key.trim();
+        if !is_key(key) {
+            bail!("line {}: '{}' isn't a valid variable name", index + 1, key);
+        }
+
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => match value[1..].find(quote) {
+                Some(end) => {
+                    let inner = &value[1..=end];
+                    if quote == '"' {
+                        inner.replace("\\n", "\n").replace("\\\"", "\"")
+                    } else {
+                        inner.to_string()
+                    }
+                }
+                None => bail!("line {}: unterminated quote", index + 1),
+            },
+            // Unquoted values end at a comment
+            _ => match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.to_string(),
+            },
+        };
+
+        vars.push((key.to_string(), value));
     }
+
+    Ok(vars)
+}
+
+/// Parse a YAML mapping of variable names to scalar values
+fn parse_yaml(contents: &str) -> Result<Vec<(String, String)>> {
+    let Value::Mapping(mapping) = serde_yaml_ng::from_str(contents)? else {
+        bail!("expected a mapping of KEY: value pairs");
+    };
+
+    mapping
+        .into_iter()
+        .map(|(key, value)| {
+            let Value::String(key) = key else {
+                bail!("keys must be variable names");
+            };
+            if !is_key(&key) {
+                bail!("'{}' isn't a valid variable name", key);
+            }
+            let value = match value {
+                Value::String(value) => value,
+                Value::Bool(value) => value.to_string(),
+                Value::Number(value) => value.to_string(),
+                Value::Null => String::new(),
+                _ => bail!(
+                    "'{}' must be a string, number or boolean, env files are flat KEY: value pairs rather than service config",
+                    key
+                ),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
@@ -9,13 +9,98 @@ use syn::{Data, DeriveInput, Fields, Item, ItemFn, LitStr, parse_macro_input};
 /// Usage:
 /// - `discover_endpoints!()` - Auto-discovers endpoints in "src/endpoints" directory
 /// - `discover_endpoints!("path/to/endpoints")` - Discovers endpoints in specified path
+/// - `discover_endpoints!(state = expr)` - Provides shared state to every handler, optionally
+///   after the path
+///
+/// Handlers are functions annotated with `#[utoipa::path]`, including those in inline
+/// modules within a file; handlers and modules need at least `pub(crate)` visibility
+///
+/// Handlers may return anything implementing `IntoResponse`, including
+/// `Result<Json<T>, AppError>` (`ApiResult<T>`), so errors are propagated with `?`
+/// rather than unwrapped
+///
+/// Without a state expression handlers can take `State<DatabaseConnection>`, the
+/// service's write connection. Without a database enabled it's disconnected and
+/// every query fails, like the `Db` extractor without a database
+///
+/// The state expression is evaluated once inside the generated
+/// `init_endpoints(service: &mut MicroKit)`, so it can use `service`. Handlers extract
+/// it with `State<AppState>`, or pieces of it with `State<T>` where `T: FromRef<AppState>`:
+///
+/// ```ignore
+/// #[derive(Clone, FromRef)]
+/// pub struct AppState {
+///     pub db: DatabaseConnection,
+///     pub cache: Cache,
+/// }
+///
+/// microkit::discover_endpoints!(state = AppState {
+///     db: service.database.clone().expect("database enabled").write,
+///     cache: Cache::new(),
+/// });
+/// ```
+///
+/// Handlers taking `State<DatabaseConnection>` keep working through `FromRef` when
+/// `AppState` has a `DatabaseConnection` field, or can switch to the `Db` extractor,
+/// which needs no state
+///
+/// A module can declare middleware for its own routes with a `layer` function, applied
+/// to the module's router before it's added. Modules without one are added as they are:
+///
+/// ```ignore
+/// pub(crate) fn layer(router: OpenApiRouter) -> OpenApiRouter {
+///     router.require_roles(&["admin"])
+/// }
+/// ```
 #[proc_macro]
 pub fn discover_endpoints(input: TokenStream) -> TokenStream {
-    let endpoints_path = if input.is_empty() {
-        "src/endpoints".to_string()
-    } else {
-        let path_lit = parse_macro_input!(input as LitStr);
-        path_lit.value()
+    use syn::{
+        Expr, Token,
+        parse::{Parse, ParseStream},
+    };
+
+    struct DiscoverEndpointsInput {
+        path: Option<LitStr>,
+        state: Option<Expr>,
+    }
+
+    impl Parse for DiscoverEndpointsInput {
+        fn parse(input: ParseStream) -> syn::Result<Self> {
+            let path = if input.peek(LitStr) {
+                Some(input.parse()?)
+            } else {
+                None
+            };
+
+            if path.is_some() && !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+
+            let state = if input.is_empty() {
+                None
+            } else {
+                let key: syn::Ident = input.parse()?;
+                if key != "state" {
+                    return Err(syn::Error::new(key.span(), "expected `state = <expr>`"));
+                }
+                input.parse::<Token![=]>()?;
+                let state = input.parse()?;
+                if input.peek(Token![,]) {
+                    input.parse::<Token![,]>()?;
+                }
+                Some(state)
+            };
+
+            Ok(DiscoverEndpointsInput { path, state })
+        }
+    }
+
+    let DiscoverEndpointsInput { path, state } =
+        parse_macro_input!(input as DiscoverEndpointsInput);
+
+    let endpoints_path = match path {
+        Some(path) => path.value(),
+        None => "src/endpoints".to_string(),
     };
 
     let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
@@ -26,14 +111,35 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
     struct EndpointInfo {
         module_path: Vec<String>,
         handlers: Vec<String>,
+        /// The module declares `fn layer(router: OpenApiRouter) -> OpenApiRouter`
+        has_layer: bool,
+    }
+
+    if !base_path.is_dir() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            format!(
+                "Endpoints directory '{}' does not exist (resolved '{}' against CARGO_MANIFEST_DIR '{}'). \
+                 The path is relative to the crate invoking discover_endpoints!, so call it from the \
+                 crate whose '{}' you mean",
+                base_path.display(),
+                endpoints_path,
+                manifest_dir,
+                endpoints_path
+            ),
+        )
+        .to_compile_error()
+        .into();
     }
 
     let mut endpoints = Vec::new();
+    let mut files = 0;
 
     fn discover_recursive(
         dir: &PathBuf,
         base: &PathBuf,
         endpoints: &mut Vec<EndpointInfo>,
+        files: &mut usize,
     ) -> Result<(), String> {
         if !dir.exists() || !dir.is_dir() {
             return Ok(());
@@ -46,13 +152,15 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
             let path = entry.path();
 
             if path.is_dir() {
-                discover_recursive(&path, base, endpoints)?;
+                discover_recursive(&path, base, endpoints, files)?;
             } else if path.is_file()
                 && let Some(file_name) = path.file_name()
                 && let Some(file_name_str) = file_name.to_str()
                 && file_name_str.ends_with(".rs")
                 && file_name_str != "mod.rs"
             {
+                *files += 1;
+
                 let relative = path
                     .strip_prefix(base)
                     .map_err(|e| format!("Failed to strip prefix: {}", e))?;
@@ -74,19 +182,16 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
                     && let Ok(syntax_tree) = syn::parse_file(&content)
                 {
                     let mut handlers = Vec::new();
-
-                    for item in syntax_tree.items {
-                        if let Item::Fn(func) = item
-                            && has_utoipa_path_attr(&func)
-                        {
-                            handlers.push(func.sig.ident.to_string());
-                        }
-                    }
+                    collect_handlers(&syntax_tree.items, "", &mut handlers);
 
                     if !handlers.is_empty() {
+                        let has_layer = syntax_tree.items.iter().any(
+                            |item| matches!(item, Item::Fn(func) if func.sig.ident == "layer"),
+                        );
                         endpoints.push(EndpointInfo {
                             module_path,
                             handlers,
+                            has_layer,
                         });
                     }
                 }
@@ -96,7 +201,7 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
         Ok(())
     }
 
-    if let Err(e) = discover_recursive(&base_path, &base_path, &mut endpoints) {
+    if let Err(e) = discover_recursive(&base_path, &base_path, &mut endpoints, &mut files) {
         return syn::Error::new(proc_macro2::Span::call_site(), e)
             .to_compile_error()
             .into();
@@ -105,12 +210,25 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
     endpoints.sort_by(|a, b| a.module_path.cmp(&b.module_path));
 
     if endpoints.is_empty() {
-        return syn::Error::new(
-            proc_macro2::Span::call_site(),
-            format!("No endpoint modules found in '{}'", base_path.display()),
-        )
-        .to_compile_error()
-        .into();
+        let message = if files == 0 && base_path.join("mod.rs").exists() {
+            format!(
+                "'{}' only contains mod.rs; add endpoint modules as sibling files, e.g. '{}'",
+                base_path.display(),
+                base_path.join("users.rs").display()
+            )
+        } else if files == 0 {
+            format!("No endpoint modules found in '{}'", base_path.display())
+        } else {
+            format!(
+                "No #[utoipa::path] handlers found in the {} endpoint modules in '{}'",
+                files,
+                base_path.display()
+            )
+        };
+
+        return syn::Error::new(proc_macro2::Span::call_site(), message)
+            .to_compile_error()
+            .into();
     }
 
     use std::collections::{BTreeMap, BTreeSet};
@@ -166,6 +284,12 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
 
     let module_decls = generate_module_decls(&[], &module_tree);
 
+    // Without an explicit state, handlers get the database connection as before
+    let init_state = match state {
+        Some(state) => quote! { let __microkit_state = #state; },
+        None => quote! { let __microkit_state = service.endpoint_state(); },
+    };
+
     let register_calls: Vec<_> = endpoints
         .iter()
         .map(|ep| {
@@ -177,13 +301,19 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
                 full_paths.push(path);
             }
 
+            let layer = ep.has_layer.then(|| {
+                let path: syn::Path =
+                    syn::parse_str(&format!("{}::layer", ep.module_path.join("::")))
+                        .expect("Failed to parse path");
+                quote! { let router = #path(router); }
+            });
+
             quote! {
-                if let Some(db) = &service.database {
-                    let router = ::utoipa_axum::router::OpenApiRouter::new()
-                        .routes(::utoipa_axum::routes!(#(#full_paths),*))
-                        .with_state(db.clone());
-                    service.add_route(router);
-                }
+                let router = ::utoipa_axum::router::OpenApiRouter::new()
+                    .routes(::utoipa_axum::routes!(#(#full_paths),*))
+                    .with_state(__microkit_state.clone());
+                #layer
+                service.add_route(router)?;
             }
         })
         .collect();
@@ -198,6 +328,7 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
         pub fn init_endpoints(
             service: &mut microkit::MicroKit
         ) -> anyhow::Result<()> {
+            #init_state
             #(#register_calls)*
             Ok(())
         }
@@ -206,17 +337,27 @@ pub fn discover_endpoints(input: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
-/// Check if a function has a #[utoipa::path] attribute
-fn has_utoipa_path_attr(func: &ItemFn) -> bool {
-    for attr in &func.attrs {
-        if attr.path().segments.len() == 2 {
-            let segments: Vec<_> = attr.path().segments.iter().collect();
-            if segments[0].ident == "utoipa" && segments[1].ident == "path" {
-                return true;
+/// Collect `#[utoipa::path]` handlers, qualified by any inline modules they're nested in
+fn collect_handlers(items: &[Item], prefix: &str, handlers: &mut Vec<String>) {
+    for item in items {
+        match item {
+            Item::Fn(func) if has_utoipa_path_attr(func) => {
+                handlers.push(format!("{}{}", prefix, func.sig.ident));
             }
+            Item::Mod(module) => {
+                if let Some((_, items)) = &module.content {
+                    let prefix = format!("{}{}::", prefix, module.ident);
+                    collect_handlers(items, &prefix, handlers);
+                }
+            }
+            _ => {}
         }
     }
-    false
+}
+
+/// Check if a function has a #[utoipa::path] attribute
+fn has_utoipa_path_attr(func: &ItemFn) -> bool {
+    func.attrs.iter().any(|attr| is_utoipa_path(attr.path()))
 }
 
 /// Registers endpoint modules with a MicroKit service
@@ -267,7 +408,7 @@ pub fn register_endpoints(input: TokenStream) -> TokenStream {
 
     let register_calls = endpoints.iter().map(|name| {
         quote! {
-            #service.add_route(#module::#name::api(&#db)?);
+            #service.add_route(#module::#name::api(&#db)?)?;
         }
     });
 
@@ -279,7 +420,37 @@ pub fn register_endpoints(input: TokenStream) -> TokenStream {
 }
 
 /// Derive macro for entities with creation tracking
-#[proc_macro_derive(CreationTracked)]
+///
+/// Brownfield schemas can name the tracking fields differently:
+///
+/// ```ignore
+/// #[derive(DeriveEntityModel, CreationTracked)]
+/// #[creation_tracking(system = "created_by", key = "external_id")]
+/// pub struct Model { ... }
+/// ```
+///
+/// `max_key_len` and `key_charset` (`any`, `url_safe` or `alphanumeric`) limit
+/// the keys `validate_creation_fields` accepts from events:
+///
+/// ```ignore
+/// #[creation_tracking(max_key_len = 64, key_charset = "url_safe")]
+/// ```
+///
+/// `event` names the entity's event contract and fails the build when their fields
+/// drift apart, so a column added to one isn't silently dropped by the other. Every
+/// field must appear on both, with the tracking fields matched to the contract's
+/// `creation_system` and `creation_key`. Columns the event doesn't carry opt out
+/// with `#[creation_tracking(skip_event)]`:
+///
+/// ```ignore
+/// #[creation_tracking(event = "contracts::UserCreatedEvent")]
+/// pub struct Model {
+///     ...
+///     #[creation_tracking(skip_event)]
+///     pub updated_on: chrono::DateTime<chrono::Utc>,
+/// }
+/// ```
+#[proc_macro_derive(CreationTracked, attributes(creation_tracking))]
 pub fn derive_creation_tracked(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let name = &input.ident;
@@ -306,43 +477,89 @@ pub fn derive_creation_tracked(input: TokenStream) -> TokenStream {
         }
     };
 
-    let mut has_creation_system = false;
-    let mut has_creation_key = false;
-    let mut has_generated_on = false;
+    let mut system_field = "creation_system".to_string();
+    let mut key_field = "creation_key".to_string();
+    let mut max_key_len = quote! { microkit::entity::KeyConstraints::DEFAULT.max_len };
+    let mut key_charset = quote! { microkit::entity::KeyConstraints::DEFAULT.charset };
+    let mut event: Option<syn::Path> = None;
 
-    for field in fields {
-        if let Some(ident) = &field.ident {
-            if ident == "creation_system" {
-                has_creation_system = true;
-            }
-            if ident == "creation_key" {
-                has_creation_key = true;
-            }
-            if ident == "generated_on" {
-                has_generated_on = true;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("creation_tracking") {
+            continue;
+        }
+
+        let result = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("system") {
+                system_field = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("key") {
+                key_field = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("max_key_len") {
+                let max_len = meta.value()?.parse::<syn::LitInt>()?;
+                max_key_len = quote! { #max_len };
+                Ok(())
+            } else if meta.path.is_ident("key_charset") {
+                let charset = meta.value()?.parse::<LitStr>()?;
+                let variant = match charset.value().as_str() {
+                    "any" => quote! { Any },
+                    "url_safe" => quote! { UrlSafe },
+                    "alphanumeric" => quote! { Alphanumeric },
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            charset,
+                            "expected `any`, `url_safe` or `alphanumeric`",
+                        ));
+                    }
+                };
+                key_charset = quote! { microkit::entity::KeyCharset::#variant };
+                Ok(())
+            } else if meta.path.is_ident("event") {
+                let path = meta.value()?.parse::<LitStr>()?;
+                event = Some(syn::parse_str(&path.value())?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `system`, `key`, `max_key_len`, `key_charset` or `event`"))
             }
+        });
+
+        if let Err(e) = result {
+            return e.to_compile_error().into();
         }
     }
 
-    if !has_creation_system {
+    let find_field = |field_name: &str| {
+        fields.iter().find(|field| {
+            field
+                .ident
+                .as_ref()
+                .is_some_and(|ident| ident == field_name)
+        })
+    };
+    let has_field = |field_name: &str| find_field(field_name).is_some();
+
+    if !has_field(&system_field) {
         return syn::Error::new_spanned(
             &input,
-            "CreationTracked requires a `creation_system: String` field",
+            format!(
+                "CreationTracked requires a `{}: String` field",
+                system_field
+            ),
         )
         .to_compile_error()
         .into();
     }
 
-    if !has_creation_key {
+    if !has_field(&key_field) {
         return syn::Error::new_spanned(
             &input,
-            "CreationTracked requires a `creation_key: String` field",
+            format!("CreationTracked requires a `{}: String` field", key_field),
         )
         .to_compile_error()
         .into();
     }
 
-    if !has_generated_on {
+    if !has_field("generated_on") {
         return syn::Error::new_spanned(
             &input,
             "CreationTracked requires a `generated_on: chrono::DateTime<chrono::Utc>` field",
@@ -351,16 +568,92 @@ pub fn derive_creation_tracked(input: TokenStream) -> TokenStream {
         .into();
     }
 
+    let system_ident = syn::Ident::new(&system_field, proc_macro2::Span::call_site());
+    let key_ident = syn::Ident::new(&key_field, proc_macro2::Span::call_site());
+
+    // Uuid keys are formatted, anything else is borrowed as a string
+    let key_is_uuid = find_field(&key_field).is_some_and(|field| match &field.ty {
+        syn::Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Uuid"),
+        _ => false,
+    });
+    let creation_key = if key_is_uuid {
+        quote! { ::std::borrow::Cow::Owned(self.#key_ident.to_string()) }
+    } else {
+        quote! { ::std::borrow::Cow::Borrowed(&self.#key_ident) }
+    };
+
+    // Build the event without `..`, so rustc reports fields missing on either side
+    let event_check = match event {
+        Some(event) => {
+            let mut initializers = Vec::new();
+
+            for field in fields {
+                let mut skip = false;
+                for attr in &field.attrs {
+                    if !attr.path().is_ident("creation_tracking") {
+                        continue;
+                    }
+                    let result = attr.parse_nested_meta(|meta| {
+                        if meta.path.is_ident("skip_event") {
+                            skip = true;
+                            Ok(())
+                        } else {
+                            Err(meta.error("expected `skip_event`"))
+                        }
+                    });
+                    if let Err(e) = result {
+                        return e.to_compile_error().into();
+                    }
+                }
+
+                let Some(ident) = field.ident.as_ref().filter(|_| !skip) else {
+                    continue;
+                };
+                let event_field = if *ident == system_field {
+                    syn::Ident::new("creation_system", ident.span())
+                } else if *ident == key_field {
+                    syn::Ident::new("creation_key", ident.span())
+                } else {
+                    ident.clone()
+                };
+                initializers.push(quote! { #event_field: ::core::unreachable!() });
+            }
+
+            quote! {
+                const _: () = {
+                    #[allow(dead_code, unreachable_code)]
+                    fn __microkit_event_fields() -> #event {
+                        #event { #(#initializers),* }
+                    }
+                };
+            }
+        }
+        None => quote! {},
+    };
+
     // Generate the implementation
     let expanded = quote! {
+        #event_check
+
         impl microkit::entity::CreationTracking for #name {
             fn creation_system(&self) -> &str {
-                &self.creation_system
+                &self.#system_ident
             }
 
-            fn creation_key(&self) -> &str {
-                &self.creation_key
+            fn creation_key(&self) -> ::std::borrow::Cow<'_, str> {
+                #creation_key
             }
+
+            const KEY_CONSTRAINTS: microkit::entity::KeyConstraints =
+                microkit::entity::KeyConstraints {
+                    max_len: #max_key_len,
+                    charset: #key_charset,
+                    uuid: #key_is_uuid,
+                };
         }
     };
 
@@ -452,7 +745,7 @@ pub fn event_contract(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 Self {
                     creation_system,
                     creation_key,
-                    generated_on: chrono::Utc::now(),
+                    generated_on: microkit::clock::now(),
                     #(#field_names),*
                 }
             }
@@ -463,8 +756,8 @@ pub fn event_contract(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 &self.creation_system
             }
 
-            fn creation_key(&self) -> &str {
-                &self.creation_key
+            fn creation_key(&self) -> ::std::borrow::Cow<'_, str> {
+                ::std::borrow::Cow::Borrowed(&self.creation_key)
             }
         }
     };
@@ -562,11 +855,110 @@ pub fn api_contract(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 &self.creation_system
             }
 
-            fn creation_key(&self) -> &str {
-                &self.creation_key
+            fn creation_key(&self) -> ::std::borrow::Cow<'_, str> {
+                ::std::borrow::Cow::Borrowed(&self.creation_key)
             }
         }
     };
 
     TokenStream::from(expanded)
 }
+
+/// Attribute macro that requires OAuth scopes for a handler
+///
+/// Place it above `#[utoipa::path]` so the same scopes are both enforced and documented:
+///
+/// ```ignore
+/// #[require_scopes("users:write")]
+/// #[utoipa::path(post, path = PATH, responses(...))]
+/// pub async fn create_user(Json(payload): Json<UserRequest>) -> Json<UserResponse> { ... }
+/// ```
+///
+/// The handler gains an `AuthenticatedUser` extractor and responds with 403 when any scope
+/// is missing. `security(("bearer" = []), ("oidc" = [scopes]))` is appended to the
+/// `#[utoipa::path]` attribute, so it must not declare its own `security`.
+#[proc_macro_attribute]
+pub fn require_scopes(attr: TokenStream, item: TokenStream) -> TokenStream {
+    use syn::{Token, punctuated::Punctuated};
+
+    let scopes = parse_macro_input!(attr with Punctuated::<LitStr, Token![,]>::parse_terminated);
+    let mut func = parse_macro_input!(item as ItemFn);
+
+    if scopes.is_empty() {
+        return syn::Error::new_spanned(&func.sig, "require_scopes needs at least one scope")
+            .to_compile_error()
+            .into();
+    }
+
+    let scopes: Vec<_> = scopes.into_iter().collect();
+
+    // Document the requirement on the utoipa path below this attribute
+    let Some(path_attr) = func
+        .attrs
+        .iter_mut()
+        .find(|attr| is_utoipa_path(attr.path()))
+    else {
+        return syn::Error::new_spanned(
+            &func.sig,
+            "require_scopes must be placed above #[utoipa::path]",
+        )
+        .to_compile_error()
+        .into();
+    };
+
+    let syn::Meta::List(list) = &mut path_attr.meta else {
+        return syn::Error::new_spanned(&path_attr, "expected #[utoipa::path(...)]")
+            .to_compile_error()
+            .into();
+    };
+
+    let declares_security =
+        list.tokens.clone().into_iter().any(
+            |token| matches!(token, proc_macro2::TokenTree::Ident(ident) if ident == "security"),
+        );
+    if declares_security {
+        return syn::Error::new_spanned(
+            &list.tokens,
+            "remove `security(...)`, require_scopes documents it for you",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let tokens = &list.tokens;
+    list.tokens = quote! {
+        #tokens,
+        security(("bearer" = []), ("oidc" = [#(#scopes),*]))
+    };
+
+    // Enforce the requirement at runtime
+    let output = match &func.sig.output {
+        syn::ReturnType::Default => quote! { () },
+        syn::ReturnType::Type(_, ty) => quote! { #ty },
+    };
+    let block = &func.block;
+
+    func.sig.inputs.insert(
+        0,
+        syn::parse_quote! { __microkit_user: microkit::auth::AuthenticatedUser },
+    );
+    func.sig.output = syn::parse_quote! { -> axum::response::Response };
+    func.block = syn::parse_quote! {{
+        if !__microkit_user.has_all_scopes(&[#(#scopes),*]) {
+            return axum::response::IntoResponse::into_response(
+                microkit::error::AppError::forbidden("Missing required scope"),
+            );
+        }
+
+        let __microkit_result: #output = async move #block.await;
+        axum::response::IntoResponse::into_response(__microkit_result)
+    }};
+
+    TokenStream::from(quote! { #func })
+}
+
+/// Check if an attribute path is `utoipa::path`
+fn is_utoipa_path(path: &syn::Path) -> bool {
+    let segments: Vec<_> = path.segments.iter().collect();
+    segments.len() == 2 && segments[0].ident == "utoipa" && segments[1].ident == "path"
+}
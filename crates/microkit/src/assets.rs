@@ -0,0 +1,20 @@
+use crate::config::StaticConfig;
+use axum::routing::{MethodRouter, any_service};
+use std::path::Path;
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Files from `config.dir`, answering unmatched paths with its `index.html` in SPA mode
+pub(crate) fn service(config: &StaticConfig) -> MethodRouter {
+    let dir = Path::new(&config.dir);
+    let index = dir.join("index.html");
+    if !index.is_file() {
+        tracing::warn!("static: no index.html in '{}'", dir.display());
+    }
+
+    let files = ServeDir::new(dir);
+    if config.spa == Some(false) {
+        any_service(files)
+    } else {
+        any_service(files.fallback(ServeFile::new(index)))
+    }
+}
@@ -1,15 +1,13 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use axum::{
-    RequestPartsExt,
-    extract::FromRequestParts,
-    http::{StatusCode, request::Parts},
-};
-use axum_extra::{
-    TypedHeader,
-    headers::{Authorization, authorization::Bearer},
+    extract::{FromRequestParts, Query},
+    http::{StatusCode, header, request::Parts},
 };
+use axum_extra::headers::{Authorization, Cookie, HeaderMapExt, authorization::Bearer};
 use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -34,13 +32,33 @@ pub struct JwtClaims {
     pub iat: Option<usize>,
     /// Audience (client ID)
     pub aud: Option<serde_json::Value>,
+    /// Space separated OAuth scopes granted to the token
+    pub scope: Option<String>,
 }
 
 /// Authenticated user extracted from validated JWT
 ///
 /// Add this as a parameter to any handler that requires authentication
+pub type AuthenticatedUser = AuthenticatedUserOf<()>;
+
+/// Authenticated user with the token's app-specific claims deserialized into `extra`
+///
+/// The token is validated as for `AuthenticatedUser` and decoded once, into the
+/// standard `claims` and `C` together. `C` should leave out the standard claims,
+/// read those from `claims`
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Claims {
+///     tenant_id: String,
+/// }
+///
+/// async fn handler(user: AuthenticatedUserOf<Claims>) -> String {
+///     user.extra.tenant_id
+/// }
+/// ```
 #[derive(Debug, Clone, Default)]
-pub struct AuthenticatedUser {
+pub struct AuthenticatedUserOf<C> {
     /// Sub claim
     pub sub: String,
     /// User email address
@@ -49,9 +67,20 @@ pub struct AuthenticatedUser {
     pub groups: Vec<String>,
     /// Raw JWT claims
     pub claims: JwtClaims,
+    /// App-specific claims
+    pub extra: C,
+}
+
+/// A token's claims in one pass, `C` first so a map there still sees every claim
+#[derive(Deserialize)]
+struct Decoded<C> {
+    #[serde(flatten)]
+    extra: C,
+    #[serde(flatten)]
+    standard: JwtClaims,
 }
 
-impl AuthenticatedUser {
+impl<C> AuthenticatedUserOf<C> {
     pub fn has_role(&self, role: &str) -> bool {
         self.groups.iter().any(|g| g == role)
     }
@@ -60,32 +89,237 @@ impl AuthenticatedUser {
     pub fn has_any_role(&self, roles: &[&str]) -> bool {
         roles.iter().any(|role| self.has_role(role))
     }
+
+    /// OAuth scopes granted to the token
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.claims
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().any(|s| s == scope)
+    }
+
+    /// Check if user has all of the specified scopes
+    pub fn has_all_scopes(&self, scopes: &[&str]) -> bool {
+        scopes.iter().all(|scope| self.has_scope(scope))
+    }
 }
 
-/// Auth configuration for OIDC
+/// Timeout for requests to the identity provider unless set with `with_http_timeout`
+pub const DEFAULT_HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Attempts made by `prefetch_jwks` before deferring to a lazy fetch
+pub const JWKS_PREFETCH_ATTEMPTS: u32 = 3;
+
+/// The identity provider couldn't be reached, as opposed to the token being invalid
+#[derive(Debug)]
+pub struct ProviderUnavailable(pub anyhow::Error);
+
+impl std::fmt::Display for ProviderUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Identity provider unavailable: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for ProviderUnavailable {}
+
+/// The token's `kid` isn't in the cached JWKS
+#[derive(Debug)]
+pub struct KeyNotFound(pub String);
+
+impl std::fmt::Display for KeyNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Key '{}' not found in JWKS", self.0)
+    }
+}
+
+impl std::error::Error for KeyNotFound {}
+
+/// Outcome attribute of the `auth.token.validations` metric for a validation result
+///
+/// One of `ok`, `expired`, `not_yet_valid`, `invalid_signature`, `wrong_issuer`,
+/// `wrong_audience`, `jwks_miss`, `provider_unavailable` or `invalid`
+pub fn validation_outcome<C>(result: &Result<C>) -> &'static str {
+    use jsonwebtoken::errors::ErrorKind;
+
+    let Err(e) = result else {
+        return "ok";
+    };
+
+    if e.is::<ProviderUnavailable>() {
+        return "provider_unavailable";
+    }
+    if e.is::<KeyNotFound>() {
+        return "jwks_miss";
+    }
+
+    match e
+        .downcast_ref::<jsonwebtoken::errors::Error>()
+        .map(|e| e.kind())
+    {
+        Some(ErrorKind::ExpiredSignature) => "expired",
+        Some(ErrorKind::ImmatureSignature) => "not_yet_valid",
+        Some(ErrorKind::InvalidSignature) => "invalid_signature",
+        Some(ErrorKind::InvalidIssuer) => "wrong_issuer",
+        Some(ErrorKind::InvalidAudience) => "wrong_audience",
+        _ => "invalid",
+    }
+}
+
+/// Instruments for token validation, created on first use so they bind to the
+/// meter provider installed during build
+#[cfg(feature = "otel")]
+struct ValidationMetrics {
+    validations: opentelemetry::metrics::Counter<u64>,
+    duration: opentelemetry::metrics::Histogram<f64>,
+}
+
+#[cfg(feature = "otel")]
+fn record_validation(outcome: &'static str, elapsed: std::time::Duration) {
+    use opentelemetry::KeyValue;
+
+    static METRICS: std::sync::OnceLock<ValidationMetrics> = std::sync::OnceLock::new();
+    let metrics = METRICS.get_or_init(|| {
+        let meter = opentelemetry::global::meter("microkit");
+        ValidationMetrics {
+            validations: meter
+                .u64_counter("auth.token.validations")
+                .with_description("Bearer token validations by outcome")
+                .build(),
+            duration: meter
+                .f64_histogram("auth.token.validation.duration")
+                .with_unit("s")
+                .with_description("Time to validate a bearer token, including fetching the JWKS")
+                .build(),
+        }
+    });
+
+    let attributes = [KeyValue::new("outcome", outcome)];
+    metrics.validations.add(1, &attributes);
+    metrics.duration.record(elapsed.as_secs_f64(), &attributes);
+}
+
+/// User claims recorded on the request span once the user is authenticated
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceClaim {
+    /// Recorded as `user.sub`
+    Sub,
+    /// Recorded as `user.email`
+    Email,
+}
+
+/// Claims recorded on spans when none are configured
+pub const DEFAULT_TRACE_CLAIMS: &[TraceClaim] = &[TraceClaim::Sub, TraceClaim::Email];
+
+/// Where the bearer token is read from, tried in the configured order
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenSource {
+    /// `Authorization: Bearer <token>`
+    Header,
+    /// The `access_token` cookie
+    Cookie,
+    /// The `?access_token=` query parameter, which can leak into access logs
+    Query,
+}
+
+/// Token sources used when none are configured
+pub const DEFAULT_TOKEN_SOURCES: &[TokenSource] = &[TokenSource::Header];
+
+/// Cookie and query parameter carrying the token for those sources
+pub const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+/// How token signatures are verified
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// RS256 tokens checked against keys from the provider's JWKS endpoint
+    #[default]
+    Oidc,
+    /// HS256 tokens signed with a secret shared with the issuer, no JWKS involved
+    Hs256,
+}
+
+/// Environment variable holding the HS256 secret when `signing_secret` isn't set
+pub const SIGNING_SECRET_ENV: &str = "MICROKIT_AUTH_SIGNING_SECRET";
+
+/// Verification keys, from the provider or a shared secret
+#[derive(Clone)]
+enum Signing {
+    Jwks {
+        uri: String,
+        /// Cached JWKS keys
+        cache: Arc<RwLock<Option<JwkSet>>>,
+    },
+    Secret(DecodingKey),
+}
+
+/// Auth configuration for OIDC, or HS256 tokens with a shared secret
 #[derive(Clone)]
 pub struct AuthConfig {
-    jwks_uri: String,
+    signing: Signing,
     issuer: String,
     audience: Option<String>,
-    /// Cached JWKS keys
-    jwks_cache: Arc<RwLock<Option<JwkSet>>>,
     /// Optional client secret for API key authentication
     client_secret: Option<String>,
+    /// Claims recorded on the request span
+    trace_claims: Arc<[TraceClaim]>,
+    /// Where the extractor looks for the token, in priority order
+    token_sources: Arc<[TokenSource]>,
+    /// Origins whose unsafe requests may authenticate with the cookie
+    csrf_origins: Arc<[String]>,
+    /// Client for JWKS and other identity provider requests
+    http: reqwest::Client,
 }
 
 impl AuthConfig {
     /// Create auth config for generic OIDC provider
     pub fn oidc(issuer: String, jwks_uri: String) -> Self {
+        Self::with_signing(
+            issuer,
+            Signing::Jwks {
+                uri: jwks_uri,
+                cache: Arc::new(RwLock::new(None)),
+            },
+        )
+    }
+
+    /// Create auth config for HS256 tokens signed with `secret`, for internal
+    /// services without an OIDC provider
+    ///
+    /// Issuer, audience and expiry are enforced as for OIDC, and nothing is fetched
+    pub fn hs256(secret: impl AsRef<[u8]>, issuer: String) -> Self {
+        Self::with_signing(
+            issuer,
+            Signing::Secret(DecodingKey::from_secret(secret.as_ref())),
+        )
+    }
+
+    fn with_signing(issuer: String, signing: Signing) -> Self {
         Self {
-            jwks_uri,
+            signing,
             issuer,
             audience: None,
-            jwks_cache: Arc::new(RwLock::new(None)),
             client_secret: None,
+            trace_claims: DEFAULT_TRACE_CLAIMS.into(),
+            token_sources: DEFAULT_TOKEN_SOURCES.into(),
+            csrf_origins: Arc::new([]),
+            http: http_client(DEFAULT_HTTP_TIMEOUT),
         }
     }
 
+    /// Bound connecting to and reading from the identity provider, so a stalled
+    /// provider fails fast with 503 instead of hanging requests
+    pub fn with_http_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.http = http_client(timeout);
+        self
+    }
+
     /// Set expected audience (client ID) for token validation
     pub fn with_audience(mut self, audience: String) -> Self {
         self.audience = Some(audience);
@@ -98,17 +332,123 @@ impl AuthConfig {
         self
     }
 
+    /// Set which claims are recorded on the request span, an empty list records none
+    pub fn with_trace_claims(mut self, trace_claims: Vec<TraceClaim>) -> Self {
+        self.trace_claims = trace_claims.into();
+        self
+    }
+
+    /// Set where tokens are read from, in priority order (default: header only)
+    ///
+    /// Query parameter tokens end up in access and proxy logs, so they're only
+    /// worth it for clients that can't send headers or cookies. Browsers attach
+    /// cookies to cross-site requests, so the cookie only authenticates POST, PUT,
+    /// PATCH and DELETE from origins given to `with_csrf_origins`
+    pub fn with_token_sources(mut self, token_sources: Vec<TokenSource>) -> Self {
+        if token_sources.contains(&TokenSource::Query) {
+            tracing::warn!(
+                "auth: accepting tokens in the {} query parameter, they can leak into logs",
+                ACCESS_TOKEN_PARAM
+            );
+        }
+        self.token_sources = token_sources.into();
+        self
+    }
+
+    /// Set the origins allowed to make unsafe requests authenticated by the cookie,
+    /// e.g. `https://app.example.com`, the service's own origin included
+    pub fn with_csrf_origins(mut self, origins: Vec<String>) -> Self {
+        self.csrf_origins = origins.into();
+        self
+    }
+
+    /// Whether the cookie may authenticate this request: always for safe methods,
+    /// otherwise only from a trusted origin, so other sites can't forge writes
+    fn is_trusted_origin(&self, parts: &Parts) -> bool {
+        if parts.method.is_safe() {
+            return true;
+        }
+
+        let trusted = match parts
+            .headers
+            .get(header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+        {
+            Some(origin) => self.csrf_origins.iter().any(|trusted| trusted == origin),
+            // Browsers that leave out Origin still say whether the request is cross-site
+            None => parts
+                .headers
+                .get("sec-fetch-site")
+                .and_then(|site| site.to_str().ok())
+                .is_none_or(|site| site != "cross-site"),
+        };
+        if !trusted {
+            tracing::debug!(
+                "auth: ignoring the {} cookie on a {} from an untrusted origin",
+                ACCESS_TOKEN_PARAM,
+                parts.method
+            );
+        }
+        trusted
+    }
+
+    /// Token from the first configured source that carries one
+    pub(crate) fn token(&self, parts: &Parts) -> Option<String> {
+        self.token_sources.iter().find_map(|source| {
+            let token = match source {
+                TokenSource::Header => parts
+                    .headers
+                    .typed_get::<Authorization<Bearer>>()
+                    .map(|Authorization(bearer)| bearer.token().to_string()),
+                TokenSource::Cookie if self.is_trusted_origin(parts) => parts
+                    .headers
+                    .typed_get::<Cookie>()
+                    .and_then(|cookie| cookie.get(ACCESS_TOKEN_PARAM).map(str::to_string)),
+                TokenSource::Cookie => None,
+                TokenSource::Query => Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+                    .ok()
+                    .and_then(|Query(mut params)| params.remove(ACCESS_TOKEN_PARAM)),
+            };
+            token.filter(|token| !token.is_empty())
+        })
+    }
+
     /// Validate JWT token
     pub async fn validate_token(&self, token: &str) -> Result<JwtClaims> {
-        let header = decode_header(token).context("Failed to decode JWT header")?;
+        self.validate_token_as(token).await
+    }
 
-        let kid = header
-            .kid
-            .ok_or_else(|| anyhow!("JWT missing 'kid' in header"))?;
+    /// Validate JWT token, deserializing its claims into `C`
+    ///
+    /// With the `otel` feature each call counts towards `auth.token.validations`
+    /// and `auth.token.validation.duration`, tagged with its [`validation_outcome`]
+    pub async fn validate_token_as<C: DeserializeOwned>(&self, token: &str) -> Result<C> {
+        #[cfg(feature = "otel")]
+        let started = std::time::Instant::now();
 
-        let key = self.get_decoding_key(&kid).await?;
+        let result = self.decode_token(token).await;
+
+        #[cfg(feature = "otel")]
+        record_validation(validation_outcome(&result), started.elapsed());
+
+        result
+    }
+
+    async fn decode_token<C: DeserializeOwned>(&self, token: &str) -> Result<C> {
+        let (key, algorithm) = match &self.signing {
+            Signing::Jwks { .. } => {
+                let header = decode_header(token).context("Failed to decode JWT header")?;
+
+                let kid = header
+                    .kid
+                    .ok_or_else(|| anyhow!("JWT missing 'kid' in header"))?;
+
+                (self.get_decoding_key(&kid).await?, Algorithm::RS256)
+            }
+            Signing::Secret(key) => (key.clone(), Algorithm::HS256),
+        };
 
-        let mut validation = Validation::new(Algorithm::RS256);
+        let mut validation = Validation::new(algorithm);
         validation.set_issuer(&[&self.issuer]);
 
         if let Some(aud) = &self.audience {
@@ -117,75 +457,122 @@ impl AuthConfig {
             validation.validate_aud = false;
         }
 
-        let token_data =
-            decode::<JwtClaims>(token, &key, &validation).context("Failed to validate JWT")?;
+        let token_data = decode::<C>(token, &key, &validation).context("Failed to validate JWT")?;
 
         Ok(token_data.claims)
     }
 
     /// Get decoding key for a specific key ID
     async fn get_decoding_key(&self, kid: &str) -> Result<DecodingKey> {
-        {
-            let cache = self.jwks_cache.read().await;
-            if let Some(jwks) = cache.as_ref() {
-                return self.find_key_in_jwks(jwks, kid);
-            }
+        let Signing::Jwks { cache, .. } = &self.signing else {
+            bail!("JWKS not used with a shared secret");
+        };
+
+        if let Some(jwks) = cache.read().await.as_ref() {
+            return self.find_key_in_jwks(jwks, kid);
         }
 
         let jwks = self.fetch_jwks().await?;
-
-        let mut cache = self.jwks_cache.write().await;
-        *cache = Some(jwks.clone());
+        *cache.write().await = Some(jwks.clone());
 
         self.find_key_in_jwks(&jwks, kid)
     }
 
     /// Fetch JWKS from the configured endpoint
+    ///
+    /// Failures are wrapped in `ProviderUnavailable` so they aren't mistaken for bad tokens
     async fn fetch_jwks(&self) -> Result<JwkSet> {
-        let response = reqwest::get(&self.jwks_uri)
-            .await
-            .context("Failed to fetch JWKS")?;
-
-        let jwks: JwkSet = response.json().await.context("Failed to parse JWKS JSON")?;
+        let Signing::Jwks { uri, .. } = &self.signing else {
+            bail!("JWKS not used with a shared secret");
+        };
+
+        let fetch = async {
+            let response = self
+                .http
+                .get(uri)
+                .send()
+                .await
+                .context("Failed to fetch JWKS")?
+                .error_for_status()
+                .context("JWKS endpoint returned an error")?;
+
+            response
+                .json::<JwkSet>()
+                .await
+                .context("Failed to parse JWKS JSON")
+        };
+
+        fetch.await.map_err(|e| anyhow!(ProviderUnavailable(e)))
+    }
 
-        Ok(jwks)
+    /// Fetch and cache JWKS ahead of the first request, retrying with backoff
+    ///
+    /// Never fatal: if the provider can't be reached, keys are fetched lazily on first use.
+    /// Build runs it in a background task, so requests arriving first fetch the keys
+    /// themselves. Does nothing for HS256, which has no keys to fetch
+    pub async fn prefetch_jwks(&self) {
+        let Signing::Jwks { uri, .. } = &self.signing else {
+            return;
+        };
+        let mut delay = std::time::Duration::from_millis(250);
+
+        for attempt in 1..=JWKS_PREFETCH_ATTEMPTS {
+            match self.refresh_jwks().await {
+                Ok(()) => {
+                    tracing::info!("auth: JWKS cached from {}", uri);
+                    return;
+                }
+                Err(e) if attempt == JWKS_PREFETCH_ATTEMPTS => {
+                    tracing::warn!(
+                        "auth: couldn't prefetch JWKS, deferring to first request: {:#}",
+                        e
+                    );
+                }
+                Err(e) => {
+                    tracing::debug!("auth: JWKS prefetch attempt {} failed: {:#}", attempt, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
     }
 
     /// Find a specific key in the JWKS
     fn find_key_in_jwks(&self, jwks: &JwkSet, kid: &str) -> Result<DecodingKey> {
         let jwk = jwks
             .find(kid)
-            .ok_or_else(|| anyhow!("Key '{}' not found in JWKS", kid))?;
+            .ok_or_else(|| anyhow!(KeyNotFound(kid.to_string())))?;
 
         DecodingKey::from_jwk(jwk).context("Failed to create decoding key from JWK")
     }
 
-    /// Manually refresh the JWKS cache
+    /// Manually refresh the JWKS cache, a no-op for HS256
     pub async fn refresh_jwks(&self) -> Result<()> {
+        let Signing::Jwks { cache, .. } = &self.signing else {
+            return Ok(());
+        };
         let jwks = self.fetch_jwks().await?;
-        let mut cache = self.jwks_cache.write().await;
-        *cache = Some(jwks);
+        *cache.write().await = Some(jwks);
         Ok(())
     }
 }
 
-impl<S> FromRequestParts<S> for AuthenticatedUser
+fn http_client(timeout: std::time::Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .build()
+        .unwrap_or_default()
+}
+
+impl<S, C> FromRequestParts<S> for AuthenticatedUserOf<C>
 where
     S: Send + Sync,
+    C: DeserializeOwned,
 {
     type Rejection = (StatusCode, String);
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::UNAUTHORIZED,
-                    "Missing or invalid Authorization header".to_string(),
-                )
-            })?;
-
         let auth_config = parts
             .extensions
             .get::<AuthConfig>()
@@ -201,27 +588,63 @@ where
             })?
             .clone();
 
-        // Validate JWT token
-        let claims = auth_config
-            .validate_token(bearer.token())
+        let token = auth_config.token(parts).ok_or_else(|| {
+            let message = if *auth_config.token_sources == [TokenSource::Header] {
+                "Missing or invalid Authorization header"
+            } else {
+                "Missing or invalid bearer token"
+            };
+            (StatusCode::UNAUTHORIZED, message.to_string())
+        })?;
+
+        let Decoded { extra, standard } = auth_config
+            .validate_token_as::<Decoded<C>>(&token)
             .await
             .map_err(|e| {
+                if e.is::<ProviderUnavailable>() {
+                    tracing::error!("auth: {}", e);
+                    return (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Authentication provider unavailable".to_string(),
+                    );
+                }
                 tracing::warn!("JWT validation failed: {}", e);
                 (StatusCode::UNAUTHORIZED, format!("Invalid token: {}", e))
             })?;
 
-        let groups = claims
-            .cognito_groups
-            .clone()
-            .or_else(|| claims.groups.clone())
-            .unwrap_or_default();
-
-        Ok(AuthenticatedUser {
-            sub: claims.sub.clone(),
-            email: claims.email.clone(),
-            groups,
-            claims,
-        })
+        let user = AuthenticatedUserOf {
+            sub: standard.sub.clone(),
+            email: standard.email.clone(),
+            groups: standard
+                .cognito_groups
+                .clone()
+                .or_else(|| standard.groups.clone())
+                .unwrap_or_default(),
+            claims: standard,
+            extra,
+        };
+
+        record_user(&user, &auth_config.trace_claims);
+
+        Ok(user)
+    }
+}
+
+/// Record the configured claims on the span opened by `inject_auth_config`
+fn record_user<C>(user: &AuthenticatedUserOf<C>, trace_claims: &[TraceClaim]) {
+    let span = tracing::Span::current();
+
+    for claim in trace_claims {
+        match claim {
+            TraceClaim::Sub => {
+                span.record("user.sub", user.sub.as_str());
+            }
+            TraceClaim::Email => {
+                if let Some(email) = &user.email {
+                    span.record("user.email", email.as_str());
+                }
+            }
+        }
     }
 }
 
@@ -230,6 +653,21 @@ pub async fn inject_auth_config(
     mut request: axum::http::Request<axum::body::Body>,
     next: axum::middleware::Next,
 ) -> axum::response::Response {
+    use tracing::Instrument;
+
+    let trace_user = !config.trace_claims.is_empty();
     request.extensions_mut().insert(config);
-    next.run(request).await
+
+    if !trace_user {
+        return next.run(request).await;
+    }
+
+    // Fields are filled in by the `AuthenticatedUser` extractor, so every log
+    // line and span below it is attributed to the user
+    let span = tracing::info_span!(
+        "user",
+        user.sub = tracing::field::Empty,
+        user.email = tracing::field::Empty
+    );
+    next.run(request).instrument(span).await
 }
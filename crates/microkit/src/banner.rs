@@ -0,0 +1,53 @@
+use std::net::SocketAddr;
+
+/// What a started service is actually running, logged once as a single event
+///
+/// Holds names and URLs only; credentials in URLs are redacted before logging
+pub(crate) struct StartupSummary {
+    pub service: String,
+    pub version: Option<String>,
+    pub features: Vec<&'static str>,
+    pub database: Option<String>,
+    pub otel: Option<String>,
+    pub health: Vec<&'static str>,
+    pub links: Vec<String>,
+}
+
+impl StartupSummary {
+    pub fn log(&self, address: &SocketAddr) {
+        let docs: Vec<&str> = self
+            .links
+            .iter()
+            .map(String::as_str)
+            .filter(|link| !link.starts_with("/status/"))
+            .collect();
+        tracing::info!(
+            service = %self.service,
+            version = self.version.as_deref().unwrap_or("unknown"),
+            address = %address,
+            features = %self.features.join(","),
+            database = self.database.as_deref().unwrap_or("none"),
+            otel = self.otel.as_deref().map(redact_url).as_deref().unwrap_or("none"),
+            docs = %docs.join(","),
+            health = %self.health.join(","),
+            "startup: {} listening on http://{}",
+            self.service,
+            address
+        );
+    }
+}
+
+/// Replace the userinfo and drop the query of `url`, where credentials usually live
+pub(crate) fn redact_url(url: &str) -> String {
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    match rest[..authority_end].rsplit_once('@') {
+        Some((_, host)) => format!("{}://***@{}{}", scheme, host, &rest[authority_end..]),
+        None => url.to_string(),
+    }
+}
@@ -0,0 +1,312 @@
+use crate::error::ErrorBody;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Consecutive failures that open a circuit unless configured
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long an open circuit rejects calls before letting one through, unless configured
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+static REGISTRY: OnceLock<Mutex<BTreeMap<String, CircuitBreaker>>> = OnceLock::new();
+
+/// Settings for the breaker of the same name under `circuit_breakers`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open the circuit (default: 5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_threshold: Option<u32>,
+    /// Seconds the circuit stays open before a trial call is let through (default: 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_secs: Option<u64>,
+}
+
+/// State of a circuit, as reported by readiness and the `circuit_breaker.state` metric
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls are rejected with [`CircuitOpen`] until the cooldown passes
+    Open,
+    /// The cooldown passed and one trial call decides whether the circuit closes
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// Numeric form for metrics: 0 closed, 1 half-open, 2 open
+    pub fn as_metric(&self) -> u64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        }
+    }
+}
+
+/// Stops calling a failing dependency so requests fail fast instead of piling up
+///
+/// Breakers are registered by name, so every clone and every [`CircuitBreaker::named`]
+/// call for the same name share one state. After `failure_threshold` consecutive
+/// failures the circuit opens and calls fail with [`CircuitOpen`] for `cooldown`;
+/// the first call after that is a trial whose outcome closes or reopens it.
+///
+/// ```ignore
+/// let breaker = CircuitBreaker::named("billing");
+/// let invoice = breaker
+///     .call(async { billing.get_invoice(id).await })
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    name: String,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<Tracking>,
+}
+
+#[derive(Debug, Default)]
+struct Tracking {
+    failures: u32,
+    opened_at: Option<Instant>,
+    trial_in_flight: bool,
+}
+
+impl CircuitBreaker {
+    /// The breaker registered as `name`, created with the defaults if there's none yet
+    ///
+    /// Breakers listed under `circuit_breakers` in config are registered at build
+    pub fn named(name: &str) -> Self {
+        let mut registry = registry();
+        registry
+            .entry(name.to_string())
+            .or_insert_with(|| Self::build(name, DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN))
+            .clone()
+    }
+
+    /// Register `name` with explicit settings, replacing any earlier breaker of that name
+    pub fn new(name: &str, failure_threshold: u32, cooldown: Duration) -> Self {
+        let breaker = Self::build(name, failure_threshold, cooldown);
+        registry().insert(name.to_string(), breaker.clone());
+        breaker
+    }
+
+    /// Register `name` with settings from config, unset values use the defaults
+    pub fn from_config(name: &str, config: &CircuitBreakerConfig) -> Self {
+        Self::new(
+            name,
+            config
+                .failure_threshold
+                .unwrap_or(DEFAULT_FAILURE_THRESHOLD),
+            config
+                .cooldown_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_COOLDOWN),
+        )
+    }
+
+    fn build(name: &str, failure_threshold: u32, cooldown: Duration) -> Self {
+        Self(Arc::new(Inner {
+            name: name.to_string(),
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(Tracking::default()),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let tracking = self.tracking();
+        match tracking.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.0.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Run `call` unless the circuit is open, counting an `Err` as a failure
+    pub async fn call<F, T, E>(&self, call: F) -> Result<T, CircuitError<E>>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let attempt = self.acquire().map_err(CircuitError::Open)?;
+
+        let result = call.await;
+        attempt.finish(result.is_ok());
+        result.map_err(CircuitError::Inner)
+    }
+
+    /// Let a call through, or reject it while the circuit is open
+    ///
+    /// For calls `call` can't wrap; report the outcome on the returned [`Attempt`]
+    pub fn acquire(&self) -> Result<Attempt, CircuitOpen> {
+        let mut tracking = self.tracking();
+
+        if let Some(opened_at) = tracking.opened_at {
+            let elapsed = opened_at.elapsed();
+            if elapsed < self.0.cooldown || tracking.trial_in_flight {
+                return Err(CircuitOpen {
+                    name: self.0.name.clone(),
+                    retry_after_secs: self.0.cooldown.saturating_sub(elapsed).as_secs().max(1),
+                });
+            }
+            tracking.trial_in_flight = true;
+        }
+
+        Ok(Attempt {
+            breaker: self.clone(),
+            finished: false,
+        })
+    }
+
+    fn record(&self, success: bool) {
+        let mut tracking = self.tracking();
+        let was_open = tracking.opened_at.is_some();
+        tracking.trial_in_flight = false;
+
+        if success {
+            tracking.failures = 0;
+            tracking.opened_at = None;
+            if was_open {
+                tracing::info!("circuit: '{}' closed", self.0.name);
+            }
+            return;
+        }
+
+        tracking.failures = tracking.failures.saturating_add(1);
+        if was_open || tracking.failures >= self.0.failure_threshold {
+            tracking.opened_at = Some(Instant::now());
+            tracing::warn!(
+                "circuit: '{}' open after {} consecutive failures, retrying in {}s",
+                self.0.name,
+                tracking.failures,
+                self.0.cooldown.as_secs()
+            );
+        }
+    }
+
+    fn tracking(&self) -> std::sync::MutexGuard<'_, Tracking> {
+        self.0.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A call let through by [`CircuitBreaker::acquire`]
+///
+/// Dropped without `finish`, e.g. when the caller's future is cancelled, it
+/// counts as a failure so a trial call can't leave the circuit stuck open
+#[derive(Debug)]
+pub struct Attempt {
+    breaker: CircuitBreaker,
+    finished: bool,
+}
+
+impl Attempt {
+    pub fn finish(mut self, success: bool) {
+        self.finished = true;
+        self.breaker.record(success);
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.breaker.record(false);
+        }
+    }
+}
+
+fn registry() -> std::sync::MutexGuard<'static, BTreeMap<String, CircuitBreaker>> {
+    REGISTRY
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// State of every registered breaker by name
+pub fn states() -> BTreeMap<String, CircuitState> {
+    registry()
+        .iter()
+        .map(|(name, breaker)| (name.clone(), breaker.state()))
+        .collect()
+}
+
+/// Report each breaker's state as the `circuit_breaker.state` gauge
+#[cfg(feature = "otel")]
+pub(crate) fn register_metrics() {
+    use opentelemetry::KeyValue;
+
+    opentelemetry::global::meter("microkit")
+        .u64_observable_gauge("circuit_breaker.state")
+        .with_description("Circuit breaker state: 0 closed, 1 half-open, 2 open")
+        .with_callback(|gauge| {
+            for (name, state) in states() {
+                gauge.observe(state.as_metric(), &[KeyValue::new("circuit", name)]);
+            }
+        })
+        .build();
+}
+
+/// A call rejected because its circuit is open
+///
+/// Responds with 503 and `Retry-After` set to the rest of the cooldown
+#[derive(Debug, Clone)]
+pub struct CircuitOpen {
+    pub name: String,
+    pub retry_after_secs: u64,
+}
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Circuit '{}' is open, retry in {}s",
+            self.name, self.retry_after_secs
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+impl IntoResponse for CircuitOpen {
+    fn into_response(self) -> Response {
+        ErrorBody::new("Dependency unavailable", &HeaderMap::new())
+            .into_retry_response(StatusCode::SERVICE_UNAVAILABLE, self.retry_after_secs)
+    }
+}
+
+/// Error from [`CircuitBreaker::call`]: rejected by the breaker, or the call's own error
+#[derive(Debug)]
+pub enum CircuitError<E> {
+    Open(CircuitOpen),
+    Inner(E),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for CircuitError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircuitError::Open(open) => open.fmt(f),
+            CircuitError::Inner(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for CircuitError<E> {}
+
+impl<E: IntoResponse> IntoResponse for CircuitError<E> {
+    fn into_response(self) -> Response {
+        match self {
+            CircuitError::Open(open) => open.into_response(),
+            CircuitError::Inner(e) => e.into_response(),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::error::ErrorBody;
+use anyhow::{Context, Result, bail};
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::{HeaderMap, Request, StatusCode, request::Parts};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Header carrying the client address when the peer is a trusted proxy
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientIpHeader {
+    /// `X-Forwarded-For: client, proxy1, proxy2`
+    #[default]
+    XForwardedFor,
+    /// `X-Real-IP: client`, set by the proxy in front of the service
+    XRealIp,
+    /// `Forwarded: for=client, for=proxy1` (RFC 7239)
+    Forwarded,
+}
+
+impl ClientIpHeader {
+    fn name(&self) -> &'static str {
+        match self {
+            ClientIpHeader::XForwardedFor => "x-forwarded-for",
+            ClientIpHeader::XRealIp => "x-real-ip",
+            ClientIpHeader::Forwarded => "forwarded",
+        }
+    }
+}
+
+/// Client address resolution under `client_ip`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ClientIpConfig {
+    /// Header read when the peer is a trusted proxy: x-forwarded-for, x-real-ip or
+    /// forwarded (default: x-forwarded-for)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<ClientIpHeader>,
+    /// Proxies whose header is believed, as addresses or CIDR ranges, e.g. `10.0.0.0/8`
+    /// (default: none, the header is never read)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_proxies: Option<Vec<String>>,
+}
+
+impl ClientIpConfig {
+    /// Fail startup on proxies that aren't addresses or CIDR ranges
+    pub(crate) fn validate(&self) -> Result<()> {
+        for proxy in self.trusted_proxies.iter().flatten() {
+            proxy
+                .parse::<Cidr>()
+                .context("client_ip: trusted_proxies")?;
+        }
+        Ok(())
+    }
+}
+
+/// An address range such as `10.0.0.0/8` or `::1/128`, bare addresses are a single host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32| match self.prefix {
+            0 => 0,
+            prefix => u128::MAX << (bits - prefix as u32),
+        };
+
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address = address
+            .parse::<IpAddr>()
+            .with_context(|| format!("'{}' isn't an address or CIDR range", s))?
+            .to_canonical();
+
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max => prefix,
+                _ => bail!("'{}' needs a prefix length from 0 to {}", s, max),
+            },
+            None => max,
+        };
+
+        Ok(Self { address, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// Resolves the client address from the peer and, behind trusted proxies, the header
+#[derive(Debug, Clone)]
+pub(crate) struct Resolver {
+    header: ClientIpHeader,
+    trusted: Arc<[Cidr]>,
+}
+
+impl Resolver {
+    /// Proxies are checked by `Config::validate`, invalid ones are skipped here
+    pub(crate) fn new(config: Option<&ClientIpConfig>) -> Self {
+        Self {
+            header: config.and_then(|config| config.header).unwrap_or_default(),
+            trusted: config
+                .and_then(|config| config.trusted_proxies.as_ref())
+                .into_iter()
+                .flatten()
+                .filter_map(|proxy| proxy.parse().ok())
+                .collect(),
+        }
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Walk the forwarding chain back from the peer, stopping at the first hop that
+    /// isn't a trusted proxy, since anything before it could have been made up
+    fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let mut client = peer;
+        for hop in self.hops(headers).into_iter().rev() {
+            // Unknown or obfuscated hops end the chain at the proxy that reported them
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Addresses in the header, nearest the client first, `None` where unparseable
+    fn hops(&self, headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+        let values = headers
+            .get_all(self.header.name())
+            .iter()
+            .filter_map(|value| value.to_str().ok());
+
+        match self.header {
+            ClientIpHeader::XForwardedFor => values
+                .flat_map(|value| value.split(','))
+                .map(|hop| parse_node(hop.trim()))
+                .collect(),
+            // Several X-Real-IP values can't be told apart, so only a single one counts
+            ClientIpHeader::XRealIp => {
+                let values: Vec<_> = values.collect();
+                match values[..] {
+                    [value] => vec![parse_node(value.trim())],
+                    _ => Vec::new(),
+                }
+            }
+            ClientIpHeader::Forwarded => values
+                .flat_map(|value| value.split(','))
+                .map(|element| {
+                    element
+                        .split(';')
+                        .filter_map(|pair| pair.trim().split_once('='))
+                        .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                        .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// An address with an optional port, IPv6 in brackets when it has one
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest
+            .split_once(']')?
+            .0
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| ip.to_canonical());
+    }
+    node.parse::<SocketAddr>()
+        .ok()
+        .map(|address| address.ip().to_canonical())
+}
+
+/// Resolve the client address once per request for `ClientIp` and rate limiting
+pub(crate) fn apply(router: Router, resolver: Resolver) -> Router {
+    router.layer(axum::middleware::from_fn_with_state(resolver, resolve))
+}
+
+async fn resolve(
+    axum::extract::State(resolver): axum::extract::State<Resolver>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        let ip = resolver.resolve(peer.ip(), req.headers());
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(req).await
+}
+
+/// The client's address, read from the configured header only when the peer is
+/// one of `client_ip.trusted_proxies`, otherwise the socket's peer address
+///
+/// Rejects with 500 when the router isn't being served, e.g. when called directly
+/// in a test, as there's no peer to start from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    pub fn ip(&self) -> IpAddr {
+        self.0
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<ClientIp>() {
+            Some(client_ip) => Ok(*client_ip),
+            None => {
+                tracing::error!("client_ip: no peer address, is the router being served?");
+                Err(ErrorBody::new("Client address unavailable", &parts.headers)
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use std::cell::Cell;
+use std::sync::RwLock;
+
+/// Source of the current time
+pub type ClockFn = fn() -> DateTime<Utc>;
+
+static CLOCK: RwLock<ClockFn> = RwLock::new(Utc::now);
+
+thread_local! {
+    static FROZEN: Cell<Option<DateTime<Utc>>> = const { Cell::new(None) };
+}
+
+/// Current time used for `generated_on` stamps
+///
+/// A time frozen on this thread wins over the process clock, which defaults to
+/// `Utc::now`. Events keep the producer's `generated_on`, only new records and
+/// contracts created with `new` are stamped from here
+pub fn now() -> DateTime<Utc> {
+    if let Some(time) = FROZEN.with(Cell::get) {
+        return time;
+    }
+
+    let clock = *CLOCK.read().unwrap_or_else(|e| e.into_inner());
+    clock()
+}
+
+/// Replace the process clock, e.g. to read time from an external source
+pub fn set(clock: ClockFn) {
+    *CLOCK.write().unwrap_or_else(|e| e.into_inner()) = clock;
+}
+
+/// Freeze time on the current thread until the guard is dropped
+///
+/// Thread-local so parallel tests don't see each other's clocks, which covers
+/// `#[tokio::test]` on its default single-threaded runtime:
+///
+/// ```ignore
+/// let _clock = microkit::clock::freeze(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+/// let user = ActiveModel::from_api(&config, "Ada".into());
+/// assert_eq!(user.generated_on.unwrap(), microkit::clock::now());
+/// ```
+pub fn freeze(time: DateTime<Utc>) -> FrozenClock {
+    let previous = FROZEN.with(|frozen| frozen.replace(Some(time)));
+    FrozenClock { previous }
+}
+
+/// Restores the previous time on drop, see [`freeze`]
+#[must_use = "time is unfrozen when the guard is dropped"]
+pub struct FrozenClock {
+    previous: Option<DateTime<Utc>>,
+}
+
+impl Drop for FrozenClock {
+    fn drop(&mut self) {
+        FROZEN.with(|frozen| frozen.set(self.previous));
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 #[cfg(feature = "auth")]
-use crate::auth::AuthConfig;
+use crate::auth::{AuthConfig, AuthMode, TokenSource};
 
 pub async fn get() -> Result<Config> {
     let file = "microkit.yml";
@@ -19,22 +22,587 @@ pub async fn get() -> Result<Config> {
 pub struct Config {
     pub service_name: String,
     pub service_desc: Option<String>,
+    /// Baseline for the settings below that are left unset, ahead of the `MICROKIT_ENV`
+    /// preset (default: default, production: hardened)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Profile>,
+    /// Allowed cross-origin callers (default: any origin, hardened: same origin only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cors: Option<CorsConfig>,
+    /// Largest request body accepted, larger ones get 413 (default: 2 MiB for extractors, hardened: 1 MiB for all bodies)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<usize>,
+    /// Version shown on the index page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_version: Option<String>,
+    /// Serve a page at `/` linking to the docs and probes (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_page: Option<bool>,
+    /// Built frontend served beside the API, disabled unless present
+    #[serde(rename = "static", default, skip_serializing_if = "Option::is_none")]
+    pub static_files: Option<StaticConfig>,
     pub host: Option<String>,
+    /// Address family bound when `host` resolves to both: auto, ipv4 or ipv6 (default: auto)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<crate::network::IpFamily>,
+    /// Log filter, e.g. "info" or "info,sea_orm=warn". `RUST_LOG` takes precedence
+    /// unless `log_level_overrides_env` is set (default: info)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub log_level: Option<String>,
+    /// Let `log_level` take precedence over `RUST_LOG` (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level_overrides_env: Option<bool>,
+    /// Console log output: text or json (default: text, staging and production: json)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+    /// Requests allowed in flight before shedding with 503 (default: unlimited)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    /// Paths excluded from tracing, metrics, auth and load-shedding (default: health and metrics)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excluded_paths: Option<Vec<String>>,
     pub port_offset: Option<u16>,
+    /// HTTP server tuning (default: hyper's defaults)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<ServerConfig>,
+    /// Seconds each `on_shutdown` hook may run before it's abandoned (default: 10)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutdown_hook_timeout_secs: Option<u64>,
+    /// Seconds to keep serving after a shutdown signal with `/status/ready` failing,
+    /// so load balancers stop routing before requests drain (default: 5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_shutdown_delay_secs: Option<u64>,
+    /// Log request and response bodies at DEBUG, for debugging only (default: off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_bodies: Option<BodyLogConfig>,
+    /// Per-route token bucket limits, the first matching rule applies (default: none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limits: Option<Vec<crate::rate_limit::RateLimitRule>>,
+    /// Which header and proxies to trust for the client address (default: the peer address)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<crate::client_ip::ClientIpConfig>,
+    /// Circuit breaker settings by name, for `CircuitBreaker::named` (default: none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breakers: Option<BTreeMap<String, crate::circuit::CircuitBreakerConfig>>,
+    /// Retries for the shared HTTP client and the Dapr helpers (default: 3 attempts from 100ms)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<crate::retry::RetryConfig>,
+    /// Answer 503 on business routes while probes and docs stay up (default: off, toggled at runtime)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<crate::maintenance::MaintenanceConfig>,
+    /// Tenant resolution and per-tenant databases for the `Tenant` extractors (default: off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenancy: Option<crate::tenant::TenancyConfig>,
+    /// OpenAPI document settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docs: Option<DocsConfig>,
+    /// OpenAPI operationId naming: function, tag or method_path (default: function)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation_ids: Option<crate::router::OperationIdStrategy>,
+    /// How `entity::new_creation_key` generates keys: uuid_v4, uuid_v7, ulid or ksuid
+    /// (default: uuid_v4)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creation_key_strategy: Option<crate::entity::CreationKeyStrategy>,
+    /// Shape of error responses: default (`{ "error": ... }`) or problem (RFC 7807
+    /// `application/problem+json`), `with_error_renderer` takes precedence (default: default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_format: Option<crate::error::ErrorFormat>,
+    /// Wrap successful JSON responses as `{ "data": ..., "meta": { ... } }` (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_envelope: Option<bool>,
+    /// Public base URL including any path prefix, e.g. https://example.com/myservice.
+    /// Used for the OpenAPI servers entry and the URLs logged at startup (default: the bound address)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
     #[cfg(feature = "database")]
     pub database_url: Option<String>,
+    /// Read replica server URL for the `ReadDb` extractor (default: the primary)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_read_url: Option<String>,
     #[cfg(feature = "database")]
     pub database_name: Option<String>,
+    /// Postgres schema for tables, set as the connection's `search_path` (default: public)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_schema: Option<String>,
+    /// Connection pool sizing and health checks (default: SeaORM's pool with a ping every 30s)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_pool: Option<DatabasePoolConfig>,
+    /// Log statements through `tracing` and, with otel, trace each as a child span of the
+    /// request (default: SQLx's own logging)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_trace_queries: Option<bool>,
+    /// Level traced statements are logged at, under the `sqlx::query` target (default: debug)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_query_log_level: Option<crate::database::QueryLogLevel>,
+    /// Traced statements slower than this are also logged at WARN (default: off)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_slow_query_ms: Option<u64>,
+    /// Drop and recreate the database at startup, only with `MICROKIT_ALLOW_DROP=1` set (default: false)
     #[cfg(feature = "database")]
     pub database_drop: Option<bool>,
+    /// How `with_migrations` treats pending migrations at startup (default: auto)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migrations: Option<MigrationMode>,
+    /// Upper bound for the `limit` query parameter on paginated endpoints
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_max_limit: Option<u64>,
+    /// Seconds to wait for the Dapr sidecar at startup, overridden by `DAPR_WAIT_SECS`
+    #[cfg(feature = "dapr")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dapr_wait_secs: Option<u64>,
+    /// Topic that consumers publish non-retryable events to (default: events are dropped)
+    #[cfg(feature = "dapr")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter: Option<DeadLetterConfig>,
+    /// Publish events for entities implementing `DomainEvent` (default: off)
+    #[cfg(feature = "dapr")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_events: Option<DomainEventsConfig>,
+    /// Runtime switches for subsystems enabled with `with_*` calls (default: all on)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<FeatureToggles>,
+    /// Fail startup when `with_auth` is used without an `auth` section (default: false, hardened: true)
+    #[cfg(feature = "auth")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_required: Option<bool>,
+    /// Roles allowed to use the `/admin` endpoints, which fail startup without them (default: none)
+    #[cfg(feature = "auth")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_roles: Option<Vec<String>>,
     #[cfg(feature = "auth")]
     pub auth: Option<AuthConfigYaml>,
     #[cfg(feature = "otel")]
     pub otel: Option<OtelConfig>,
+    /// Top-level keys MicroKit doesn't know, for service-specific settings read with `extra_as`
+    #[serde(flatten, default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, serde_yaml_ng::Value>,
+}
+
+/// Log filter used when neither `RUST_LOG` nor `log_level` is set
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Body limit used by the hardened profile
+pub const HARDENED_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Variable naming the environment preset: development, staging or production
+pub const ENVIRONMENT_ENV: &str = "MICROKIT_ENV";
+
+/// Trace sampling ratio used in production unless `otel.sample_ratio` is set
+pub const PRODUCTION_SAMPLE_RATIO: f64 = 0.1;
+
+/// Preset of defaults chosen with `MICROKIT_ENV`, so one microkit.yml serves every environment
+///
+/// `development` keeps the permissive defaults. `staging` logs JSON. `production`
+/// logs JSON, samples a tenth of new traces and uses the hardened profile. Settings
+/// in microkit.yml, `profile` included, take precedence over the preset
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Development,
+    Staging,
+    Production,
+}
+
+impl Environment {
+    /// The environment named by `MICROKIT_ENV`, `None` when it's unset or empty
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var(ENVIRONMENT_ENV) else {
+            return Ok(None);
+        };
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" => Ok(None),
+            "development" => Ok(Some(Self::Development)),
+            "staging" => Ok(Some(Self::Staging)),
+            "production" => Ok(Some(Self::Production)),
+            _ => anyhow::bail!(
+                "{}: '{}' isn't one of development, staging or production",
+                ENVIRONMENT_ENV,
+                value
+            ),
+        }
+    }
+
+    /// Profile used when `profile` isn't set
+    pub fn profile(self) -> Profile {
+        match self {
+            Self::Production => Profile::Hardened,
+            Self::Development | Self::Staging => Profile::Default,
+        }
+    }
+
+    /// Log format used when `log_format` isn't set
+    pub fn log_format(self) -> LogFormat {
+        match self {
+            Self::Development => LogFormat::Text,
+            Self::Staging | Self::Production => LogFormat::Json,
+        }
+    }
+
+    /// Trace sampling ratio used when `otel.sample_ratio` isn't set
+    pub fn sample_ratio(self) -> f64 {
+        match self {
+            Self::Production => PRODUCTION_SAMPLE_RATIO,
+            Self::Development | Self::Staging => 1.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Development => "development",
+            Self::Staging => "staging",
+            Self::Production => "production",
+        })
+    }
+}
+
+/// Console log output
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, for log collectors
+    Json,
+}
+
+/// Defaults for settings that aren't configured
+///
+/// `hardened` turns off the conveniences that suit development: permissive CORS,
+/// documentors, unbounded streamed bodies and starting without auth config.
+/// Each can still be set explicitly
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    #[default]
+    Default,
+    Hardened,
+}
+
+/// Static files served from a directory, e.g. a built single-page app
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct StaticConfig {
+    /// Directory holding the files, relative to the working directory
+    pub dir: String,
+    /// Path the files are served under, API routes take precedence (default: /)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<String>,
+    /// Answer paths with no matching file with `index.html`, for client-side routing (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spa: Option<bool>,
+}
+
+impl StaticConfig {
+    pub fn mount(&self) -> &str {
+        self.mount.as_deref().unwrap_or("/")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CorsConfig {
+    /// Origins allowed to call the service, `*` for any
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed (default: any)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_methods: Option<Vec<String>>,
+    /// Request headers allowed (default: any)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_headers: Option<Vec<String>>,
+    /// Allow cookies and auth headers, not allowed with `*` (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_credentials: Option<bool>,
 }
 
 impl Config {
+    /// The environment preset from `MICROKIT_ENV`, development when it's unset or invalid
+    pub fn environment(&self) -> Environment {
+        Environment::from_env().ok().flatten().unwrap_or_default()
+    }
+
+    /// The profile in effect, from `profile` or else the environment preset
+    pub fn profile(&self) -> Profile {
+        self.profile.unwrap_or(self.environment().profile())
+    }
+
+    pub fn is_hardened(&self) -> bool {
+        self.profile() == Profile::Hardened
+    }
+
+    /// Console log format, from `log_format` or else the environment preset
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or(self.environment().log_format())
+    }
+
+    /// Share of new traces sampled, from `otel.sample_ratio` or else the environment preset
+    #[cfg(feature = "otel")]
+    pub fn sample_ratio(&self) -> f64 {
+        self.otel
+            .as_ref()
+            .and_then(|otel| otel.sample_ratio)
+            .unwrap_or(self.environment().sample_ratio())
+    }
+
+    /// Whether the documentors and their OpenAPI document are served
+    pub fn docs_enabled(&self) -> bool {
+        self.docs
+            .as_ref()
+            .and_then(|docs| docs.enabled)
+            .unwrap_or(!self.is_hardened())
+    }
+
+    /// Path the OpenAPI document is served at as JSON, the YAML form sits beside it
+    pub fn spec_path(&self) -> &str {
+        self.docs
+            .as_ref()
+            .and_then(|docs| docs.spec_path.as_deref())
+            .unwrap_or(DEFAULT_SPEC_PATH)
+    }
+
+    /// Whether the documentor UIs are served alongside the OpenAPI document
+    pub fn documentors_enabled(&self) -> bool {
+        self.docs_enabled()
+            && !self
+                .docs
+                .as_ref()
+                .and_then(|docs| docs.spec_only)
+                .unwrap_or(false)
+    }
+
+    /// Body limit applied to every request, `None` keeps axum's extractor default
+    pub fn body_limit(&self) -> Option<usize> {
+        self.max_body_bytes
+            .or(self.is_hardened().then_some(HARDENED_MAX_BODY_BYTES))
+    }
+
+    /// Whether `with_auth` without an `auth` section fails startup
+    #[cfg(feature = "auth")]
+    pub fn auth_required(&self) -> bool {
+        self.auth_required.unwrap_or(self.is_hardened())
+    }
+
+    /// Deserialize the service-specific section `key`
+    ///
+    /// ```ignore
+    /// // payments:
+    /// //   provider_url: https://pay.example.com
+    /// #[derive(Deserialize)]
+    /// struct Payments {
+    ///     provider_url: String,
+    /// }
+    ///
+    /// let payments: Payments = config.extra_as("payments")?;
+    /// ```
+    ///
+    /// A missing section is read as null, so ask for `Option<T>` when it's optional
+    pub fn extra_as<T: DeserializeOwned>(&self, key: &str) -> Result<T> {
+        let value = self.extra.get(key).cloned().unwrap_or_default();
+        let missing = value.is_null();
+        serde_yaml_ng::from_value(value).with_context(|| {
+            if missing {
+                format!("'{}' missing from microkit.yml", key)
+            } else {
+                format!("Could not deserialize '{}' from microkit.yml", key)
+            }
+        })
+    }
+
+    /// URL `role` is reached at from this machine, as the server binds it
+    ///
+    /// `public_url` for the API when it's set. Otherwise `host` with the role's port
+    /// after `port_offset`, using localhost when `host` is unset or a wildcard address.
+    /// Lets clients such as the template website follow the config instead of
+    /// hardcoding a port:
+    ///
+    /// ```ignore
+    /// let api = config.service_url(ServicePort::Api)?; // http://localhost:50000
+    /// ```
+    pub fn service_url(&self, role: crate::ServicePort) -> Result<String> {
+        if matches!(role, crate::ServicePort::Ephemeral) {
+            anyhow::bail!("An ephemeral port is only known once the service is bound");
+        }
+        if matches!(role, crate::ServicePort::Api)
+            && let Some(public_url) = &self.public_url
+        {
+            return Ok(public_url.trim_end_matches('/').to_string());
+        }
+
+        let host = match self.host.as_deref().map(str::trim) {
+            None | Some("" | "0.0.0.0" | "::" | "[::]") => "localhost",
+            Some(host) => host,
+        };
+        let host = if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]", host)
+        } else {
+            host.to_string()
+        };
+
+        match crate::network::bind_port(&role, self.port_offset)? {
+            80 => Ok(format!("http://{}", host)),
+            port => Ok(format!("http://{}:{}", host, port)),
+        }
+    }
+
+    /// Paths excluded from tracing, metrics, auth and load-shedding
+    pub fn excluded_paths(&self) -> Arc<[String]> {
+        match &self.excluded_paths {
+            Some(paths) => paths.clone().into(),
+            None => crate::middleware::DEFAULT_EXCLUDED_PATHS
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+        }
+    }
+
+    /// Log filter and where it came from: `RUST_LOG`, then `log_level`, then info
+    ///
+    /// `log_level_overrides_env` puts `log_level` ahead of `RUST_LOG`
+    pub fn log_filter(&self) -> (String, &'static str) {
+        let env = std::env::var("RUST_LOG")
+            .ok()
+            .filter(|filter| !filter.trim().is_empty())
+            .map(|filter| (filter, "RUST_LOG"));
+        let config = self
+            .log_level
+            .clone()
+            .filter(|filter| !filter.trim().is_empty())
+            .map(|filter| (filter, "config"));
+
+        let filter = if self.log_level_overrides_env == Some(true) {
+            config.or(env)
+        } else {
+            env.or(config)
+        };
+        filter.unwrap_or_else(|| (DEFAULT_LOG_LEVEL.to_string(), "default"))
+    }
+
+    /// Why `log_level` isn't a valid filter, if it isn't
+    ///
+    /// Not a `validate` problem, as logging warns and falls back to info instead
+    #[cfg(feature = "tracing")]
+    pub fn log_level_problem(&self) -> Option<String> {
+        let level = self.log_level.as_ref()?;
+        tracing_subscriber::EnvFilter::try_new(level)
+            .err()
+            .map(|e| format!("log_level: invalid filter '{}': {}", level, e))
+    }
+
+    /// Check settings that would fail at startup or misbehave at runtime
+    ///
+    /// Reports every problem found rather than stopping at the first, one per line
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if let Err(e) = Environment::from_env() {
+            problems.push(e.to_string());
+        }
+
+        if let Some(tenancy) = &self.tenancy
+            && let Err(e) = tenancy.validate()
+        {
+            problems.push(e.to_string());
+        }
+
+        if !self.spec_path().starts_with('/') {
+            problems.push(format!(
+                "docs: spec_path '{}' must start with '/'",
+                self.spec_path()
+            ));
+        }
+
+        if let Some(retry) = &self.retry
+            && let Err(e) = retry.validate()
+        {
+            problems.push(e.to_string());
+        }
+
+        if let Some(cors) = &self.cors
+            && let Err(e) = crate::middleware::cors_layer(cors)
+        {
+            problems.push(e.to_string());
+        }
+
+        if let Some(static_files) = &self.static_files {
+            let mount = static_files.mount();
+            if !mount.starts_with('/') || (mount != "/" && mount.ends_with('/')) {
+                problems.push(format!(
+                    "static: mount '{}' must start with '/' and not end with one",
+                    mount
+                ));
+            }
+        }
+
+        if let Some(offset) = self.port_offset {
+            for (role, port) in crate::ServicePort::port_map(offset) {
+                if port.is_none() {
+                    problems.push(format!(
+                        "port_offset: {} puts the {} port past {}",
+                        offset,
+                        role,
+                        u16::MAX
+                    ));
+                }
+            }
+        }
+
+        if let Some(client_ip) = &self.client_ip
+            && let Err(e) = client_ip.validate()
+        {
+            problems.push(format!("{:#}", e));
+        }
+
+        for rule in self.rate_limits.iter().flatten() {
+            if rule.rate.is_nan() || rule.rate <= 0.0 {
+                problems.push(format!(
+                    "rate_limits: '{}' needs a rate above 0, its bucket would never refill",
+                    rule.path
+                ));
+            }
+        }
+
+        #[cfg(feature = "otel")]
+        if let Some(ratio) = self.otel.as_ref().and_then(|otel| otel.sample_ratio)
+            && !(0.0..=1.0).contains(&ratio)
+        {
+            problems.push(format!(
+                "otel: sample_ratio {} must be between 0 and 1",
+                ratio
+            ));
+        }
+
+        #[cfg(feature = "auth")]
+        if let Err(e) = self.create_auth_config() {
+            problems.push(e.to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!(problems.join("\n"))
+        }
+    }
+
+    /// How long to wait for the Dapr sidecar, preferring the environment over config
+    #[cfg(feature = "dapr")]
+    pub fn dapr_wait(&self) -> std::time::Duration {
+        std::env::var(crate::dapr::WAIT_ENV)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .or(self.dapr_wait_secs)
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(crate::dapr::DEFAULT_WAIT)
+    }
+
     /// Create an AuthConfig from the configuration
     #[cfg(feature = "auth")]
     pub fn create_auth_config(&self) -> Result<Option<AuthConfig>> {
@@ -42,7 +610,32 @@ impl Config {
             return Ok(None);
         };
 
-        let mut auth = AuthConfig::oidc(auth_config.issuer.clone(), auth_config.jwks_uri.clone());
+        let issuer = auth_config.issuer.clone();
+        // Only hs256 looks at the environment, so a secret exported for another
+        // service doesn't clash with jwks_uri
+        let signing_secret = auth_config.signing_secret.clone().or_else(|| {
+            (auth_config.mode() == AuthMode::Hs256)
+                .then(|| std::env::var(crate::auth::SIGNING_SECRET_ENV).ok())
+                .flatten()
+        });
+        let mut auth = match (auth_config.mode(), &auth_config.jwks_uri, &signing_secret) {
+            (_, Some(_), Some(_)) => {
+                anyhow::bail!(
+                    "auth: set either jwks_uri (oidc) or signing_secret (hs256), not both"
+                )
+            }
+            (AuthMode::Oidc, Some(jwks_uri), None) => AuthConfig::oidc(issuer, jwks_uri.clone()),
+            (AuthMode::Oidc, None, _) => anyhow::bail!("auth: jwks_uri is required in oidc mode"),
+            (AuthMode::Hs256, None, Some(secret)) if !secret.is_empty() => {
+                AuthConfig::hs256(secret, issuer)
+            }
+            (AuthMode::Hs256, _, _) => {
+                anyhow::bail!(
+                    "auth: signing_secret or {} is required in hs256 mode",
+                    crate::auth::SIGNING_SECRET_ENV
+                )
+            }
+        };
 
         if let Some(audience) = &auth_config.audience {
             auth = auth.with_audience(audience.clone());
@@ -52,15 +645,212 @@ impl Config {
             auth = auth.with_client_secret(client_secret.clone());
         }
 
+        if let Some(secs) = auth_config.http_timeout_secs {
+            auth = auth.with_http_timeout(std::time::Duration::from_secs(secs));
+        }
+
+        if let Some(trace_claims) = &auth_config.trace_claims {
+            auth = auth.with_trace_claims(trace_claims.clone());
+        }
+
+        if let Some(token_sources) = &auth_config.token_sources {
+            auth = auth.with_token_sources(token_sources.clone());
+        }
+
+        // Browsers send the cookie along with cross-site requests, so writes that rely
+        // on it are only taken from origins listed for CORS
+        if auth_config
+            .token_sources
+            .as_ref()
+            .is_some_and(|sources| sources.contains(&TokenSource::Cookie))
+        {
+            let origins = self
+                .cors
+                .as_ref()
+                .map(|cors| cors.allowed_origins.clone())
+                .unwrap_or_default();
+            if origins.is_empty() || origins.iter().any(|origin| origin == "*") {
+                anyhow::bail!(
+                    "auth: token_sources cookie needs cors.allowed_origins without \"*\", as any site could make requests with the cookie"
+                );
+            }
+            auth = auth.with_csrf_origins(origins);
+        }
+
         Ok(Some(auth))
     }
 }
 
+/// Body logging settings, enabled by being present
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct BodyLogConfig {
+    /// Largest body captured, in bytes (default: 4096)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    /// Headers redacted in addition to `Authorization` and cookies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redact_headers: Option<Vec<String>>,
+}
+
+/// Disables subsystems without a rebuild, e.g. `features.otel: false`
+///
+/// Only switches off what the builder enabled; unset flags leave it on
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct FeatureToggles {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otel: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_checks: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dapr: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<bool>,
+}
+
+/// Seconds between background pings of the primary database unless configured
+#[cfg(feature = "database")]
+pub const DEFAULT_DB_HEALTH_CHECK_SECS: u64 = 30;
+
+/// Failed pings in a row before the database is reported unready unless configured
+#[cfg(feature = "database")]
+pub const DEFAULT_DB_UNHEALTHY_AFTER: u32 = 3;
+
+#[cfg(feature = "database")]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DatabasePoolConfig {
+    /// Most connections held open (default: SeaORM's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// Connections kept open while idle (default: SeaORM's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_connections: Option<u32>,
+    /// Ping each connection before handing it out, so dead ones are replaced (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_before_acquire: Option<bool>,
+    /// Only ping connections idle for at least this long, instead of on every acquire
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_idle_secs: Option<u64>,
+    /// Seconds between background pings of the primary, 0 to disable (default: 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check_secs: Option<u64>,
+    /// Failed pings in a row before readiness reports the database down (default: 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unhealthy_after: Option<u32>,
+    /// Requests allowed to wait for a connection once all are in use, more get 503
+    /// with Retry-After instead of queueing (default: unlimited)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_waiting: Option<u32>,
+}
+
+/// Startup behaviour for pending migrations
+#[cfg(feature = "database")]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationMode {
+    /// Apply pending migrations
+    #[default]
+    Auto,
+    /// Fail startup if any migrations are pending, without applying them
+    Check,
+    /// Apply nothing, only warn about pending migrations
+    Off,
+}
+
+/// Connection settings for the HTTP server, unset values keep hyper's defaults
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ServerConfig {
+    /// Accept HTTP/2 with prior knowledge (h2c) alongside HTTP/1.1 (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2: Option<bool>,
+    /// Reuse HTTP/1.1 connections between requests (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<bool>,
+    /// Seconds between HTTP/2 PINGs that keep idle connections open (default: off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    /// Seconds to wait for a PING acknowledgement before closing (default: 20)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2_keep_alive_timeout_secs: Option<u64>,
+    /// Streams multiplexed on one HTTP/2 connection (default: 200)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2_max_concurrent_streams: Option<u32>,
+}
+
+/// Path of the OpenAPI JSON document unless configured
+pub const DEFAULT_SPEC_PATH: &str = "/api-docs/openapi.json";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DocsConfig {
+    /// Serve the documentors and OpenAPI document (default: true, hardened: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Path of the OpenAPI JSON document, YAML is served with a `.yaml` extension
+    /// (default: /api-docs/openapi.json)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_path: Option<String>,
+    /// Serve only the OpenAPI document, without the documentor UIs (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_only: Option<bool>,
+    /// Normalize the OpenAPI document for client generators: operation ids, closed request
+    /// objects and nullable optional fields (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_conventions: Option<bool>,
+    /// Duplicate operation ids and schema names defined differently by merged routes:
+    /// error fails startup, warn logs them (default: error)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_conflict: Option<crate::router::ConflictPolicy>,
+    /// Tag metadata, listed in display order. Undeclared tags used by handlers follow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<TagConfig>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TagConfig {
+    /// Tag as used by handlers, e.g. "Users (API)"
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_docs: Option<ExternalDocsConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ExternalDocsConfig {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Dead-letter destination for events consumers can't process
+#[cfg(feature = "dapr")]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeadLetterConfig {
+    /// Dapr pub/sub component name
+    pub pubsub: String,
+    pub topic: String,
+}
+
+/// Where entities publish their domain events
+#[cfg(feature = "dapr")]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DomainEventsConfig {
+    /// Dapr pub/sub component name
+    pub pubsub: String,
+}
+
 #[cfg(feature = "otel")]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct OtelConfig {
     pub url: String,
     pub token: String,
+    /// Share of new traces sampled, 0 to 1, traces started upstream follow the caller's
+    /// decision (default: 1, production: 0.1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_ratio: Option<f64>,
 }
 
 /// Authentication configuration from YAML
@@ -70,15 +860,44 @@ pub struct AuthConfigYaml {
     /// OIDC issuer URL
     /// For Cognito: https://cognito-idp.{region}.amazonaws.com/{userPoolId}
     pub issuer: String,
-    /// OIDC JWKS URI
+    /// How token signatures are verified: `oidc` or `hs256` (default: oidc)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<crate::auth::AuthMode>,
+    /// OIDC JWKS URI, required in oidc mode
     /// For Cognito: https://cognito-idp.{region}.amazonaws.com/{userPoolId}/.well-known/jwks.json
-    pub jwks_uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    /// Shared HS256 secret, required in hs256 mode unless `MICROKIT_AUTH_SIGNING_SECRET`
+    /// is set, which keeps it out of committed config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret: Option<String>,
     /// Expected audience/client ID
     pub audience: Option<String>,
     /// Documentor: Default scopes
     pub scopes: Option<Vec<String>>,
     /// Documentor: Client ID
     pub client_id: Option<String>,
-    /// Documentor: Client secret (Provide within config-private.yml so it doesn't get committed)
+    /// Documentor: Client secret
     pub client_secret: Option<String>,
+    /// Seconds before requests to the identity provider time out (default: 5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_timeout_secs: Option<u64>,
+    /// Fetch signing keys in the background at startup rather than on the first request
+    /// (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefetch_jwks: Option<bool>,
+    /// Claims recorded on request spans (default: sub and email, `[]` to disable)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_claims: Option<Vec<crate::auth::TraceClaim>>,
+    /// Where tokens are read from, in priority order: header, cookie (`access_token`)
+    /// and query (`?access_token=`, can leak into logs) (default: [header])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_sources: Option<Vec<crate::auth::TokenSource>>,
+}
+
+#[cfg(feature = "auth")]
+impl AuthConfigYaml {
+    pub fn mode(&self) -> AuthMode {
+        self.mode.unwrap_or_default()
+    }
 }
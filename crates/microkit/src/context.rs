@@ -0,0 +1,108 @@
+use axum::http::{Extensions, HeaderMap, Request, request::Parts};
+use axum::response::Response;
+
+pub use crate::config::Config;
+pub use crate::server::Draining;
+pub use crate::tenant::Tenant;
+
+#[cfg(feature = "auth")]
+pub use crate::auth::AuthConfig;
+
+#[cfg(feature = "database")]
+pub use crate::database::DbPools;
+#[cfg(feature = "database")]
+pub use crate::tenant::TenantDatabases;
+#[cfg(feature = "database")]
+pub use sea_orm::DatabaseConnection;
+
+#[cfg(feature = "dapr")]
+pub use crate::dapr::Dapr;
+
+/// Anything carrying request headers and extensions, either a whole request in
+/// `from_fn` middleware or the parts seen by an extractor
+///
+/// ```ignore
+/// async fn audit(req: Request, next: Next) -> Response {
+///     let service = context::config(&req).map(|config| config.service_name.clone());
+///     tracing::info!(?service, request_id = ?context::request_id(&req), "audit");
+///     next.run(req).await
+/// }
+/// ```
+pub trait RequestContext {
+    fn extensions(&self) -> &Extensions;
+    fn headers(&self) -> &HeaderMap;
+}
+
+impl RequestContext for Parts {
+    fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+impl<B> RequestContext for Request<B> {
+    fn extensions(&self) -> &Extensions {
+        self.extensions()
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+/// The service's loaded config
+pub fn config(req: &impl RequestContext) -> Option<&Config> {
+    req.extensions().get()
+}
+
+/// Request ID from the `x-request-id` header, as echoed in error bodies
+pub fn request_id(req: &impl RequestContext) -> Option<String> {
+    crate::error::request_id(req.headers())
+}
+
+/// Whether the server has begun shutting down, false when not served by MicroKit
+pub fn is_draining(req: &impl RequestContext) -> bool {
+    req.extensions()
+        .get::<Draining>()
+        .is_some_and(Draining::is_draining)
+}
+
+/// Tenant of the request, resolved as the [`Tenant`] extractor does
+///
+/// Rejects with the extractor's error response, so middleware can return it as is
+pub async fn tenant(parts: &mut Parts) -> Result<Tenant, Response> {
+    <Tenant as axum::extract::FromRequestParts<()>>::from_request_parts(parts, &()).await
+}
+
+/// Token validation settings used by `AuthenticatedUser`
+#[cfg(feature = "auth")]
+pub fn auth_config(req: &impl RequestContext) -> Option<&AuthConfig> {
+    req.extensions().get()
+}
+
+/// Primary connection, as given to the `Db` extractor
+#[cfg(feature = "database")]
+pub fn database(req: &impl RequestContext) -> Option<&DatabaseConnection> {
+    req.extensions().get()
+}
+
+/// Replica connection, or the primary without `database_read_url`, as given to `ReadDb`
+#[cfg(feature = "database")]
+pub fn read_database(req: &impl RequestContext) -> Option<&DatabaseConnection> {
+    req.extensions().get::<DbPools>().map(|pools| &pools.read)
+}
+
+/// Per-tenant connections, as used by the `TenantDb` extractor
+#[cfg(feature = "database")]
+pub fn tenant_databases(req: &impl RequestContext) -> Option<&TenantDatabases> {
+    req.extensions().get()
+}
+
+/// Dapr client, as given to the `DaprClient` extractor
+#[cfg(feature = "dapr")]
+pub fn dapr(req: &impl RequestContext) -> Option<&Dapr> {
+    req.extensions().get()
+}
@@ -1,30 +1,313 @@
-use anyhow::{Context, Result, bail};
-use dapr::{Client, client::TonicClient, dapr::proto::runtime::v1::dapr_client::DaprClient};
+use crate::error::ErrorBody;
+use crate::retry::{Failure, RetryPolicy};
+use anyhow::{Context, Result, anyhow, bail};
+use axum::extract::FromRequestParts;
+use axum::http::{StatusCode, request::Parts};
+use axum::response::Response;
+use dapr::{
+    Client, client::TonicClient, dapr::proto::runtime::v1::dapr_client::DaprClient as GrpcClient,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 use tonic::transport::Channel;
 
+/// How long to wait for the sidecar when neither config nor env specify it
+pub const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+
+/// Environment variable overriding `dapr_wait_secs`
+pub const WAIT_ENV: &str = "DAPR_WAIT_SECS";
+
+/// Dapr sidecar client
+///
+/// Cloning is cheap, clones share one multiplexed gRPC channel to the sidecar
+#[derive(Clone)]
 pub struct Dapr {
-    pub client: Client<DaprClient<Channel>>,
+    pub client: Client<GrpcClient<Channel>>,
 }
 
 impl Dapr {
     pub async fn new() -> Result<Self> {
+        Self::connect(DEFAULT_WAIT).await
+    }
+
+    /// Wait up to `max_wait` for the sidecar to report healthy, then connect
+    pub async fn connect(max_wait: Duration) -> Result<Self> {
         let endpoint = "https://127.0.0.1".to_string();
-        let client = match dapr::Client::<TonicClient>::connect(endpoint).await {
-            Ok(client) => client,
+
+        let result = match wait_for_sidecar(max_wait).await {
+            Ok(()) => dapr::Client::<TonicClient>::connect(endpoint)
+                .await
+                .map_err(|e| anyhow!(e)),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(client) => Ok(Self { client }),
             Err(e) => {
                 if cfg!(debug_assertions) {
                     bail!("Dapr is not running. To run with Dapr, run: cargo mk all");
                 }
-                return Err(anyhow::anyhow!(e)).context("Dapr is not running");
+                Err(e).context("Dapr is not running")
             }
-        };
+        }
+    }
+
+    /// Publish `data` as JSON to a topic
+    ///
+    /// With the `otel` feature the current trace context is set as the CloudEvent's
+    /// `traceparent`, which Dapr forwards on delivery, so the consumer's spans
+    /// continue the publisher's trace
+    pub async fn publish<T: Serialize>(
+        &mut self,
+        pubsub: &str,
+        topic: &str,
+        data: &T,
+    ) -> Result<()> {
+        self.publish_with(pubsub, topic, data, &RetryPolicy::configured())
+            .await
+    }
 
-        Ok(Self { client })
+    /// Publish like [`publish`](Self::publish), retrying according to `policy`
+    ///
+    /// A publish whose answer is lost may have gone through, so retrying can
+    /// deliver the event twice. It's only retried when `policy` opts in with
+    /// `non_idempotent`, for consumers that deduplicate
+    pub async fn publish_with<T: Serialize>(
+        &mut self,
+        pubsub: &str,
+        topic: &str,
+        data: &T,
+        policy: &RetryPolicy,
+    ) -> Result<()> {
+        let data = serde_json::to_vec(data)?;
+        policy
+            .run(
+                false,
+                || {
+                    // Clones share the channel, each attempt gets its own handle
+                    let mut client = self.client.clone();
+                    let data = data.clone();
+                    async move {
+                        client
+                            .publish_event(
+                                pubsub,
+                                topic,
+                                "application/json",
+                                data,
+                                trace_metadata(),
+                            )
+                            .await
+                    }
+                },
+                |e| Failure::Error(e),
+            )
+            .await
+            .with_context(|| format!("Couldn't publish to '{}' on '{}'", topic, pubsub))
     }
 
-    pub async fn get_secret(&mut self, secret_name: &str) -> Result<String> {
-        let result = self.client.get_secret("secrets", secret_name).await?;
-        let secret_opt = result.data.get(secret_name).cloned();
-        secret_opt.ok_or_else(|| anyhow::anyhow!("Couldn't get secret"))
+    /// Get the value stored under the secret's own name
+    pub async fn get_secret(&mut self, store: &str, secret_name: &str) -> Result<String> {
+        let mut secret = self.get_secret_map(store, secret_name).await?;
+        secret
+            .remove(secret_name)
+            .ok_or_else(|| anyhow::anyhow!("Couldn't get secret"))
+    }
+
+    /// Get every key of a secret, e.g. a composite database credential
+    pub async fn get_secret_map(
+        &mut self,
+        store: &str,
+        secret_name: &str,
+    ) -> Result<HashMap<String, String>> {
+        let result = RetryPolicy::configured()
+            .run(
+                true,
+                || {
+                    let mut client = self.client.clone();
+                    async move { client.get_secret(store, secret_name).await }
+                },
+                |e| Failure::Error(e),
+            )
+            .await
+            .with_context(|| format!("Couldn't get secret '{}' from '{}'", secret_name, store))?;
+        Ok(result.data)
+    }
+
+    /// Get a secret and deserialize its keys into `T`
+    pub async fn get_secret_as<T: DeserializeOwned>(
+        &mut self,
+        store: &str,
+        secret_name: &str,
+    ) -> Result<T> {
+        let secret = self.get_secret_map(store, secret_name).await?;
+        serde_json::from_value(serde_json::to_value(secret)?)
+            .with_context(|| format!("Couldn't deserialize secret '{}'", secret_name))
+    }
+}
+
+/// Dapr client extractor
+///
+/// Reads the client from request extensions, where MicroKit places it when Dapr
+/// is enabled. Each request gets its own clone, so the `&mut self` calls need no
+/// lock and concurrent handlers don't wait on each other; the clones share the
+/// underlying gRPC channel, which multiplexes their calls
+///
+/// ```ignore
+/// async fn create(mut dapr: DaprClient, Json(user): Json<User>) -> Result<StatusCode, AppError> {
+///     dapr.publish("pubsub", "users", &user).await?;
+///     Ok(StatusCode::CREATED)
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DaprClient(pub Dapr);
+
+impl Deref for DaprClient {
+    type Target = Dapr;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for DaprClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for DaprClient
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Dapr>() {
+            Some(dapr) => Ok(DaprClient(dapr.clone())),
+            None => {
+                tracing::error!(
+                    "Dapr not found in request extensions. \
+                         Did you forget to call with_dapr?"
+                );
+                Err(ErrorBody::new("Dapr not configured", &parts.headers)
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
+
+/// Publish metadata overriding the CloudEvent trace attributes with the current span's
+///
+/// The client can't attach gRPC headers, so without this Dapr starts a new trace
+/// for each publish
+#[cfg(feature = "otel")]
+fn trace_metadata() -> Option<HashMap<String, String>> {
+    use opentelemetry::global;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    let mut fields = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut fields));
+
+    let metadata: HashMap<String, String> = fields
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| (format!("cloudevent.{}", key), value))
+        .collect();
+    (!metadata.is_empty()).then_some(metadata)
+}
+
+#[cfg(not(feature = "otel"))]
+fn trace_metadata() -> Option<HashMap<String, String>> {
+    None
+}
+
+/// Timeout for requests to the sidecar's HTTP API
+const SIDECAR_TIMEOUT: Duration = Duration::from_secs(2);
+
+static SIDECAR_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// URL of `path` on the sidecar's HTTP API, on `DAPR_HTTP_PORT` (default: 3500)
+fn sidecar_url(path: &str) -> String {
+    let port = std::env::var("DAPR_HTTP_PORT").unwrap_or_else(|_| "3500".to_string());
+    format!("http://127.0.0.1:{}{}", port, path)
+}
+
+/// URL invoking `method` on the Dapr app `app_id` through the sidecar
+///
+/// For calls with the shared HTTP client, which propagates the trace and can be
+/// guarded by a circuit breaker with `http::client_with_breaker`
+pub fn invoke_url(app_id: &str, method: &str) -> String {
+    sidecar_url(&format!(
+        "/v1.0/invoke/{}/method/{}",
+        app_id,
+        method.trim_start_matches('/')
+    ))
+}
+
+fn sidecar_client() -> &'static reqwest::Client {
+    SIDECAR_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(SIDECAR_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// Ping the sidecar's metadata endpoint for readiness
+///
+/// The error says whether the sidecar is down (nothing listening), starting
+/// (listening but not serving yet) or not responding in time
+#[cfg(feature = "health-checks")]
+pub(crate) async fn sidecar_status() -> Result<(), String> {
+    match sidecar_client()
+        .get(sidecar_url("/v1.0/metadata"))
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!(
+            "sidecar starting: metadata returned {}",
+            response.status()
+        )),
+        Err(e) if e.is_connect() => Err("sidecar down: connection refused".to_string()),
+        Err(e) if e.is_timeout() => Err(format!(
+            "sidecar not responding within {}s",
+            SIDECAR_TIMEOUT.as_secs()
+        )),
+        Err(e) => Err(format!("sidecar unreachable: {}", e)),
+    }
+}
+
+/// Poll the sidecar's outbound health endpoint with a bounded exponential backoff
+async fn wait_for_sidecar(max_wait: Duration) -> Result<()> {
+    let url = sidecar_url("/v1.0/healthz/outbound");
+    let client = sidecar_client();
+
+    let deadline = Instant::now() + max_wait;
+    let mut delay = Duration::from_millis(100);
+
+    loop {
+        let error = match client.get(&url).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => anyhow!("sidecar responded {}", response.status()),
+            Err(e) => anyhow!(e),
+        };
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(error).context(format!(
+                "Dapr sidecar not healthy after {}s",
+                max_wait.as_secs()
+            ));
+        }
+
+        tracing::debug!("dapr: waiting for sidecar: {}", error);
+        tokio::time::sleep(delay.min(deadline - now)).await;
+        delay = (delay * 2).min(Duration::from_secs(2));
     }
 }
@@ -1,10 +1,520 @@
+use crate::config::DatabasePoolConfig;
+use crate::error::{AppError, ErrorBody};
 use anyhow::{Result, bail};
-use sea_orm::{ConnectionTrait, Database, DatabaseConnection, Statement};
+use axum::extract::FromRequestParts;
+use axum::http::{StatusCode, request::Parts};
+use axum::response::Response;
+use sea_orm::{
+    ActiveModelTrait, ConnectOptions, ConnectionTrait, Database, DatabaseConnection,
+    DatabaseTransaction, EntityTrait, Statement, TransactionTrait, TryInsertResult,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Pool size when `database_pool.max_connections` isn't set, sqlx's default
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+
+/// Seconds clients are told to wait after a 503 from `database_pool.max_waiting`
+pub const BUSY_RETRY_AFTER_SECS: u64 = 1;
+
+/// Backpressure on requests that use the database, from `database_pool.max_waiting`
+///
+/// At most `max_connections + max_waiting` requests may hold a connection from
+/// each pool at once: the primary, the replica and every tenant database are
+/// counted apart, as each has its own connections. The next one is answered 503
+/// with `Retry-After` as soon as it extracts `Db`, `ReadDb`, `TenantDb` or `Txn`,
+/// instead of queueing for a connection until the pool's acquire timeout fails it
+/// with 500. A request counts once per pool however many extractors it uses, until
+/// its response is ready
+#[derive(Debug, Clone)]
+pub struct DbAdmission {
+    /// Requests each pool admits at once
+    capacity: usize,
+    /// Whether `ReadDb` has a pool of its own rather than the primary's
+    replica: bool,
+    semaphores: Arc<Mutex<HashMap<Pool, Arc<Semaphore>>>>,
+}
+
+impl DbAdmission {
+    /// `None` unless `max_waiting` is set, `replica` when `database_read_url` is
+    pub fn from_config(pool: Option<&DatabasePoolConfig>, replica: bool) -> Option<Self> {
+        let pool = pool?;
+        let waiting = pool.max_waiting?;
+        let max = pool.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        Some(Self {
+            capacity: max.saturating_add(waiting) as usize,
+            replica,
+            semaphores: Arc::default(),
+        })
+    }
+
+    /// Permits of `pool`, created on first use as tenants are only known per request
+    fn semaphore(&self, pool: &Pool) -> Arc<Semaphore> {
+        self.semaphores
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(pool.clone())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.capacity)))
+            .clone()
+    }
+}
+
+/// Connection pool a database extractor draws from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Pool {
+    Primary,
+    Replica,
+    Tenant(String),
+}
+
+impl std::fmt::Display for Pool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pool::Primary => write!(f, "primary"),
+            Pool::Replica => write!(f, "replica"),
+            Pool::Tenant(id) => write!(f, "tenant '{}'", id),
+        }
+    }
+}
+
+/// Permits of the current request, one per pool, held by [`admission`] until the
+/// response is ready
+#[derive(Clone)]
+struct Admitted {
+    admission: DbAdmission,
+    permits: Arc<Mutex<HashMap<Pool, OwnedSemaphorePermit>>>,
+}
+
+/// Track each request's database permits, applied when `max_waiting` is set
+pub(crate) async fn admission(
+    axum::extract::State(admission): axum::extract::State<DbAdmission>,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let admitted = Admitted {
+        admission,
+        permits: Arc::default(),
+    };
+    // Extensions can be dropped before the handler runs, this clone outlives it
+    let held = admitted.permits.clone();
+    request.extensions_mut().insert(admitted);
+
+    let response = next.run(request).await;
+    drop(held);
+    response
+}
+
+/// Take a permit of `pool` for the request, false when that pool is saturated
+pub(crate) fn admit(extensions: &axum::http::Extensions, pool: Pool) -> bool {
+    let Some(admitted) = extensions.get::<Admitted>() else {
+        return true;
+    };
+    let pool = match pool {
+        Pool::Replica if !admitted.admission.replica => Pool::Primary,
+        pool => pool,
+    };
+
+    let mut permits = admitted.permits.lock().unwrap_or_else(|e| e.into_inner());
+    if permits.contains_key(&pool) {
+        return true;
+    }
+
+    match admitted.admission.semaphore(&pool).try_acquire_owned() {
+        Ok(permit) => {
+            permits.insert(pool, permit);
+            true
+        }
+        Err(_) => {
+            tracing::debug!("database: {} pool saturated, turning request away", pool);
+            false
+        }
+    }
+}
+
+/// Response to requests [`admit`] turned away
+pub(crate) fn busy(headers: &axum::http::HeaderMap) -> Response {
+    ErrorBody::new("Database busy", headers)
+        .into_retry_response(StatusCode::SERVICE_UNAVAILABLE, BUSY_RETRY_AFTER_SECS)
+}
+
+/// Database connection extractor
+///
+/// Reads the connection from request extensions, where MicroKit places it when
+/// the database is enabled, so handlers don't depend on the router's typed state
+#[derive(Debug, Clone)]
+pub struct Db(pub DatabaseConnection);
+
+impl Deref for Db {
+    type Target = DatabaseConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for Db
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if !admit(&parts.extensions, Pool::Primary) {
+            return Err(busy(&parts.headers));
+        }
+
+        match parts.extensions.get::<DatabaseConnection>() {
+            Some(db) => Ok(Db(db.clone())),
+            None => {
+                tracing::error!(
+                    "DatabaseConnection not found in request extensions. \
+                         Did you forget to call with_database?"
+                );
+                Err(ErrorBody::new("Database not configured", &parts.headers)
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
+
+/// Primary and read connections
+///
+/// Without `database_read_url` both point at the primary. With a replica, reads
+/// through `read` may lag behind writes: a row just inserted through `write` can
+/// be missing from `read` until replication catches up, so handlers that read
+/// their own writes, or read to decide what to write, should use `write`.
+/// Migrations and writes always go to the primary
+#[derive(Debug, Clone)]
+pub struct DbPools {
+    pub write: DatabaseConnection,
+    pub read: DatabaseConnection,
+}
+
+impl DbPools {
+    /// Pools sharing the primary connection for reads
+    pub fn primary(write: DatabaseConnection) -> Self {
+        Self {
+            read: write.clone(),
+            write,
+        }
+    }
+}
+
+/// Read-only database connection extractor
+///
+/// Uses the replica from `database_read_url` when configured, otherwise the
+/// primary. See [`DbPools`] for the consistency caveats
+#[derive(Debug, Clone)]
+pub struct ReadDb(pub DatabaseConnection);
+
+impl Deref for ReadDb {
+    type Target = DatabaseConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for ReadDb
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if !admit(&parts.extensions, Pool::Replica) {
+            return Err(busy(&parts.headers));
+        }
+
+        match parts.extensions.get::<DbPools>() {
+            Some(pools) => Ok(ReadDb(pools.read.clone())),
+            None => {
+                tracing::error!(
+                    "DbPools not found in request extensions. \
+                         Did you forget to call with_database?"
+                );
+                Err(ErrorBody::new("Database not configured", &parts.headers)
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
+
+/// Run `f` inside a transaction, committing on `Ok` and rolling back on `Err`
+///
+/// ```ignore
+/// let user = transaction(&db, async |txn| {
+///     let existing = Entity::find_by_id(key).one(txn).await?;
+///     if existing.is_some() {
+///         return Err(AppError::conflict("User already exists"));
+///     }
+///     Ok(active_model.insert(txn).await?)
+/// })
+/// .await?;
+/// ```
+pub async fn transaction<F, T, E>(db: &DatabaseConnection, f: F) -> Result<T, AppError>
+where
+    F: AsyncFnOnce(&DatabaseTransaction) -> Result<T, E>,
+    E: Into<AppError>,
+{
+    let txn = db.begin().await?;
+
+    match crate::events::hold(f(&txn)).await {
+        (Ok(value), events) => {
+            txn.commit().await?;
+            crate::events::release(events).await;
+            Ok(value)
+        }
+        // The handler's error is what matters, a failed rollback is only logged
+        (Err(e), events) => {
+            crate::events::discard(events);
+            if let Err(rollback) = txn.rollback().await {
+                tracing::warn!("database: rollback failed: {}", rollback);
+            }
+            Err(e.into())
+        }
+    }
+}
+
+/// Transaction opened for the request by [`WithTransaction`]
+///
+/// Committed when the handler's response is 2xx and rolled back otherwise,
+/// including when an `AppError` is returned, so handlers just use `?`.
+/// Pass `&*txn` wherever a connection is expected:
+///
+/// ```ignore
+/// async fn api_create_user(txn: Txn, JsonBody(body): JsonBody<CreateUser>) -> ApiResult<User> {
+///     let user = active_model(body).insert(&*txn).await?;
+///     audit::record(&*txn, &user).await?;
+///     Ok(Json(user.into()))
+/// }
+/// ```
+///
+/// Don't keep a clone past the handler, e.g. in a spawned task: the
+/// transaction can't be committed then and the request fails with 500.
+/// `Db` and `ReadDb` in the same handler use other connections that don't see
+/// uncommitted writes, and can wait forever on the single test connection
+#[derive(Debug, Clone)]
+pub struct Txn(Arc<DatabaseTransaction>);
+
+impl Deref for Txn {
+    type Target = DatabaseTransaction;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for Txn
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Txn>() {
+            Some(txn) => Ok(txn.clone()),
+            None => {
+                tracing::error!(
+                    "Txn not found in request extensions. \
+                         Did you forget to call with_transaction on the route?"
+                );
+                Err(ErrorBody::new("Transaction not started", &parts.headers)
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
+
+/// Run each request to a router's routes in a transaction, for the [`Txn`] extractor
+///
+/// Opt in on the routes that write, since the transaction holds a pooled
+/// connection from the start of the request until its response is ready.
+/// Handlers that wait on other services or run for long tie up connections and
+/// can exhaust the pool under load, so keep those calls outside these routes
+/// and size `database_pool.max_connections` for the concurrent writes expected.
+/// Streamed response bodies are produced after the commit
+///
+/// ```ignore
+/// let writes = OpenApiRouter::new().routes(routes!(api_create_user)).with_transaction();
+/// ```
+pub trait WithTransaction {
+    fn with_transaction(self) -> Self;
+}
+
+impl<S> WithTransaction for utoipa_axum::router::OpenApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn with_transaction(self) -> Self {
+        self.layer(axum::middleware::from_fn(transaction_per_request))
+    }
+}
+
+async fn transaction_per_request(
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    use axum::response::IntoResponse;
+
+    let Some(db) = request.extensions().get::<DatabaseConnection>().cloned() else {
+        tracing::error!(
+            "DatabaseConnection not found in request extensions. \
+                 Did you forget to call with_database?"
+        );
+        return ErrorBody::new("Database not configured", request.headers())
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    if !admit(request.extensions(), Pool::Primary) {
+        return busy(request.headers());
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => Arc::new(txn),
+        Err(e) => return AppError::from(e).into_response(),
+    };
+    request.extensions_mut().insert(Txn(txn.clone()));
+
+    let (response, events) = crate::events::hold(next.run(request)).await;
+
+    let Ok(txn) = Arc::try_unwrap(txn) else {
+        // Rolled back when the last clone drops
+        crate::events::discard(events);
+        tracing::error!("database: Txn outlived its request, rolled back instead of committed");
+        return AppError::internal("Transaction still in use").into_response();
+    };
+
+    if !response.status().is_success() {
+        crate::events::discard(events);
+        if let Err(e) = txn.rollback().await {
+            tracing::warn!("database: rollback failed: {}", e);
+        }
+        return response;
+    }
+
+    match txn.commit().await {
+        Ok(()) => {
+            crate::events::release(events).await;
+            response
+        }
+        Err(e) => {
+            crate::events::discard(events);
+            AppError::from(e).into_response()
+        }
+    }
+}
+
+/// Rows per `INSERT` in [`insert_batch`], keeping statements under Postgres' bind parameter limit
+pub const BATCH_CHUNK_ROWS: usize = 1000;
+
+/// Outcome of [`insert_batch`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchInsert {
+    pub inserted: u64,
+    /// Rows whose primary key already existed, e.g. redelivered events
+    pub skipped: u64,
+}
+
+/// Insert a batch of events in one transaction, skipping rows that already exist
+///
+/// Each event is converted with `to_model`, typically `ActiveModel::from_event`,
+/// and rows are written with multi-row inserts that ignore conflicts on the
+/// primary key, so for creation tracked entities a redelivered event is counted
+/// as skipped instead of failing the batch. Any other error rolls back the whole
+/// batch. `ActiveModelBehavior` hooks don't run for multi-row inserts.
+///
+/// ```ignore
+/// let result = insert_batch(&db, events, ActiveModel::from_event).await?;
+/// tracing::info!(inserted = result.inserted, skipped = result.skipped, "Users imported");
+/// ```
+pub async fn insert_batch<E, A, F>(
+    db: &DatabaseConnection,
+    events: Vec<E>,
+    to_model: F,
+) -> Result<BatchInsert, AppError>
+where
+    A: ActiveModelTrait + Send,
+    F: Fn(E) -> A,
+{
+    let total = events.len() as u64;
+    if total == 0 {
+        return Ok(BatchInsert::default());
+    }
+
+    let mut models: Vec<A> = events.into_iter().map(to_model).collect();
+
+    let inserted = transaction(db, async |txn| {
+        let mut inserted = 0;
+        while !models.is_empty() {
+            let rest = models.split_off(models.len().min(BATCH_CHUNK_ROWS));
+            let chunk = std::mem::replace(&mut models, rest);
+
+            let result = <A::Entity as EntityTrait>::insert_many(chunk)
+                .on_conflict_do_nothing()
+                .exec_without_returning(txn)
+                .await?;
+
+            if let TryInsertResult::Inserted(rows) = result {
+                inserted += rows;
+            }
+        }
+        Ok::<_, AppError>(inserted)
+    })
+    .await?;
+
+    Ok(BatchInsert {
+        inserted,
+        skipped: total - inserted,
+    })
+}
+
+/// Environment variable that must be `1` for `database_drop` to take effect
+pub const ALLOW_DROP_ENV: &str = "MICROKIT_ALLOW_DROP";
+
+/// Fail unless the environment confirms a `database_drop` from config
+///
+/// Dropping needs both keys, so a stray `database_drop: true` reaching a
+/// production config stops startup instead of deleting data
+fn confirm_drop(name: &str) -> Result<()> {
+    if std::env::var(ALLOW_DROP_ENV).as_deref() == Ok("1") {
+        tracing::warn!(
+            "database: dropping '{}', confirmed by {}",
+            name,
+            ALLOW_DROP_ENV
+        );
+        return Ok(());
+    }
+
+    tracing::error!(
+        "database: refusing to drop '{}': database_drop is true but {} is not set to 1",
+        name,
+        ALLOW_DROP_ENV
+    );
+    bail!(
+        "database_drop is true but {}=1 is not set. Dropping '{}' would delete all of its data; \
+         set {}=1 to confirm, or set database_drop to false",
+        ALLOW_DROP_ENV,
+        name,
+        ALLOW_DROP_ENV
+    )
+}
+
+/// Schema used when `database_schema` isn't set
+pub const DEFAULT_SCHEMA: &str = "public";
 
 pub async fn setup_database(
     url: &Option<String>,
     name: &Option<String>,
+    schema: &Option<String>,
     drop: &Option<bool>,
+    pool: &Option<DatabasePoolConfig>,
+    queries: &QueryTracing,
 ) -> Result<DatabaseConnection> {
     let url = match url {
         Some(url) => url,
@@ -16,6 +526,10 @@ pub async fn setup_database(
         None => bail!("database_name not set"),
     };
 
+    if let Some(true) = drop {
+        confirm_drop(name)?;
+    }
+
     tracing::info!("database: connecting to root database");
     let db = Database::connect(url).await?;
 
@@ -42,6 +556,303 @@ pub async fn setup_database(
     }
 
     tracing::info!("connecting to database '{}'", &name);
-    let url = format!("{}/{}", &url, &name);
-    Ok(Database::connect(&url).await?)
+    let db = connect(&format!("{}/{}", &url, &name), schema, pool, queries).await?;
+
+    if let Some(schema) = schema
+        && schema != DEFAULT_SCHEMA
+    {
+        // Migrations at startup create their tables in the schema, so it must exist
+        db.execute_unprepared(&format!(
+            "CREATE SCHEMA IF NOT EXISTS \"{}\";",
+            schema.replace('"', "\"\"")
+        ))
+        .await?;
+    }
+
+    Ok(db)
+}
+
+/// Level statements are logged at when `database_trace_queries` is on
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryLogLevel {
+    Error,
+    Warn,
+    Info,
+    #[default]
+    Debug,
+    Trace,
+}
+
+impl QueryLogLevel {
+    fn filter(self) -> log::LevelFilter {
+        match self {
+            QueryLogLevel::Error => log::LevelFilter::Error,
+            QueryLogLevel::Warn => log::LevelFilter::Warn,
+            QueryLogLevel::Info => log::LevelFilter::Info,
+            QueryLogLevel::Debug => log::LevelFilter::Debug,
+            QueryLogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// How connections log and trace their statements
+///
+/// Statements are logged by SQLx as `tracing` events under the `sqlx::query`
+/// target, inside the request span. With the `otel` feature each statement is
+/// also a child span of the request, carrying its parameterized SQL and duration.
+/// Unset, SQLx's own logging is left as it is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryTracing {
+    pub enabled: Option<bool>,
+    pub level: QueryLogLevel,
+    /// Statements taking longer are also logged at WARN
+    pub slow: Option<Duration>,
+}
+
+impl QueryTracing {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            enabled: config.database_trace_queries,
+            level: config.database_query_log_level.unwrap_or_default(),
+            slow: config.database_slow_query_ms.map(Duration::from_millis),
+        }
+    }
+
+    fn apply(&self, options: &mut ConnectOptions) {
+        match self.enabled {
+            Some(true) => {
+                options
+                    .sqlx_logging(true)
+                    .sqlx_logging_level(self.level.filter());
+                if let Some(slow) = self.slow {
+                    options.sqlx_slow_statements_logging_settings(log::LevelFilter::Warn, slow);
+                }
+            }
+            Some(false) => {
+                options.sqlx_logging(false);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Record each statement as a client span under the current request span
+///
+/// SeaORM reports a statement once it has finished, so the span is backdated
+/// by its duration
+#[cfg(feature = "otel")]
+fn trace_statement(info: &sea_orm::metric::Info<'_>) {
+    use opentelemetry::KeyValue;
+    use opentelemetry::trace::{Span, SpanKind, Status, Tracer};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let sql = info.statement.sql.as_str();
+    let operation = sql
+        .split_whitespace()
+        .next()
+        .unwrap_or("query")
+        .to_ascii_uppercase();
+    let system = match info.statement.db_backend {
+        sea_orm::DatabaseBackend::Postgres => "postgresql",
+        sea_orm::DatabaseBackend::Sqlite => "sqlite",
+        _ => "other_sql",
+    };
+
+    let end = std::time::SystemTime::now();
+    let parent = tracing::Span::current().context();
+    let tracer = opentelemetry::global::tracer("microkit");
+    let mut span = tracer
+        .span_builder(operation.clone())
+        .with_kind(SpanKind::Client)
+        .with_start_time(end - info.elapsed)
+        .with_attributes([
+            KeyValue::new("db.system.name", system),
+            KeyValue::new("db.operation.name", operation),
+            KeyValue::new("db.query.text", sql.to_string()),
+        ])
+        .start_with_context(&tracer, &parent);
+
+    if info.failed {
+        span.set_status(Status::error("query failed"));
+    }
+    span.end_with_timestamp(end);
+}
+
+/// Connect with `search_path` set to `schema`, so unqualified table names resolve there
+async fn connect(
+    url: &str,
+    schema: &Option<String>,
+    pool: &Option<DatabasePoolConfig>,
+    queries: &QueryTracing,
+) -> Result<DatabaseConnection> {
+    let mut options = ConnectOptions::new(url);
+    queries.apply(&mut options);
+    if let Some(schema) = schema {
+        options.set_schema_search_path(schema);
+    }
+
+    if let Some(pool) = pool {
+        if let Some(max) = pool.max_connections {
+            options.max_connections(max);
+        }
+        if let Some(min) = pool.min_connections {
+            options.min_connections(min);
+        }
+        if let Some(test) = pool.test_before_acquire {
+            options.test_before_acquire(test);
+        }
+        if let Some(idle) = pool.test_idle_secs {
+            options.test_before_acquire_if_idle_for(Duration::from_secs(idle));
+        }
+    }
+
+    #[allow(unused_mut)]
+    let mut db = Database::connect(options).await?;
+
+    #[cfg(feature = "otel")]
+    if queries.enabled == Some(true) {
+        db.set_metric_callback(trace_statement);
+    }
+
+    Ok(db)
+}
+
+/// Database used by `MicroKitBuilder::with_test_database`
+#[cfg(feature = "sqlite")]
+pub const TEST_DATABASE_URL: &str = "sqlite::memory:";
+
+/// Open a fresh in-memory SQLite database for tests
+///
+/// Each connection to `:memory:` is a separate database, so the pool holds
+/// exactly one connection and never recycles it; the data lives as long as the
+/// returned pools. Column types from the creation tracking macros map to SQLite,
+/// Postgres-only SQL in migrations or handlers won't run here
+#[cfg(feature = "sqlite")]
+pub async fn setup_test_database() -> Result<DbPools> {
+    let mut options = ConnectOptions::new(TEST_DATABASE_URL);
+    options
+        .max_connections(1)
+        .min_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None);
+
+    tracing::info!("database: using in-memory SQLite");
+    Ok(DbPools::primary(Database::connect(options).await?))
+}
+
+/// Connect to the primary, and to the read replica when `read_url` is set
+///
+/// The replica's database is expected to exist, it's never created or dropped
+pub async fn setup_pools(config: &crate::config::Config) -> Result<DbPools> {
+    let write = setup_database(
+        &config.database_url,
+        &config.database_name,
+        &config.database_schema,
+        &config.database_drop,
+        &config.database_pool,
+        &QueryTracing::from_config(config),
+    )
+    .await?;
+
+    let Some(read_url) = &config.database_read_url else {
+        return Ok(DbPools::primary(write));
+    };
+
+    let name = config.database_name.as_deref().unwrap_or_default();
+    tracing::info!("database: connecting to read replica for '{}'", name);
+    let read = connect(
+        &format!("{}/{}", read_url, name),
+        &config.database_schema,
+        &config.database_pool,
+        &QueryTracing::from_config(config),
+    )
+    .await?;
+
+    Ok(DbPools { write, read })
+}
+
+/// Result of the background pings started with `DbHealth::monitor`
+///
+/// The pool replaces dead connections as it hands them out; the pings notice an
+/// outage while the service is idle, so readiness can report it
+#[derive(Debug, Clone)]
+pub struct DbHealth(Arc<HealthState>);
+
+#[derive(Debug)]
+struct HealthState {
+    failures: AtomicU32,
+    unhealthy_after: u32,
+    last_error: Mutex<Option<String>>,
+}
+
+impl DbHealth {
+    /// Ping `db` every `interval` until the process exits
+    pub fn monitor(db: DatabaseConnection, interval: Duration, unhealthy_after: u32) -> Self {
+        let health = Self(Arc::new(HealthState {
+            failures: AtomicU32::new(0),
+            unhealthy_after: unhealthy_after.max(1),
+            last_error: Mutex::new(None),
+        }));
+
+        let state = health.0.clone();
+        tokio::spawn(async move {
+            #[cfg(feature = "otel")]
+            let failed_pings = opentelemetry::global::meter("microkit")
+                .u64_counter("db.client.ping.failures")
+                .with_description("Failed background pings of the primary database")
+                .build();
+
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                match db.ping().await {
+                    Ok(()) => {
+                        let failures = state.failures.swap(0, Ordering::Relaxed);
+                        if failures > 0 {
+                            tracing::info!("database: ping recovered after {} failures", failures);
+                        }
+                    }
+                    Err(e) => {
+                        #[cfg(feature = "otel")]
+                        failed_pings.add(1, &[]);
+                        let failures = state.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                        if failures == state.unhealthy_after {
+                            tracing::error!(
+                                "database: reporting unready after {} failed pings: {}",
+                                failures,
+                                e
+                            );
+                        } else {
+                            tracing::warn!("database: ping failed ({} in a row): {}", failures, e);
+                        }
+                        *state.last_error.lock().unwrap_or_else(|e| e.into_inner()) =
+                            Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        health
+    }
+
+    /// `Err` with the last ping error once `unhealthy_after` pings in a row failed
+    pub fn status(&self) -> Result<(), String> {
+        let failures = self.0.failures.load(Ordering::Relaxed);
+        if failures < self.0.unhealthy_after {
+            return Ok(());
+        }
+
+        let error = self
+            .0
+            .last_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_default();
+        Err(format!("{} failed pings in a row: {}", failures, error))
+    }
 }
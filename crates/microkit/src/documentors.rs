@@ -1,36 +1,142 @@
+use anyhow::{Context, Result, bail};
 use axum::Router;
-use std::net::SocketAddr;
+use axum::http::header;
+use axum::routing::get;
 use utoipa::openapi::OpenApi;
 
-#[cfg(feature = "auth")]
+#[cfg(all(
+    any(
+        feature = "swagger",
+        feature = "redoc",
+        feature = "rapidoc",
+        feature = "scalar"
+    ),
+    feature = "auth"
+))]
 use crate::config::AuthConfigYaml;
 
-#[cfg(feature = "auth")]
+/// Documentor features compiled into this build
+pub const COMPILED: &[&str] = &[
+    #[cfg(feature = "swagger")]
+    "swagger",
+    #[cfg(feature = "redoc")]
+    "redoc",
+    #[cfg(feature = "rapidoc")]
+    "rapidoc",
+    #[cfg(feature = "scalar")]
+    "scalar",
+];
+
+/// Warn when the docs config asks for something the compiled features don't match
+///
+/// Only explicit settings are checked, so the hardened profile hiding the
+/// documentors by default stays quiet
+pub(crate) fn check_features(config: &crate::config::Config) {
+    let docs = config.docs.as_ref();
+    let enabled = docs.and_then(|docs| docs.enabled);
+    let spec_only = docs.and_then(|docs| docs.spec_only);
+
+    if COMPILED.is_empty() {
+        if enabled == Some(true) && config.documentors_enabled() {
+            tracing::warn!(
+                "docs: docs.enabled is set but no documentor is compiled in, enable one of \
+                 microkit's swagger, redoc, rapidoc or scalar features. Only the OpenAPI \
+                 document is served, at {}",
+                config.spec_path()
+            );
+        }
+    } else if !config.documentors_enabled() {
+        let setting = if enabled == Some(false) {
+            "docs.enabled is false"
+        } else if spec_only == Some(true) {
+            "docs.spec_only is true"
+        } else {
+            return;
+        };
+        tracing::warn!(
+            "docs: {} compiled in but not served as {}, drop the feature to trim the binary",
+            COMPILED.join(", "),
+            setting
+        );
+    }
+}
+
+/// Serve the OpenAPI document as JSON at `spec_path` and as YAML beside it
+///
+/// Mounted whenever docs are enabled, with or without a documentor feature
+pub fn spec(
+    router: Router,
+    api: &OpenApi,
+    base_url: Option<&str>,
+    spec_path: &str,
+) -> Result<(Router, Vec<String>)> {
+    if !spec_path.starts_with('/') {
+        bail!("docs: spec_path '{}' must start with '/'", spec_path);
+    }
+    let yaml_path = format!(
+        "{}.yaml",
+        spec_path.strip_suffix(".json").unwrap_or(spec_path)
+    );
+
+    let json = api
+        .to_json()
+        .context("docs: failed to serialize OpenAPI document as JSON")?;
+    let yaml = serde_yaml_ng::to_string(api)
+        .context("docs: failed to serialize OpenAPI document as YAML")?;
+
+    let router = router
+        .route(
+            spec_path,
+            get(move || async move { ([(header::CONTENT_TYPE, "application/json")], json) }),
+        )
+        .route(
+            &yaml_path,
+            get(move || async move { ([(header::CONTENT_TYPE, "application/yaml")], yaml) }),
+        );
+
+    if let Some(base_url) = base_url {
+        tracing::info!("openapi: {}{}", base_url, spec_path);
+    }
+
+    Ok((router, vec![spec_path.to_string(), yaml_path]))
+}
+
+#[cfg(all(
+    any(
+        feature = "swagger",
+        feature = "redoc",
+        feature = "rapidoc",
+        feature = "scalar"
+    ),
+    feature = "auth"
+))]
+#[allow(unused_variables)]
 pub fn documentors(
     router: Router,
     api: &OpenApi,
-    local_addr: &SocketAddr,
+    base_url: Option<&str>,
+    spec_path: &str,
     auth_config: Option<&AuthConfigYaml>,
-) -> Router {
+) -> (Router, Vec<&'static str>) {
     let mut router = router;
-    let mut documentors: Vec<&str> = Vec::with_capacity(4);
+    let mut documentors: Vec<&'static str> = Vec::with_capacity(4);
 
     // Documentation endpoints
     {
-        #[allow(unused_variables)]
-        let openapi_json = "/api-docs/openapi.json";
-
         #[cfg(feature = "swagger")]
         {
-            use utoipa_swagger_ui::{SwaggerUi, oauth};
+            use utoipa_swagger_ui::{Config, SwaggerUi, oauth};
             let endpoint = "/swagger";
 
-            let mut swagger_ui = SwaggerUi::new(endpoint).url(openapi_json, api.clone());
+            // Points at the document served by `spec` rather than serving its own copy
+            let mut swagger_ui =
+                SwaggerUi::new(endpoint).config(Config::new([spec_path.to_string()]));
 
             // Configure OAuth2 if auth is available
             if let Some(auth) = auth_config {
+                // Authorization code with PKCE, endpoints come from the oidc discovery document
                 let mut oauth_config =
-                    oauth::Config::new().use_pkce_with_authorization_code_grant(false);
+                    oauth::Config::new().use_pkce_with_authorization_code_grant(true);
 
                 if let Some(client_id) = &auth.client_id {
                     oauth_config = oauth_config.client_id(client_id);
@@ -63,7 +169,7 @@ pub fn documentors(
         {
             use utoipa_rapidoc::RapiDoc;
             let endpoint = "/rapidoc";
-            router = router.merge(RapiDoc::new(openapi_json).path(endpoint));
+            router = router.merge(RapiDoc::new(spec_path.to_string()).path(endpoint));
             documentors.push(endpoint);
         }
 
@@ -77,29 +183,42 @@ pub fn documentors(
     }
 
     // Documentation viewers
-    for documentor in documentors {
-        let name = &documentor[1..];
-        tracing::info!("{}: http://{}/{}", name, local_addr, name);
+    if let Some(base_url) = base_url {
+        for documentor in &documentors {
+            tracing::info!("{}: {}{}", &documentor[1..], base_url, documentor);
+        }
     }
 
-    router
+    (router, documentors)
 }
 
-#[cfg(not(feature = "auth"))]
-pub fn documentors(router: Router, api: &OpenApi, local_addr: &SocketAddr) -> Router {
+#[cfg(all(
+    any(
+        feature = "swagger",
+        feature = "redoc",
+        feature = "rapidoc",
+        feature = "scalar"
+    ),
+    not(feature = "auth")
+))]
+#[allow(unused_variables)]
+pub fn documentors(
+    router: Router,
+    api: &OpenApi,
+    base_url: Option<&str>,
+    spec_path: &str,
+) -> (Router, Vec<&'static str>) {
     let mut router = router;
-    let mut documentors: Vec<&str> = Vec::with_capacity(4);
+    let mut documentors: Vec<&'static str> = Vec::with_capacity(4);
 
     // Documentation endpoints
     {
-        #[allow(unused_variables)]
-        let openapi_json = "/api-docs/openapi.json";
-
         #[cfg(feature = "swagger")]
         {
-            use utoipa_swagger_ui::SwaggerUi;
+            use utoipa_swagger_ui::{Config, SwaggerUi};
             let endpoint = "/swagger";
-            router = router.merge(SwaggerUi::new(endpoint).url(openapi_json, api.clone()));
+            router =
+                router.merge(SwaggerUi::new(endpoint).config(Config::new([spec_path.to_string()])));
             documentors.push(endpoint);
         }
 
@@ -115,7 +234,7 @@ pub fn documentors(router: Router, api: &OpenApi, local_addr: &SocketAddr) -> Ro
         {
             use utoipa_rapidoc::RapiDoc;
             let endpoint = "/rapidoc";
-            router = router.merge(RapiDoc::new(openapi_json).path(endpoint));
+            router = router.merge(RapiDoc::new(spec_path.to_string()).path(endpoint));
             documentors.push(endpoint);
         }
 
@@ -129,10 +248,11 @@ pub fn documentors(router: Router, api: &OpenApi, local_addr: &SocketAddr) -> Ro
     }
 
     // Documentation viewers
-    for documentor in documentors {
-        let name = &documentor[1..];
-        tracing::info!("{}: http://{}/{}", name, local_addr, name);
+    if let Some(base_url) = base_url {
+        for documentor in &documentors {
+            tracing::info!("{}: {}{}", &documentor[1..], base_url, documentor);
+        }
     }
 
-    router
+    (router, documentors)
 }
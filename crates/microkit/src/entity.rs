@@ -1,16 +1,243 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Crockford base32, as used by ULIDs
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Base62, as used by KSUIDs
+const BASE62: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// KSUID timestamps count seconds from this Unix time
+const KSUID_EPOCH: i64 = 1_400_000_000;
+
 /// Trait for entities that track creation metadata with composite keys
 pub trait CreationTracking {
     /// Get the creation system (service name)
     fn creation_system(&self) -> &str;
 
     /// Get the creation key (UUID or external system identifier)
-    fn creation_key(&self) -> &str;
+    ///
+    /// Borrowed for `String` keys, formatted for `Uuid` keys
+    fn creation_key(&self) -> Cow<'_, str>;
+
+    /// Limits checked on incoming events by [`validate_creation_fields`]
+    const KEY_CONSTRAINTS: KeyConstraints = KeyConstraints::DEFAULT;
+}
+
+/// Characters allowed in creation keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCharset {
+    /// Anything but control characters
+    Any,
+    /// ASCII letters, digits and `-_.:~`, safe in URLs and log lines
+    UrlSafe,
+    /// ASCII letters and digits
+    Alphanumeric,
+}
+
+impl KeyCharset {
+    fn allows(&self, c: char) -> bool {
+        match self {
+            KeyCharset::Any => !c.is_control(),
+            KeyCharset::UrlSafe => c.is_ascii_alphanumeric() || "-_.:~".contains(c),
+            KeyCharset::Alphanumeric => c.is_ascii_alphanumeric(),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            KeyCharset::Any => "anything but control characters",
+            KeyCharset::UrlSafe => "letters, digits and -_.:~",
+            KeyCharset::Alphanumeric => "letters and digits",
+        }
+    }
+}
+
+/// Limits on the creation tracking fields of an entity's incoming events
+///
+/// Set with `#[creation_tracking(max_key_len = 64, key_charset = "url_safe")]`
+/// beside `#[derive(CreationTracked)]`. `uuid` follows the key column's type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyConstraints {
+    /// Longest creation key and creation system, in characters
+    pub max_len: usize,
+    pub charset: KeyCharset,
+    /// The key must be a UUID, for `Uuid` key columns
+    pub uuid: bool,
+}
+
+impl KeyConstraints {
+    pub const DEFAULT: Self = Self {
+        max_len: 255,
+        charset: KeyCharset::Any,
+        uuid: false,
+    };
+}
+
+/// Check an event's creation tracking fields against `E`'s [`KeyConstraints`]
+///
+/// Fails with a 400 naming the field and the broken rule, so bad events are
+/// rejected before they reach the database
+///
+/// ```ignore
+/// validate_creation_fields::<Model>(&event.creation_system, &event.creation_key)?;
+/// ```
+pub fn validate_creation_fields<E: CreationTracking>(
+    system: &str,
+    key: &str,
+) -> Result<(), crate::error::AppError> {
+    let constraints = E::KEY_CONSTRAINTS;
+    let invalid = |field: &str, value: &str, reason: String| {
+        // Oversized values are cut short so they don't flood the error and logs
+        let shown: String = value.chars().take(64).collect();
+        let ellipsis = if shown.len() < value.len() { "..." } else { "" };
+        crate::error::AppError::bad_request(format!(
+            "Invalid {} '{}{}': {}",
+            field,
+            shown.escape_debug(),
+            ellipsis,
+            reason
+        ))
+    };
+
+    for (field, value) in [("creation_system", system), ("creation_key", key)] {
+        if value.is_empty() {
+            return Err(invalid(field, value, "must not be empty".to_string()));
+        }
+        let len = value.chars().count();
+        if len > constraints.max_len {
+            return Err(invalid(
+                field,
+                value,
+                format!(
+                    "{} characters, at most {} allowed",
+                    len, constraints.max_len
+                ),
+            ));
+        }
+        if let Some(c) = value.chars().find(|&c| !KeyCharset::Any.allows(c)) {
+            return Err(invalid(field, value, format!("contains {:?}", c)));
+        }
+    }
+
+    if let Some(c) = key.chars().find(|&c| !constraints.charset.allows(c)) {
+        return Err(invalid(
+            "creation_key",
+            key,
+            format!(
+                "contains {:?}, only {} allowed",
+                c,
+                constraints.charset.describe()
+            ),
+        ));
+    }
+    if constraints.uuid
+        && let Err(e) = uuid::Uuid::parse_str(key)
+    {
+        return Err(invalid("creation_key", key, e.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Generates creation keys for records created through the API
+///
+/// Implement it to plug in your own scheme, or pick a built-in with
+/// `creation_key_strategy` in config and call [`new_creation_key`]
+pub trait KeyStrategy {
+    fn generate(&self) -> String;
+}
+
+/// Built-in creation key strategies
+///
+/// The time-ordered ones keep new rows together at the end of the primary key
+/// index, which helps insert throughput on busy tables. Timestamps come from
+/// [`clock::now`](crate::clock::now), so a frozen clock freezes the prefix
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CreationKeyStrategy {
+    /// Random UUID, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    #[default]
+    UuidV4,
+    /// Time-ordered UUID, also fits native `uuid` key columns
+    UuidV7,
+    /// 26 Crockford base32 characters, ordered to the millisecond
+    Ulid,
+    /// 27 base62 characters, ordered to the second
+    Ksuid,
+}
+
+impl KeyStrategy for CreationKeyStrategy {
+    fn generate(&self) -> String {
+        let now = crate::clock::now();
+
+        match self {
+            CreationKeyStrategy::UuidV4 => uuid::Uuid::new_v4().to_string(),
+            CreationKeyStrategy::UuidV7 => {
+                let timestamp = uuid::Timestamp::from_unix(
+                    uuid::NoContext,
+                    now.timestamp().max(0) as u64,
+                    now.timestamp_subsec_nanos(),
+                );
+                uuid::Uuid::new_v7(timestamp).to_string()
+            }
+            CreationKeyStrategy::Ulid => {
+                let millis = now.timestamp_millis().max(0) as u128 & ((1 << 48) - 1);
+                let random = rand::random::<u128>() & ((1 << 80) - 1);
+                let value = (millis << 80) | random;
+                (0..26)
+                    .rev()
+                    .map(|i| CROCKFORD[((value >> (i * 5)) & 0x1f) as usize] as char)
+                    .collect()
+            }
+            CreationKeyStrategy::Ksuid => {
+                let seconds = (now.timestamp() - KSUID_EPOCH).clamp(0, u32::MAX as i64) as u32;
+                let mut bytes = [0u8; 20];
+                bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+                bytes[4..].copy_from_slice(&rand::random::<[u8; 16]>());
+                base62(bytes)
+            }
+        }
+    }
+}
+
+/// Fixed width base62 of a 160-bit big-endian number, 27 characters
+fn base62(mut bytes: [u8; 20]) -> String {
+    let mut digits = [b'0'; 27];
+
+    for digit in digits.iter_mut().rev() {
+        let mut remainder = 0u32;
+        for byte in bytes.iter_mut() {
+            let value = (remainder << 8) | *byte as u32;
+            *byte = (value / 62) as u8;
+            remainder = value % 62;
+        }
+        *digit = BASE62[remainder as usize];
+    }
+
+    digits.iter().map(|&digit| digit as char).collect()
+}
+
+/// A new creation key from `creation_key_strategy` in config (default: UUIDv4)
+pub fn new_creation_key(config: &crate::config::Config) -> String {
+    config.creation_key_strategy.unwrap_or_default().generate()
+}
+
+/// Parse an event's creation key for entities with a `Uuid` key column
+///
+/// Malformed keys are a 400, the event can never be stored
+#[cfg(feature = "database")]
+pub fn parse_creation_key(key: &str) -> Result<sea_orm::prelude::Uuid, crate::error::AppError> {
+    sea_orm::prelude::Uuid::parse_str(key).map_err(|e| {
+        crate::error::AppError::bad_request(format!("Invalid creation_key '{}': {}", key, e))
+    })
 }
 
 /// Helper trait for creating ActiveModels from API requests
 ///
 /// This is automatically used by entities with creation tracking.
-/// It auto-generates creation_system from config and creation_key as UUID.
+/// It auto-generates creation_system from config and creation_key with
+/// [`new_creation_key`].
 pub trait FromApiRequest<T> {
     type Error;
 
@@ -18,7 +245,7 @@ pub trait FromApiRequest<T> {
     ///
     /// Automatically sets:
     /// - creation_system from config.service_name
-    /// - creation_key as a new UUID
+    /// - creation_key from the configured `creation_key_strategy`
     fn from_api(config: &crate::config::Config, payload: T) -> Result<Self, Self::Error>
     where
         Self: Sized;
@@ -40,43 +267,84 @@ pub trait FromEventContract<T> {
 }
 
 /// Macro to add creation tracking fields to SeaORM entities
+///
+/// Field names can be overridden for existing schemas, pair this with
+/// `#[creation_tracking(system = "...", key = "...")]` on the model. A trailing
+/// `uuid` makes the key a `Uuid` instead of a `String`:
+///
+/// ```ignore
+/// creation_tracking_fields!(system = created_by, key = external_id);
+/// creation_tracking_fields!(uuid);
+/// ```
 #[macro_export]
 macro_rules! creation_tracking_fields {
     () => {
+        $crate::creation_tracking_fields!(@fields creation_system, creation_key, String)
+    };
+    (uuid) => {
+        $crate::creation_tracking_fields!(@fields creation_system, creation_key, sea_orm::prelude::Uuid)
+    };
+    (system = $system:ident, key = $key:ident $(,)?) => {
+        $crate::creation_tracking_fields!(@fields $system, $key, String)
+    };
+    (system = $system:ident, key = $key:ident, uuid $(,)?) => {
+        $crate::creation_tracking_fields!(@fields $system, $key, sea_orm::prelude::Uuid)
+    };
+    (@fields $system:ident, $key:ident, $key_type:ty) => {
         /// System that created this record (e.g. service name)
         #[sea_orm(primary_key, auto_increment = false)]
-        pub creation_system: String,
+        pub $system: String,
 
         /// Unique identifier - UUID for API, message ID for events
         #[sea_orm(primary_key, auto_increment = false)]
-        pub creation_key: String,
+        pub $key: $key_type,
     };
 }
 
 /// Macro to add creation tracking columns to migrations
+///
+/// Column names can be overridden for existing schemas, and a trailing `uuid`
+/// creates a native `uuid` key column:
+///
+/// ```ignore
+/// creation_tracking_columns!(system = "created_by", key = "external_id")
+/// creation_tracking_columns!(uuid)
+/// ```
 #[macro_export]
 macro_rules! creation_tracking_columns {
     () => {
+        $crate::creation_tracking_columns!(@columns "creation_system", "creation_key", string)
+    };
+    (uuid) => {
+        $crate::creation_tracking_columns!(@columns "creation_system", "creation_key", uuid)
+    };
+    (system = $system:expr, key = $key:expr $(,)?) => {
+        $crate::creation_tracking_columns!(@columns $system, $key, string)
+    };
+    (system = $system:expr, key = $key:expr, uuid $(,)?) => {
+        $crate::creation_tracking_columns!(@columns $system, $key, uuid)
+    };
+    (@columns $system:expr, $key:expr, $key_type:ident) => {
         |table: &mut sea_orm_migration::prelude::TableCreateStatement| {
             table
                 .col(
                     sea_orm_migration::prelude::ColumnDef::new(
-                        sea_orm_migration::prelude::Alias::new("creation_system"),
+                        sea_orm_migration::prelude::Alias::new($system),
                     )
                     .string()
                     .not_null(),
                 )
                 .col(
                     sea_orm_migration::prelude::ColumnDef::new(
-                        sea_orm_migration::prelude::Alias::new("creation_key"),
+                        sea_orm_migration::prelude::Alias::new($key),
                     )
-                    .string()
+                    .$key_type()
                     .not_null(),
                 )
                 .primary_key(
                     sea_orm_migration::prelude::Index::create()
-                        .col(sea_orm_migration::prelude::Alias::new("creation_system"))
-                        .col(sea_orm_migration::prelude::Alias::new("creation_key")),
+                        .col(sea_orm_migration::prelude::Alias::new($system))
+                        .col(sea_orm_migration::prelude::Alias::new($key)),
                 )
         }
     };
@@ -0,0 +1,274 @@
+use crate::error::request_id;
+use axum::Json;
+use axum::body::{Body, HttpBody, to_bytes};
+use axum::extract::Request;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use utoipa::openapi::{self, ObjectBuilder, Ref, RefOr, Schema, path::Operation};
+use utoipa::{PartialSchema, ToSchema};
+
+/// Responses larger than this are passed through rather than buffered
+const MAX_ENVELOPE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Fields of a `Paginated` body, lifted into `meta` rather than wrapped twice
+const PAGE_FIELDS: [&str; 3] = ["items", "total", "next_cursor"];
+
+/// Fields of an [`Envelope`] body
+const ENVELOPE_FIELDS: [&str; 2] = ["data", "meta"];
+
+/// Response body wrapped as `{ "data": ..., "meta": { ... } }`
+///
+/// Return it from a handler to wrap one response, or set `response_envelope` to
+/// wrap every successful JSON response. Responses that are already an `Envelope`
+/// aren't wrapped again
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Envelope<T> {
+    pub data: T,
+    #[serde(default)]
+    pub meta: EnvelopeMeta,
+}
+
+/// Metadata of an [`Envelope`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct EnvelopeMeta {
+    /// Request ID taken from the `x-request-id` header, if present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Total number of items across all pages, for paginated responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    /// Offset of the next page, for paginated responses that have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<u64>,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data,
+            meta: EnvelopeMeta::default(),
+        }
+    }
+}
+
+/// A page's items as `data`, with `total` and `next_cursor` in `meta`
+#[cfg(feature = "database")]
+impl<T> From<crate::pagination::Paginated<T>> for Envelope<Vec<T>> {
+    fn from(page: crate::pagination::Paginated<T>) -> Self {
+        Self {
+            data: page.items,
+            meta: EnvelopeMeta {
+                total: Some(page.total),
+                next_cursor: page.next_cursor,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Marks a response that is already enveloped
+#[derive(Debug, Clone, Copy)]
+struct Enveloped;
+
+impl<T: Serialize> IntoResponse for Envelope<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self).into_response();
+        response.extensions_mut().insert(Enveloped);
+        response
+    }
+}
+
+/// Wrap successful JSON responses in an [`Envelope`], installed by `response_envelope`
+///
+/// Error responses keep the configured error shape, and a `Paginated` body has its
+/// items lifted into `data` with `total` and `next_cursor` in `meta`. CloudEvent
+/// deliveries are left alone since Dapr reads their responses, as are streamed
+/// bodies and those over 16 MiB
+pub async fn wrap(req: Request, next: Next) -> Response {
+    let is_event = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/cloudevents"));
+    let request_id = request_id(req.headers());
+
+    let response = next.run(req).await;
+
+    if is_event
+        || !response.status().is_success()
+        || response.status() == StatusCode::NO_CONTENT
+        || response.extensions().get::<Enveloped>().is_some()
+        || !is_json(response.headers())
+        // Streamed bodies have no exact size and are never buffered
+        || response
+            .body()
+            .size_hint()
+            .exact()
+            .is_none_or(|size| size > MAX_ENVELOPE_BYTES as u64)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, MAX_ENVELOPE_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("envelope: couldn't buffer the response body: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let mut envelope = match value {
+        Value::Object(mut page) if is_page(&page) => Envelope {
+            data: page.remove("items").unwrap_or_default(),
+            meta: EnvelopeMeta {
+                total: page.get("total").and_then(Value::as_u64),
+                next_cursor: page.get("next_cursor").and_then(Value::as_u64),
+                ..Default::default()
+            },
+        },
+        value => Envelope::new(value),
+    };
+    envelope.meta.request_id = request_id;
+
+    match serde_json::to_vec(&envelope) {
+        Ok(body) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.extensions.insert(Enveloped);
+            Response::from_parts(parts, Body::from(body))
+        }
+        Err(e) => {
+            tracing::error!("envelope: couldn't serialize the response: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+fn is_page(object: &Map<String, Value>) -> bool {
+    object.len() == PAGE_FIELDS.len() && PAGE_FIELDS.iter().all(|field| object.contains_key(*field))
+}
+
+/// Document the wrapping done by `wrap` on every successful JSON response
+///
+/// Schemas of `Paginated` bodies are unwrapped the same way, to their items
+pub fn apply_schemas(api: &mut openapi::OpenApi) {
+    let components = api.components.get_or_insert_with(Default::default);
+    components
+        .schemas
+        .entry("EnvelopeMeta".to_string())
+        .or_insert_with(<EnvelopeMeta as PartialSchema>::schema);
+    let schemas = components.schemas.clone();
+
+    for item in api.paths.paths.values_mut() {
+        for operation in [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+            &mut item.patch,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            wrap_operation(operation, &schemas);
+        }
+    }
+}
+
+fn wrap_operation(operation: &mut Operation, schemas: &BTreeMap<String, RefOr<Schema>>) {
+    for (status, response) in operation.responses.responses.iter_mut() {
+        if !status.starts_with('2') {
+            continue;
+        }
+        let RefOr::T(response) = response else {
+            continue;
+        };
+        let Some(content) = response.content.get_mut("application/json") else {
+            continue;
+        };
+        let Some(schema) = content.schema.take() else {
+            continue;
+        };
+        // Handlers returning `Envelope` already document the wrapped shape
+        if has_fields(&schema, schemas, &ENVELOPE_FIELDS) {
+            content.schema = Some(schema);
+            continue;
+        }
+        // Examples show the bare body, which no longer matches
+        content.example = None;
+        content.examples.clear();
+
+        let data = page_items(&schema, schemas).unwrap_or(schema);
+        let wrapped = ObjectBuilder::new()
+            .property("data", data)
+            .required("data")
+            .property("meta", Ref::from_schema_name("EnvelopeMeta"))
+            .required("meta")
+            .build();
+
+        content.schema = Some(wrapped.into());
+    }
+}
+
+/// The `items` schema when `schema` is a `Paginated` object
+fn page_items(
+    schema: &RefOr<Schema>,
+    schemas: &BTreeMap<String, RefOr<Schema>>,
+) -> Option<RefOr<Schema>> {
+    if !has_fields(schema, schemas, &PAGE_FIELDS) {
+        return None;
+    }
+    match resolve(schema, schemas)? {
+        Schema::Object(object) => object.properties.get("items").cloned(),
+        _ => None,
+    }
+}
+
+/// Whether `schema` is an object with exactly `fields` as its properties
+fn has_fields(
+    schema: &RefOr<Schema>,
+    schemas: &BTreeMap<String, RefOr<Schema>>,
+    fields: &[&str],
+) -> bool {
+    match resolve(schema, schemas) {
+        Some(Schema::Object(object)) => {
+            object.properties.len() == fields.len()
+                && fields
+                    .iter()
+                    .all(|field| object.properties.contains_key(*field))
+        }
+        _ => false,
+    }
+}
+
+/// `schema` itself, or the component it references
+fn resolve<'a>(
+    schema: &'a RefOr<Schema>,
+    schemas: &'a BTreeMap<String, RefOr<Schema>>,
+) -> Option<&'a Schema> {
+    match schema {
+        RefOr::Ref(reference) => {
+            let name = reference.ref_location.rsplit('/').next()?;
+            match schemas.get(name)? {
+                RefOr::T(schema) => Some(schema),
+                RefOr::Ref(_) => None,
+            }
+        }
+        RefOr::T(schema) => Some(schema),
+    }
+}
@@ -0,0 +1,418 @@
+use axum::Json;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::openapi::{self, Ref, RefOr};
+use utoipa::{PartialSchema, ToSchema};
+
+/// Header used to correlate a request with its error response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Content type of RFC 7807 problem details
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Message sent to clients for 500s, the details are only logged
+pub const INTERNAL_ERROR_MESSAGE: &str = "Internal server error";
+
+tokio::task_local! {
+    /// The request being handled, for responses built without its headers or the service
+    static REQUEST: RequestScope;
+}
+
+/// What error responses need from the request and the service handling it
+#[derive(Clone)]
+struct RequestScope {
+    request_id: Option<String>,
+    renderer: Arc<dyn ErrorRenderer>,
+}
+
+/// JSON error body returned by framework-generated error responses
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// Human readable error message
+    pub error: String,
+    /// Request ID taken from the `x-request-id` header, if present
+    pub request_id: Option<String>,
+    /// Request body fields that failed to parse
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+    /// Seconds to wait before retrying, the same as the `Retry-After` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_seconds: Option<u64>,
+}
+
+/// A request body field that failed to parse
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FieldError {
+    /// Path to the field, e.g. `address.city` or `items[0].quantity`
+    pub field: String,
+    /// Why the field was rejected, e.g. "missing field `name`"
+    pub message: String,
+}
+
+impl ErrorBody {
+    pub fn new(error: impl Into<String>, headers: &HeaderMap) -> Self {
+        Self {
+            error: error.into(),
+            request_id: request_id(headers),
+            fields: Vec::new(),
+            retry_after_seconds: None,
+        }
+    }
+
+    pub fn with_fields(mut self, fields: Vec<FieldError>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Pair the body with a status code, rendered in the configured error format
+    pub fn into_response_with(self, status: StatusCode) -> Response {
+        renderer().render(status, self)
+    }
+
+    /// Pair the body with a throttling status, telling the client when to retry
+    ///
+    /// Sets `Retry-After` and `retry_after_seconds` to the same number of seconds
+    pub fn into_retry_response(mut self, status: StatusCode, retry_after: u64) -> Response {
+        self.retry_after_seconds = Some(retry_after);
+        let mut response = self.into_response_with(status);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, retry_after.into());
+        response
+    }
+}
+
+/// Turns error bodies into responses, so services can choose the shape of their errors
+///
+/// Every error MicroKit produces goes through the renderer: [`AppError`], the
+/// 404/405 fallbacks, extractor rejections, rate limiting, load shedding and
+/// open circuits. Pick one with `error_format` in config, or install your own
+/// with `with_error_renderer` on the builder:
+///
+/// ```ignore
+/// struct Envelope;
+///
+/// impl ErrorRenderer for Envelope {
+///     fn render(&self, status: StatusCode, body: ErrorBody) -> Response {
+///         (status, Json(json!({ "ok": false, "message": body.error }))).into_response()
+///     }
+/// }
+///
+/// MicroKit::builder().await?.with_error_renderer(Envelope)
+/// ```
+pub trait ErrorRenderer: Send + Sync + 'static {
+    /// Build the response for an error with `status`
+    fn render(&self, status: StatusCode, body: ErrorBody) -> Response;
+
+    /// Describe the rendered shape in the OpenAPI document, where operations
+    /// document their errors as `ErrorBody` (default: left as `ErrorBody`)
+    fn document(&self, _api: &mut openapi::OpenApi) {}
+}
+
+/// Error shape chosen with `error_format`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorFormat {
+    /// [`ErrorBody`] as JSON, `{ "error": "...", "request_id": "..." }`
+    #[default]
+    Default,
+    /// RFC 7807 [`ProblemDetails`] as `application/problem+json`
+    Problem,
+}
+
+impl ErrorFormat {
+    pub fn renderer(self) -> Box<dyn ErrorRenderer> {
+        match self {
+            ErrorFormat::Default => Box::new(DefaultErrorRenderer),
+            ErrorFormat::Problem => Box::new(ProblemRenderer),
+        }
+    }
+}
+
+/// Renders [`ErrorBody`] as JSON, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultErrorRenderer;
+
+impl ErrorRenderer for DefaultErrorRenderer {
+    fn render(&self, status: StatusCode, body: ErrorBody) -> Response {
+        (status, Json(body)).into_response()
+    }
+}
+
+/// RFC 7807 problem details, rendered by [`ProblemRenderer`]
+///
+/// MicroKit's own fields are carried as extension members under the same names
+/// as in [`ErrorBody`]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProblemDetails {
+    /// URI identifying the problem type, `about:blank` when the status says it all
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the problem type, the status code's reason phrase
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Human readable explanation of this occurrence
+    pub detail: String,
+    /// Request ID taken from the `x-request-id` header, if present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Request body fields that failed to parse
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+    /// Seconds to wait before retrying, the same as the `Retry-After` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_seconds: Option<u64>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, body: ErrorBody) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: body.error,
+            request_id: body.request_id,
+            fields: body.fields,
+            retry_after_seconds: body.retry_after_seconds,
+        }
+    }
+}
+
+/// Renders errors as RFC 7807 [`ProblemDetails`] with `application/problem+json`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProblemRenderer;
+
+impl ErrorRenderer for ProblemRenderer {
+    fn render(&self, status: StatusCode, body: ErrorBody) -> Response {
+        let mut response = (status, Json(ProblemDetails::new(status, body))).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
+    }
+
+    /// Swap `ErrorBody` error responses for `ProblemDetails` under `application/problem+json`
+    fn document(&self, api: &mut openapi::OpenApi) {
+        let error_body = Ref::from_schema_name("ErrorBody").ref_location;
+
+        let mut documented = false;
+        for item in api.paths.paths.values_mut() {
+            for operation in [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                for response in operation.responses.responses.values_mut() {
+                    let RefOr::T(response) = response else {
+                        continue;
+                    };
+                    // Rebuilt rather than removed from, as the map type depends on utoipa's features
+                    response.content = std::mem::take(&mut response.content)
+                        .into_iter()
+                        .map(|(content_type, mut content)| {
+                            let is_error_body = content_type == "application/json"
+                                && matches!(
+                                    &content.schema,
+                                    Some(RefOr::Ref(schema)) if schema.ref_location == error_body
+                                );
+                            if !is_error_body {
+                                return (content_type, content);
+                            }
+                            content.schema = Some(Ref::from_schema_name("ProblemDetails").into());
+                            // Examples show the `ErrorBody` shape, which no longer matches
+                            content.example = None;
+                            content.examples.clear();
+                            documented = true;
+                            (PROBLEM_JSON.to_string(), content)
+                        })
+                        .collect();
+                }
+            }
+        }
+
+        if documented {
+            let components = api.components.get_or_insert_with(Default::default);
+            components
+                .schemas
+                .entry("ProblemDetails".to_string())
+                .or_insert_with(<ProblemDetails as PartialSchema>::schema);
+            components
+                .schemas
+                .entry("FieldError".to_string())
+                .or_insert_with(<FieldError as PartialSchema>::schema);
+        }
+    }
+}
+
+/// The renderer of the service handling the current request, or the default
+/// outside of one
+pub fn renderer() -> Arc<dyn ErrorRenderer> {
+    REQUEST
+        .try_with(|scope| scope.renderer.clone())
+        .unwrap_or_else(|_| Arc::new(DefaultErrorRenderer))
+}
+
+/// Get the request ID from the request headers
+pub fn request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Make the request ID and the service's renderer available to error responses
+/// while the request is handled
+pub(crate) async fn scope_request(
+    axum::extract::State(renderer): axum::extract::State<Arc<dyn ErrorRenderer>>,
+    req: axum::http::Request<axum::body::Body>,
+    next: axum::middleware::Next,
+) -> Response {
+    let scope = RequestScope {
+        request_id: request_id(req.headers()),
+        renderer,
+    };
+    REQUEST.scope(scope, next.run(req)).await
+}
+
+/// Request ID of the request being handled, for errors built without its headers
+pub(crate) fn current_request_id() -> Option<String> {
+    REQUEST
+        .try_with(|scope| scope.request_id.clone())
+        .ok()
+        .flatten()
+}
+
+/// Default fallback for unmatched routes
+pub async fn not_found(headers: HeaderMap) -> Response {
+    ErrorBody::new("Not found", &headers).into_response_with(StatusCode::NOT_FOUND)
+}
+
+/// Default fallback for routes matched with an unsupported method
+pub async fn method_not_allowed(headers: HeaderMap) -> Response {
+    ErrorBody::new("Method not allowed", &headers)
+        .into_response_with(StatusCode::METHOD_NOT_ALLOWED)
+}
+
+/// Return type for JSON handlers, so failures can be propagated with `?`
+///
+/// ```ignore
+/// pub async fn get_user(db: Db, Path(id): Path<i32>) -> ApiResult<UserResponse> {
+///     let user = Entity::find_by_id(id)
+///         .one(&*db)
+///         .await?
+///         .ok_or_else(|| AppError::not_found("User not found"))?;
+///     Ok(Json(user.into()))
+/// }
+/// ```
+pub type ApiResult<T> = Result<Json<T>, AppError>;
+
+/// Error type for handlers, rendered as an `ErrorBody` JSON response
+///
+/// `?` converts database and `anyhow` errors, so handlers don't need to unwrap
+#[derive(Debug, Clone)]
+pub struct AppError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, message)
+    }
+
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            tracing::error!(status = %self.status, "{}", self.message);
+        }
+
+        ErrorBody {
+            error: self.message,
+            request_id: current_request_id(),
+            fields: Vec::new(),
+            retry_after_seconds: None,
+        }
+        .into_response_with(self.status)
+    }
+}
+
+/// Logged in full, the client only gets a generic 500
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        tracing::error!("{:#}", e);
+        Self::internal(INTERNAL_ERROR_MESSAGE)
+    }
+}
+
+/// Constraint violations and other database errors are logged in full, clients
+/// get a generic message that doesn't reveal the SQL or schema
+#[cfg(feature = "database")]
+impl From<sea_orm::DbErr> for AppError {
+    fn from(e: sea_orm::DbErr) -> Self {
+        use sea_orm::{DbErr, SqlErr};
+
+        match (&e, e.sql_err()) {
+            (DbErr::RecordNotFound(message), _) => Self::not_found(message.clone()),
+            (_, Some(SqlErr::UniqueConstraintViolation(message))) => {
+                tracing::warn!("database: {}", message);
+                Self::conflict("Conflicts with an existing record")
+            }
+            (_, Some(SqlErr::ForeignKeyConstraintViolation(message))) => {
+                tracing::warn!("database: {}", message);
+                Self::conflict("Conflicts with a related record")
+            }
+            _ => {
+                tracing::error!("database: {}", e);
+                Self::internal(INTERNAL_ERROR_MESSAGE)
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::entity::CreationTracking;
+use serde::Serialize;
+use std::cell::RefCell;
+
+#[cfg(feature = "dapr")]
+use std::sync::OnceLock;
+
+tokio::task_local! {
+    /// Events published inside `transaction` or a `Txn`, sent once it commits
+    static OUTBOX: RefCell<Vec<Outgoing>>;
+}
+
+/// An event waiting for its transaction to commit
+#[cfg_attr(not(feature = "dapr"), allow(dead_code))]
+pub(crate) struct Outgoing {
+    topic: &'static str,
+    event: serde_json::Value,
+    creation_key: String,
+}
+
+/// Entity that publishes an event when a row is created
+///
+/// Opt in per entity by implementing this on the `Model` and calling
+/// [`publish_created`] from `ActiveModelBehavior::after_save`:
+///
+/// ```ignore
+/// impl DomainEvent for Model {
+///     type Event = contracts::UserCreatedEvent;
+///     const TOPIC: &'static str = "user.created";
+///
+///     fn to_event(&self) -> Self::Event {
+///         contracts::UserCreatedEvent {
+///             creation_system: self.creation_system.clone(),
+///             creation_key: self.creation_key.clone(),
+///             generated_on: self.generated_on,
+///             name: self.name.clone(),
+///         }
+///     }
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl ActiveModelBehavior for ActiveModel {
+///     async fn after_save<C: ConnectionTrait>(model: Model, _db: &C, insert: bool) -> Result<Model, DbErr> {
+///         microkit::events::publish_created(&model, insert).await;
+///         Ok(model)
+///     }
+/// }
+/// ```
+pub trait DomainEvent: CreationTracking {
+    type Event: Serialize;
+
+    /// Topic the event is published to
+    const TOPIC: &'static str;
+
+    /// Build the event, carrying the creation tracking fields as its identity
+    fn to_event(&self) -> Self::Event;
+}
+
+#[cfg(feature = "dapr")]
+struct Publisher {
+    dapr: crate::dapr::Dapr,
+    pubsub: String,
+    service_name: String,
+}
+
+#[cfg(feature = "dapr")]
+static PUBLISHER: OnceLock<Publisher> = OnceLock::new();
+
+/// Route domain events through `dapr`, called once when the service is built
+#[cfg(feature = "dapr")]
+pub(crate) fn install(dapr: crate::dapr::Dapr, pubsub: String, service_name: String) {
+    let publisher = Publisher {
+        dapr,
+        pubsub,
+        service_name,
+    };
+
+    if PUBLISHER.set(publisher).is_err() {
+        tracing::warn!("events: publisher already installed, keeping the first");
+    }
+}
+
+/// Publish the entity's event after an insert
+///
+/// Only rows created by this service are published, so rows inserted from a
+/// consumed event aren't echoed back. Does nothing for updates or when
+/// `domain_events` isn't configured.
+///
+/// Inside [`transaction`](crate::database::transaction) or a `Txn` the event is
+/// held until the commit and dropped on rollback. Other inserts have already
+/// committed when `after_save` runs, so they're published straight away; a
+/// transaction begun by hand publishes before its commit. The insert isn't rolled
+/// back when publishing fails, the failure is logged.
+pub async fn publish_created<M: DomainEvent>(model: &M, insert: bool) {
+    #[cfg(feature = "dapr")]
+    if insert
+        && let Some(publisher) = PUBLISHER.get()
+        && model.creation_system() == publisher.service_name
+    {
+        let event = match serde_json::to_value(model.to_event()) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::error!(
+                    creation_key = %model.creation_key(),
+                    "events: failed to serialize for '{}': {}",
+                    M::TOPIC,
+                    e
+                );
+                return;
+            }
+        };
+        let mut outgoing = Some(Outgoing {
+            topic: M::TOPIC,
+            event,
+            creation_key: model.creation_key().into_owned(),
+        });
+
+        let _ = OUTBOX.try_with(|outbox| outbox.borrow_mut().extend(outgoing.take()));
+        if let Some(outgoing) = outgoing {
+            send(publisher, outgoing).await;
+        }
+    }
+
+    #[cfg(not(feature = "dapr"))]
+    let _ = (model, insert);
+}
+
+/// Whether this service published `event`, so consumers subscribed to their own
+/// topic can acknowledge it instead of inserting the row a second time
+///
+/// Always false when `domain_events` isn't configured
+pub fn is_own_event<E: CreationTracking>(event: &E) -> bool {
+    #[cfg(feature = "dapr")]
+    return PUBLISHER
+        .get()
+        .is_some_and(|publisher| event.creation_system() == publisher.service_name);
+
+    #[cfg(not(feature = "dapr"))]
+    {
+        let _ = event;
+        false
+    }
+}
+
+/// Run `transaction` holding the events published inside it, to be released once
+/// it commits
+#[cfg(feature = "database")]
+pub(crate) async fn hold<F: Future>(transaction: F) -> (F::Output, Vec<Outgoing>) {
+    OUTBOX
+        .scope(RefCell::new(Vec::new()), async {
+            let output = transaction.await;
+            (output, OUTBOX.with(|outbox| outbox.take()))
+        })
+        .await
+}
+
+/// Publish events held by [`hold`] after their transaction committed
+#[cfg(feature = "database")]
+pub(crate) async fn release(events: Vec<Outgoing>) {
+    #[cfg(feature = "dapr")]
+    if let Some(publisher) = PUBLISHER.get() {
+        for outgoing in events {
+            send(publisher, outgoing).await;
+        }
+    }
+
+    #[cfg(not(feature = "dapr"))]
+    let _ = events;
+}
+
+/// Drop events held by [`hold`] after their transaction rolled back
+#[cfg(feature = "database")]
+pub(crate) fn discard(events: Vec<Outgoing>) {
+    if !events.is_empty() {
+        tracing::debug!(
+            "events: dropped {} unpublished after a rollback",
+            events.len()
+        );
+    }
+}
+
+#[cfg(feature = "dapr")]
+async fn send(publisher: &Publisher, outgoing: Outgoing) {
+    let mut dapr = publisher.dapr.clone();
+    match dapr
+        .publish(&publisher.pubsub, outgoing.topic, &outgoing.event)
+        .await
+    {
+        Ok(()) => tracing::debug!(
+            creation_key = %outgoing.creation_key,
+            "events: published to '{}'",
+            outgoing.topic
+        ),
+        Err(e) => tracing::error!(
+            creation_key = %outgoing.creation_key,
+            "events: failed to publish to '{}': {:#}",
+            outgoing.topic,
+            e
+        ),
+    }
+}
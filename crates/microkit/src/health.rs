@@ -1,11 +1,96 @@
+use axum::Json;
 use axum::Router;
-use axum::response::Html;
+use axum::http::{Extensions, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Body of `/status/ready`
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// `ready` when every component is up
+    pub status: &'static str,
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+    /// Circuit breaker states, informational so an open circuit doesn't take
+    /// the service out of rotation
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub circuits: BTreeMap<String, crate::circuit::CircuitState>,
+}
+
+/// Readiness of one dependency
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    /// `up` or `down`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl From<Result<(), String>> for ComponentStatus {
+    fn from(result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Self {
+                status: "up",
+                message: None,
+            },
+            Err(message) => Self {
+                status: "down",
+                message: Some(message),
+            },
+        }
+    }
+}
 
 pub fn register_endpoints(router: Router) -> Router {
     router.merge(
         Router::new()
-            .route("/status/ready", get(Html("ready")))
+            .route("/status/ready", get(ready))
             .route("/status/live", get(Html("live"))),
     )
 }
+
+/// 200 when every component is up, 503 otherwise
+///
+/// Components are found in the request extensions, so only subsystems that were
+/// initialized are checked
+#[allow(unused_variables, unused_mut)]
+async fn ready(extensions: Extensions) -> Response {
+    let mut components: BTreeMap<&'static str, ComponentStatus> = BTreeMap::new();
+
+    #[cfg(feature = "database")]
+    if let Some(health) = extensions.get::<crate::database::DbHealth>() {
+        components.insert("database", health.status().into());
+    }
+
+    #[cfg(feature = "dapr")]
+    if extensions.get::<crate::dapr::Dapr>().is_some() {
+        components.insert("dapr", crate::dapr::sidecar_status().await.into());
+    }
+
+    if extensions
+        .get::<crate::server::Draining>()
+        .is_some_and(crate::server::Draining::is_draining)
+    {
+        components.insert("server", Err("shutting down".to_string()).into());
+    }
+
+    let ready = components
+        .values()
+        .all(|component| component.status == "up");
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(Readiness {
+            status: if ready { "ready" } else { "not_ready" },
+            components,
+            circuits: crate::circuit::states(),
+        }),
+    )
+        .into_response()
+}
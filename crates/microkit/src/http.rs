@@ -0,0 +1,194 @@
+use crate::circuit::CircuitBreaker;
+use crate::retry::{Failure, RetryPolicy, is_idempotent};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Timeout applied to outbound requests made with the shared client
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+static CLIENT: OnceLock<ClientWithMiddleware> = OnceLock::new();
+
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+
+/// User-agent of clients built before `init`
+const DEFAULT_USER_AGENT: &str = "microkit";
+
+/// Shared, pooled HTTP client for calling other services
+///
+/// Uses the service name as the user-agent, retries transient failures with the
+/// configured [`RetryPolicy`] and, with the `otel` feature, propagates the current
+/// trace context via W3C `traceparent` headers
+pub fn client() -> &'static ClientWithMiddleware {
+    CLIENT.get_or_init(|| build_client(None))
+}
+
+/// Client like [`client`] whose requests go through `breaker`
+///
+/// Transport errors and 5xx responses count as failures; while the circuit is
+/// open requests fail with a [`CircuitOpen`](crate::circuit::CircuitOpen)
+/// middleware error without being sent, and aren't retried. Each retry counts
+/// as a call. Build it once and keep it, e.g. in state
+pub fn client_with_breaker(breaker: CircuitBreaker) -> ClientWithMiddleware {
+    build_client(Some(breaker))
+}
+
+/// Set the user-agent of clients built from now on, called during build
+///
+/// The shared client is built on first use, so it only misses the service name
+/// when called before build
+pub(crate) fn init(service_name: &str) {
+    if CLIENT.get().is_some() && USER_AGENT.get().is_none() {
+        tracing::warn!(
+            "http: shared client was used before build, its user-agent stays '{}'",
+            DEFAULT_USER_AGENT
+        );
+    }
+    if let Err(name) = USER_AGENT.set(service_name.to_string())
+        && USER_AGENT.get() != Some(&name)
+    {
+        tracing::warn!(
+            "http: user-agent is already '{}', keeping it for '{}'",
+            USER_AGENT.get().map(String::as_str).unwrap_or_default(),
+            name
+        );
+    }
+}
+
+fn build_client(breaker: Option<CircuitBreaker>) -> ClientWithMiddleware {
+    let user_agent = USER_AGENT
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_USER_AGENT);
+    let client = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .timeout(DEFAULT_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    // Outermost, so every attempt goes through the breaker and gets trace headers
+    #[allow(unused_mut)]
+    let mut builder = ClientBuilder::new(client).with(Retry);
+
+    if let Some(breaker) = breaker {
+        builder = builder.with(breaker);
+    }
+
+    #[cfg(feature = "otel")]
+    {
+        builder = builder.with(TracePropagation);
+    }
+
+    builder.build()
+}
+
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for CircuitBreaker {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut axum::http::Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        let attempt = self
+            .acquire()
+            .map_err(|open| reqwest_middleware::Error::Middleware(open.into()))?;
+
+        let result = next.run(req, extensions).await;
+        attempt.finish(matches!(&result, Ok(response) if !response.status().is_server_error()));
+        result
+    }
+}
+
+/// Retries with the request's [`RetryPolicy`] extension, or the configured policy
+struct Retry;
+
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for Retry {
+    async fn handle(
+        &self,
+        mut req: reqwest::Request,
+        extensions: &mut axum::http::Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        let policy = extensions
+            .get::<RetryPolicy>()
+            .cloned()
+            .unwrap_or_else(RetryPolicy::configured);
+        let attempts = policy.attempts(is_idempotent(req.method()));
+        let method = req.method().clone();
+        let url = format!(
+            "{}{}",
+            req.url().host_str().unwrap_or_default(),
+            req.url().path()
+        );
+
+        let mut attempt = 1;
+        loop {
+            // Streamed bodies can't be replayed, so they get a single attempt
+            let Some(replay) = (attempt < attempts).then(|| req.try_clone()).flatten() else {
+                return next.run(req, extensions).await;
+            };
+
+            let result = next.clone().run(req, extensions).await;
+            let retry = match &result {
+                Ok(response)
+                    if response.status().is_client_error()
+                        || response.status().is_server_error() =>
+                {
+                    let failure = Failure::Status(response.status());
+                    policy.is_retryable(&failure).then(|| failure.to_string())
+                }
+                Err(reqwest_middleware::Error::Reqwest(e)) => {
+                    let failure = Failure::Error(e);
+                    policy.is_retryable(&failure).then(|| failure.to_string())
+                }
+                // Successes, and middleware errors such as an open circuit
+                _ => None,
+            };
+            let Some(failure) = retry else {
+                return result;
+            };
+
+            let delay = policy.delay(attempt);
+            tracing::warn!(
+                "http: {} {} failed ({}/{}), retrying in {}ms: {}",
+                method,
+                url,
+                attempt,
+                attempts,
+                delay.as_millis(),
+                failure
+            );
+            tokio::time::sleep(delay).await;
+            req = replay;
+            attempt += 1;
+        }
+    }
+}
+
+/// Injects the current span's trace context into outbound request headers
+#[cfg(feature = "otel")]
+struct TracePropagation;
+
+#[cfg(feature = "otel")]
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for TracePropagation {
+    async fn handle(
+        &self,
+        mut req: reqwest::Request,
+        extensions: &mut axum::http::Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        use opentelemetry::global;
+        use opentelemetry_http::HeaderInjector;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let context = tracing::Span::current().context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(req.headers_mut()))
+        });
+
+        next.run(req, extensions).await
+    }
+}
@@ -0,0 +1,65 @@
+use axum::Router;
+use axum::http::{HeaderMap, header};
+use axum::response::{Html, IntoResponse, Json, Response};
+use axum::routing::get;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Service summary served at `/`
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexPage {
+    pub service: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Paths of the mounted documentors and probes
+    pub links: Vec<String>,
+}
+
+/// Router serving the index page at `/`, as HTML for browsers and JSON otherwise
+pub(crate) fn router(page: IndexPage) -> Router {
+    let page = Arc::new(page);
+    Router::new().route(
+        "/",
+        get(move |headers: HeaderMap| async move { render(&page, &headers) }),
+    )
+}
+
+fn render(page: &IndexPage, headers: &HeaderMap) -> Response {
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    if !wants_html {
+        return Json(page).into_response();
+    }
+
+    let mut html = format!(
+        "<!doctype html><title>{0}</title><h1>{0}</h1>",
+        escape(&page.service)
+    );
+    if let Some(description) = &page.description {
+        html.push_str(&format!("<p>{}</p>", escape(description)));
+    }
+    if let Some(version) = &page.version {
+        html.push_str(&format!("<p>Version {}</p>", escape(version)));
+    }
+    html.push_str("<ul>");
+    for link in &page.links {
+        // Relative so links work behind a path prefix
+        let link = escape(link.trim_start_matches('/'));
+        html.push_str(&format!("<li><a href=\"{0}\">{0}</a></li>", link));
+    }
+    html.push_str("</ul>");
+
+    Html(html).into_response()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
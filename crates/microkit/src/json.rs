@@ -0,0 +1,125 @@
+use crate::error::{ErrorBody, FieldError};
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+
+/// JSON request body whose parse errors name the offending field
+///
+/// A drop-in for `axum::Json` that rejects with 400 and the field path in
+/// `fields` instead of a bare 422, 413 when the body is over the limit and 415
+/// without a JSON `Content-Type`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonBody<T>(pub T);
+
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+
+        if !is_json(&headers) {
+            return Err(ErrorBody::new(
+                "Expected request with `Content-Type: application/json`",
+                &headers,
+            )
+            .into_response_with(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        }
+
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| ErrorBody::new(e.body_text(), &headers).into_response_with(e.status()))?;
+
+        parse(&bytes, &headers)
+            .map(JsonBody)
+            .map_err(|body| body.into_response_with(StatusCode::BAD_REQUEST))
+    }
+}
+
+impl<T> IntoResponse for JsonBody<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json"
+        || essence.starts_with("application/") && essence.ends_with("+json")
+}
+
+/// Deserialize a JSON body, the error names the failing field's path
+pub(crate) fn parse<T: DeserializeOwned>(
+    bytes: &[u8],
+    headers: &HeaderMap,
+) -> Result<T, ErrorBody> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+
+    let result = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|e| (e.path().to_string(), e.into_inner()))
+        .and_then(|value| {
+            deserializer
+                .end()
+                .map(|()| value)
+                .map_err(|e| (String::new(), e))
+        });
+
+    result.map_err(|(path, e)| match e.classify() {
+        Category::Data => {
+            let message = message(&e);
+            ErrorBody::new("Invalid request body", headers).with_fields(vec![FieldError {
+                field: field(&path, &message),
+                message,
+            }])
+        }
+        Category::Syntax | Category::Eof | Category::Io => {
+            ErrorBody::new(format!("Invalid JSON: {}", e), headers)
+        }
+    })
+}
+
+/// The error message without serde_json's trailing location
+fn message(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    let location = format!(" at line {} column {}", e.line(), e.column());
+    message
+        .strip_suffix(&location)
+        .map(str::to_string)
+        .unwrap_or(message)
+}
+
+/// Path of the rejected field, serde reports missing and duplicate fields at their parent
+pub(crate) fn field(path: &str, message: &str) -> String {
+    let missing = message
+        .strip_prefix("missing field `")
+        .or_else(|| message.strip_prefix("duplicate field `"))
+        .and_then(|rest| rest.strip_suffix('`'));
+
+    match (path, missing) {
+        (".", Some(name)) => name.to_string(),
+        (path, Some(name)) => format!("{}.{}", path, name),
+        (path, None) => path.to_string(),
+    }
+}
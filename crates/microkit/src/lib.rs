@@ -1,17 +1,37 @@
+mod assets;
+mod banner;
+pub mod circuit;
+pub mod client_ip;
+pub mod clock;
 pub mod config;
+pub mod context;
 pub mod entity;
+pub mod envelope;
+pub mod error;
+pub mod events;
+pub mod index;
+pub mod json;
+pub mod maintenance;
+pub mod middleware;
+pub mod negotiation;
 pub mod network;
 pub mod prelude;
+pub mod query;
+pub mod rate_limit;
+pub mod response;
+pub mod retry;
 pub mod router;
+pub mod schema;
+pub mod server;
+pub mod tenant;
 
-pub use microkit_macros::*;
+pub use microkit_macros::{
+    CreationTracked, api_contract, discover_endpoints, event_contract, register_endpoints,
+};
+// Expands to an `AuthenticatedUser` extractor
+#[cfg(feature = "auth")]
+pub use microkit_macros::require_scopes;
 
-#[cfg(any(
-    feature = "swagger",
-    feature = "redoc",
-    feature = "rapidoc",
-    feature = "scalar"
-))]
 pub mod documentors;
 
 #[cfg(feature = "health-checks")]
@@ -23,6 +43,9 @@ pub mod otel;
 #[cfg(feature = "dapr")]
 pub mod dapr;
 
+#[cfg(feature = "dapr")]
+pub mod pubsub;
+
 #[cfg(feature = "auth")]
 pub mod auth;
 
@@ -30,7 +53,14 @@ pub mod auth;
 pub mod database;
 
 #[cfg(feature = "database")]
-use sea_orm::DatabaseConnection;
+pub mod migrations;
+
+#[cfg(feature = "http-client")]
+pub mod http;
+
+#[cfg(feature = "database")]
+pub mod pagination;
+
 #[cfg(feature = "database")]
 use sea_orm_migration::MigratorTrait;
 
@@ -39,7 +69,9 @@ use tracing_subscriber::{EnvFilter, fmt};
 
 use anyhow::{Result, bail};
 use config::Config;
+use server::ServerHandle;
 use std::fmt::Display;
+use std::net::SocketAddr;
 use tower_http::cors::CorsLayer;
 use utoipa_axum::router::OpenApiRouter;
 
@@ -47,19 +79,57 @@ pub enum ServicePort {
     Api,
     Client,
     Other(u16),
+    /// Port 0, so the OS assigns a free one whatever `port_offset` says. Read it
+    /// back from the handle returned by `start_with_handle`:
+    ///
+    /// ```ignore
+    /// let handle = service.start_with_handle(ServicePort::Ephemeral).await?;
+    /// let url = format!("http://127.0.0.1:{}", handle.port());
+    /// ```
+    Ephemeral,
 }
 
 impl ServicePort {
+    /// Roles every service may bind, used to compute a service's port map
+    pub const ROLES: [ServicePort; 2] = [ServicePort::Api, ServicePort::Client];
+
     pub fn get(&self) -> u16 {
         match self {
             ServicePort::Api => 50000,
             ServicePort::Client => 60000,
             ServicePort::Other(port) => *port,
+            ServicePort::Ephemeral => 0,
         }
     }
 
-    pub fn get_with_offset(&self, port_base: u16) -> u16 {
-        Self::get(self) + port_base
+    /// Port for this role shifted by `port_offset`, failing when it would exceed `u16::MAX`
+    ///
+    /// `Ephemeral` stays 0 rather than binding the offset itself
+    pub fn get_with_offset(&self, port_offset: u16) -> Result<u16> {
+        if let ServicePort::Ephemeral = self {
+            return Ok(0);
+        }
+        match self.get().checked_add(port_offset) {
+            Some(port) => Ok(port),
+            None => bail!(
+                "Port offset {} is too large, the {} port ({}) would exceed {}",
+                port_offset,
+                self,
+                self.get(),
+                u16::MAX
+            ),
+        }
+    }
+
+    /// Effective port for every known role, `None` where the offset overflows
+    pub fn port_map(port_offset: u16) -> Vec<(ServicePort, Option<u16>)> {
+        Self::ROLES
+            .into_iter()
+            .map(|role| {
+                let port = role.get().checked_add(port_offset);
+                (role, port)
+            })
+            .collect()
     }
 }
 
@@ -69,43 +139,39 @@ impl Display for ServicePort {
             ServicePort::Api => write!(f, "api"),
             ServicePort::Client => write!(f, "client"),
             ServicePort::Other(_) => write!(f, "other"),
+            ServicePort::Ephemeral => write!(f, "ephemeral"),
         }
     }
 }
 
+/// Hook that installs custom fallback handlers on the final router
+pub type FallbackHook = Box<dyn FnOnce(axum::Router) -> axum::Router + Send>;
+
 pub struct MicroKit {
     pub config: Config,
     pub router: Option<OpenApiRouter>,
     #[cfg(feature = "database")]
-    pub database: Option<DatabaseConnection>,
+    pub database: Option<database::DbPools>,
+    #[cfg(feature = "database")]
+    pub tenants: Option<tenant::TenantDatabases>,
+    /// Background ping results for the primary, reported by readiness
+    #[cfg(feature = "database")]
+    pub database_health: Option<database::DbHealth>,
     #[cfg(feature = "dapr")]
     pub dapr: Option<dapr::Dapr>,
     #[cfg(feature = "auth")]
     pub auth: Option<auth::AuthConfig>,
-}
-
-#[cfg(feature = "database")]
-trait MigratorRunner: Send + Sync {
-    fn run<'a>(
-        &'a self,
-        db: &'a DatabaseConnection,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>>;
-}
-
-#[cfg(feature = "database")]
-struct TypedMigrator<M: MigratorTrait>(std::marker::PhantomData<M>);
-
-#[cfg(feature = "database")]
-impl<M: MigratorTrait + Send + Sync> MigratorRunner for TypedMigrator<M> {
-    fn run<'a>(
-        &'a self,
-        db: &'a DatabaseConnection,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
-        Box::pin(async move {
-            M::up(db, None).await?;
-            Ok(())
-        })
-    }
+    fallbacks: Option<FallbackHook>,
+    shutdown_hooks: Vec<server::ShutdownHook>,
+    /// Document conflicts found while merging routes, reported by `report_conflicts`
+    conflicts: Vec<String>,
+    maintenance: maintenance::Maintenance,
+    /// Shapes every error response, from `with_error_renderer` or `error_format`
+    error_renderer: std::sync::Arc<dyn error::ErrorRenderer>,
+    #[cfg(feature = "otel")]
+    otel: Option<otel::OtelGuard>,
+    #[cfg(feature = "health-checks")]
+    health_checks: bool,
 }
 
 pub struct MicroKitBuilder {
@@ -114,12 +180,20 @@ pub struct MicroKitBuilder {
     routes: Vec<OpenApiRouter>,
     #[allow(clippy::type_complexity)]
     endpoint_initializer: Option<Box<dyn FnOnce(&mut MicroKit) -> Result<()> + Send>>,
+    fallbacks: Option<FallbackHook>,
+    error_renderer: Option<Box<dyn error::ErrorRenderer>>,
+    shutdown_hooks: Vec<server::ShutdownHook>,
     #[cfg(feature = "tracing")]
     enable_logging: bool,
     #[cfg(feature = "database")]
     enable_database: bool,
     #[cfg(feature = "database")]
-    migrator: Option<Box<dyn MigratorRunner>>,
+    migrator: Option<std::sync::Arc<dyn migrations::MigratorRunner>>,
+    #[cfg(feature = "database")]
+    enable_migrations_endpoint: bool,
+    enable_maintenance_endpoint: bool,
+    #[cfg(feature = "sqlite")]
+    test_database: bool,
     #[cfg(feature = "otel")]
     enable_otel: bool,
     #[cfg(feature = "health-checks")]
@@ -142,81 +216,551 @@ impl MicroKit {
         MicroKitBuilder::new(config)
     }
 
-    pub fn add_route(&mut self, route: OpenApiRouter) {
-        match &mut self.router {
-            Some(router) => self.router = Some(router.clone().merge(route)),
-            None => self.router = Some(route),
+    /// State given to `discover_endpoints!` routes without a `state = ...`, so handlers
+    /// can take `State<DatabaseConnection>`
+    ///
+    /// The write connection, or a disconnected one that fails every query when no
+    /// database is enabled
+    #[doc(hidden)]
+    #[cfg(feature = "database")]
+    pub fn endpoint_state(&self) -> sea_orm::DatabaseConnection {
+        self.database
+            .as_ref()
+            .map(|database| database.write.clone())
+            .unwrap_or_default()
+    }
+
+    #[doc(hidden)]
+    #[cfg(not(feature = "database"))]
+    pub fn endpoint_state(&self) {}
+
+    /// Merge a route into the service, failing if it duplicates an existing route
+    ///
+    /// Duplicate operation ids and schema names are reported once all routes are
+    /// merged, at the end of build or when serving for routes added after
+    pub fn add_route(&mut self, mut route: OpenApiRouter) -> Result<()> {
+        router::name_operations(&mut route, self.config.operation_ids.unwrap_or_default());
+        self.router = Some(match self.router.take() {
+            Some(router) => router::merge(router, route, &mut self.conflicts)?,
+            None => route,
+        });
+        Ok(())
+    }
+
+    /// Limit an `/admin` route to `admin_roles`, failing when there's no way to
+    #[allow(unused_variables)]
+    fn admin_route(&self, path: &str, route: OpenApiRouter) -> Result<OpenApiRouter> {
+        #[cfg(feature = "auth")]
+        if let Some(roles) = self.config.admin_roles.as_deref()
+            && !roles.is_empty()
+            && self.auth.is_some()
+        {
+            use router::RequireRoles;
+            let roles: Vec<&str> = roles.iter().map(String::as_str).collect();
+            return Ok(route.require_roles(&roles));
+        }
+
+        bail!(
+            "admin: {} needs the auth feature, an auth section and admin_roles, as anyone who can reach it could use it",
+            path
+        )
+    }
+
+    /// Fail on the document conflicts found so far, or log them when `docs.on_conflict` is warn
+    fn report_conflicts(&mut self) -> Result<()> {
+        if self.conflicts.is_empty() {
+            return Ok(());
+        }
+        let conflicts = std::mem::take(&mut self.conflicts);
+
+        let policy = self
+            .config
+            .docs
+            .as_ref()
+            .and_then(|docs| docs.on_conflict)
+            .unwrap_or_default();
+        match policy {
+            router::ConflictPolicy::Error => bail!(
+                "OpenAPI conflicts, rename the handlers or types, change operation_ids, or set docs.on_conflict: warn:\n{}",
+                conflicts.join("\n")
+            ),
+            router::ConflictPolicy::Warn => {
+                for conflict in conflicts {
+                    tracing::warn!("docs: {}", conflict);
+                }
+                Ok(())
+            }
         }
     }
 
-    /// Run database migrations
+    /// Run database migrations, logging each one applied
+    ///
+    /// Combine several migrator crates with [`migrations::Chain`]
     #[cfg(feature = "database")]
     pub async fn run_migrations<M: MigratorTrait>(&self) -> Result<()> {
         if let Some(database) = &self.database {
-            M::up(database, None).await?;
+            migrations::apply::<M>(&database.write).await?;
         }
         Ok(())
     }
 
-    pub async fn start(mut self, port_base: ServicePort) -> Result<()> {
-        if let Some(router) = &mut self.router {
-            #[allow(unused_mut)]
-            let (mut router, api) = router.clone().split_for_parts();
-
-            let config = self.config.clone();
-            router = router.layer(axum::middleware::from_fn(
-                move |mut req: axum::http::Request<axum::body::Body>,
-                      next: axum::middleware::Next| {
-                    let config = config.clone();
-                    async move {
-                        req.extensions_mut().insert(config);
-                        next.run(req).await
-                    }
-                },
+    /// Start serving and block until the server stops
+    ///
+    /// Shuts down gracefully on Ctrl+C or SIGTERM: `/status/ready` fails first,
+    /// requests are served for `pre_shutdown_delay_secs` while load balancers catch
+    /// up, then in-flight requests drain, the `on_shutdown` hooks run and telemetry
+    /// is flushed before returning
+    pub async fn start(self, port_base: ServicePort) -> Result<()> {
+        let delay = self
+            .config
+            .pre_shutdown_delay_secs
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(server::DEFAULT_PRE_SHUTDOWN_DELAY);
+        let mut handle = self.start_with_handle(port_base).await?;
+        tokio::select! {
+            _ = server::shutdown_signal() => {}
+            result = handle.stopped() => {
+                tracing::error!("server: stopped without a shutdown signal");
+                return result;
+            }
+        }
+        tracing::info!("server: shutdown signal received");
+        handle
+            .shutdown_after(delay, server::shutdown_signal())
+            .await
+    }
+
+    /// Switch for maintenance mode, e.g. to turn it on around a data migration
+    pub fn maintenance(&self) -> maintenance::Maintenance {
+        self.maintenance.clone()
+    }
+
+    /// Take ownership of the OTel providers, e.g. to flush them when using `into_router`
+    #[cfg(feature = "otel")]
+    pub fn take_otel_guard(&mut self) -> Option<otel::OtelGuard> {
+        self.otel.take()
+    }
+
+    /// Start serving in the background, returning the bound address and a shutdown handle
+    ///
+    /// The `on_shutdown` hooks run and telemetry is flushed once the server stops,
+    /// before `wait` or `shutdown` return
+    #[allow(unused_mut)]
+    pub async fn start_with_handle(mut self, port_base: ServicePort) -> Result<ServerHandle> {
+        if self.router.is_none() {
+            bail!("No router");
+        }
+
+        let (address, listener) = network::network(
+            &self.config.host,
+            self.config.ip_family.unwrap_or_default(),
+            port_base,
+            self.config.port_offset,
+            self.config.public_url.as_deref(),
+        )
+        .await?;
+
+        let mut summary = self.startup_summary();
+
+        #[allow(unused_mut)]
+        let mut on_stop: Vec<server::StopHook> = Vec::new();
+
+        #[cfg(feature = "otel")]
+        if let Some(otel) = self.otel.take() {
+            on_stop.push(Box::new(move || otel.shutdown()));
+        }
+
+        let settings = self.config.server.clone().unwrap_or_default();
+        let cleanup = server::Cleanup {
+            hooks: std::mem::take(&mut self.shutdown_hooks),
+            hook_timeout: self
+                .config
+                .shutdown_hook_timeout_secs
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(server::DEFAULT_SHUTDOWN_HOOK_TIMEOUT),
+            on_stop,
+        };
+        if self
+            .config
+            .maintenance
+            .as_ref()
+            .and_then(|maintenance| maintenance.toggle_signal)
+            != Some(false)
+        {
+            maintenance::listen_for_signal(self.maintenance.clone());
+        }
+
+        let (router, links) = self.layered_router(Some(&address))?;
+
+        summary.links = links;
+        summary.log(&address);
+
+        Ok(ServerHandle::spawn(
+            listener, address, router, settings, cleanup,
+        ))
+    }
+
+    /// Build the fully layered router without binding a socket
+    ///
+    /// Intended for integration tests, which can drive the router directly:
+    ///
+    /// ```ignore
+    /// use axum::{body::Body, http::{Request, StatusCode}};
+    /// use tower::ServiceExt;
+    ///
+    /// #[tokio::test]
+    /// async fn ready() -> anyhow::Result<()> {
+    ///     let router = MicroKit::builder_with_config(config)
+    ///         .with_router()
+    ///         .with_health_checks()
+    ///         .build()
+    ///         .await?
+    ///         .into_router()?;
+    ///
+    ///     let request = Request::get("/status/ready").body(Body::empty())?;
+    ///     let response = router.oneshot(request).await?;
+    ///     assert_eq!(response.status(), StatusCode::OK);
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// OTel providers stay installed for the rest of the process unless taken
+    /// beforehand with `take_otel_guard`
+    #[allow(unused_mut)]
+    pub fn into_router(mut self) -> Result<axum::Router> {
+        #[cfg(feature = "otel")]
+        if let Some(otel) = self.otel.take() {
+            otel.detach();
+        }
+
+        self.layered_router(None).map(|(router, _)| router)
+    }
+
+    /// Running subsystems, captured before `start_with_handle` takes them apart
+    fn startup_summary(&self) -> banner::StartupSummary {
+        #[allow(unused_mut)]
+        let mut features = Vec::new();
+        #[allow(unused_mut)]
+        let mut database = None;
+        #[allow(unused_mut)]
+        let mut otel = None;
+        #[allow(unused_mut)]
+        let mut health = Vec::new();
+
+        #[cfg(feature = "database")]
+        if self.database.is_some() {
+            features.push("database");
+            database = self.config.database_name.clone();
+        }
+        #[cfg(feature = "dapr")]
+        if self.dapr.is_some() {
+            features.push("dapr");
+        }
+        #[cfg(feature = "auth")]
+        if self.auth.is_some() {
+            features.push("auth");
+        }
+        #[cfg(feature = "otel")]
+        if self.otel.is_some() {
+            features.push("otel");
+            otel = self.config.otel.as_ref().map(|otel| otel.url.clone());
+        }
+        #[cfg(feature = "health-checks")]
+        if self.health_checks {
+            features.push("health-checks");
+            health.extend(["/status/ready", "/status/live"]);
+        }
+
+        banner::StartupSummary {
+            service: self.config.service_name.clone(),
+            version: self.config.service_version.clone(),
+            features,
+            database,
+            otel,
+            health,
+            links: Vec::new(),
+        }
+    }
+
+    /// The layered router and the links shown on the index page
+    fn layered_router(
+        mut self,
+        address: Option<&SocketAddr>,
+    ) -> Result<(axum::Router, Vec<String>)> {
+        self.report_conflicts()?;
+
+        let Some(router) = self.router else {
+            bail!("No router");
+        };
+
+        #[allow(unused_mut, unused_variables)]
+        let (mut router, mut api) = router.split_for_parts();
+
+        router::apply_servers(&mut api, self.config.public_url.as_deref(), address);
+
+        if let Some(tags) = self
+            .config
+            .docs
+            .as_ref()
+            .and_then(|docs| docs.tags.as_ref())
+        {
+            router::apply_tags(&mut api, tags);
+        }
+
+        self.error_renderer.document(&mut api);
+
+        let excluded_paths = self.config.excluded_paths();
+
+        // Only the service's routes, the docs and index page merged below stay bare
+        if self.config.response_envelope == Some(true) {
+            envelope::apply_schemas(&mut api);
+            router = router.layer(middleware::ExcludePaths::new(
+                axum::middleware::from_fn(envelope::wrap),
+                excluded_paths.clone(),
             ));
+        }
 
-            #[cfg(feature = "auth")]
-            if let Some(auth) = &self.auth {
-                router = router.layer(axum::middleware::from_fn_with_state(
-                    auth.clone(),
-                    auth::inject_auth_config,
-                ));
-            }
+        // After the envelope so the wrapped response schemas are normalized too
+        if self
+            .config
+            .docs
+            .as_ref()
+            .and_then(|docs| docs.client_conventions)
+            == Some(true)
+        {
+            router::apply_client_conventions(&mut api);
+        }
 
-            #[allow(unused_variables)]
-            let (address, listener) =
-                network::network(&self.config.host, port_base, self.config.port_offset).await?;
+        let config = self.config.clone();
+        router = router.layer(axum::middleware::from_fn(
+            move |mut req: axum::http::Request<axum::body::Body>, next: axum::middleware::Next| {
+                let config = config.clone();
+                async move {
+                    req.extensions_mut().insert(config);
+                    next.run(req).await
+                }
+            },
+        ));
 
-            #[cfg(feature = "auth")]
-            let router =
-                documentors::documentors(router, &api, &address, self.config.auth.as_ref());
-
-            #[cfg(all(
-                any(
-                    feature = "swagger",
-                    feature = "redoc",
-                    feature = "rapidoc",
-                    feature = "scalar"
-                ),
-                not(feature = "auth")
-            ))]
-            let router = documentors::documentors(router, &api, &address);
+        #[cfg(feature = "database")]
+        if let Some(database) = &self.database {
+            router = router
+                .layer(axum::Extension(database.write.clone()))
+                .layer(axum::Extension(database.clone()));
+        }
 
-            let router = router.layer(CorsLayer::very_permissive());
+        #[cfg(feature = "database")]
+        if self.database.is_some()
+            && let Some(admission) = database::DbAdmission::from_config(
+                self.config.database_pool.as_ref(),
+                self.config.database_read_url.is_some(),
+            )
+        {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                admission,
+                database::admission,
+            ));
+        }
 
-            #[cfg(feature = "otel")]
-            let router = if self.config.otel.is_some() {
-                otel::apply_layers(router)
-            } else {
-                router
-            };
+        #[cfg(feature = "database")]
+        if let Some(tenants) = &self.tenants {
+            router = router.layer(axum::Extension(tenants.clone()));
+        }
+
+        #[cfg(feature = "database")]
+        if let Some(health) = &self.database_health {
+            router = router.layer(axum::Extension(health.clone()));
+        }
+
+        #[cfg(feature = "dapr")]
+        if let Some(dapr) = &self.dapr {
+            router = router.layer(axum::Extension(dapr.clone()));
+        }
 
-            axum::serve(listener, router.into_make_service()).await?;
+        #[cfg(feature = "dapr")]
+        if let Some(dapr) = &self.dapr
+            && let Some(dead_letter) = &self.config.dead_letter
+        {
+            router = router.layer(axum::Extension(pubsub::DeadLetter::new(
+                dapr.clone(),
+                dead_letter.clone(),
+            )));
+        }
+
+        #[cfg(feature = "auth")]
+        if let Some(auth) = &self.auth {
+            router = router.layer(middleware::ExcludePaths::new(
+                axum::middleware::from_fn_with_state(auth.clone(), auth::inject_auth_config),
+                excluded_paths.clone(),
+            ));
+        }
+
+        // Only the service's routes, the docs and index page merged below stay up
+        router = router.layer(axum::middleware::from_fn_with_state(
+            maintenance::Gate::new(self.maintenance.clone(), self.config.maintenance.as_ref()),
+            maintenance::check,
+        ));
+
+        let mut links: Vec<String> = Vec::new();
+
+        // Logged URLs, left out when building a router that isn't served
+        let base_url =
+            address.map(|address| network::base_url(self.config.public_url.as_deref(), address));
+        let base_url = base_url.as_deref();
+
+        let spec_path = self.config.spec_path();
+        let router = if self.config.docs_enabled() {
+            let (router, spec) = documentors::spec(router, &api, base_url, spec_path)?;
+            links.extend(spec);
+            router
         } else {
-            bail!("No router");
+            router
+        };
+
+        #[allow(unused_variables)]
+        let documentors_enabled = self.config.documentors_enabled();
+
+        #[cfg(all(
+            any(
+                feature = "swagger",
+                feature = "redoc",
+                feature = "rapidoc",
+                feature = "scalar"
+            ),
+            feature = "auth"
+        ))]
+        let router = if documentors_enabled {
+            let (router, documentors) = documentors::documentors(
+                router,
+                &api,
+                base_url,
+                spec_path,
+                self.config.auth.as_ref(),
+            );
+            links.extend(documentors.into_iter().map(str::to_string));
+            router
+        } else {
+            router
+        };
+
+        #[cfg(all(
+            any(
+                feature = "swagger",
+                feature = "redoc",
+                feature = "rapidoc",
+                feature = "scalar"
+            ),
+            not(feature = "auth")
+        ))]
+        let router = if documentors_enabled {
+            let (router, documentors) = documentors::documentors(router, &api, base_url, spec_path);
+            links.extend(documentors.into_iter().map(str::to_string));
+            router
+        } else {
+            router
+        };
+
+        #[cfg(feature = "health-checks")]
+        if self.health_checks {
+            links.push("/status/live".to_string());
         }
 
-        Ok(())
+        let static_files = self.config.static_files.as_ref();
+        let static_root = static_files.filter(|files| files.mount() == "/");
+
+        // Opt in, and skipped when the service or its static files serve `/` themselves
+        let router = if self.config.index_page == Some(true)
+            && !api.paths.paths.contains_key("/")
+            && static_root.is_none()
+        {
+            router.merge(index::router(index::IndexPage {
+                service: self.config.service_name.clone(),
+                description: self.config.service_desc.clone(),
+                version: self.config.service_version.clone(),
+                links: links.clone(),
+            }))
+        } else {
+            router
+        };
+
+        // Mounted after the API, docs and index page so they take precedence
+        let router = match static_files {
+            Some(files) if static_root.is_none() => {
+                router.nest_service(files.mount(), assets::service(files))
+            }
+            _ => router,
+        };
+
+        // Installed after all routes are merged so only genuine misses are caught.
+        // Static files mounted at `/` stand in for the 404
+        let router = match (self.fallbacks, static_root) {
+            (Some(fallbacks), _) => fallbacks(router),
+            (None, Some(files)) => router
+                .fallback_service(assets::service(files))
+                .method_not_allowed_fallback(error::method_not_allowed),
+            (None, None) => router
+                .fallback(error::not_found)
+                .method_not_allowed_fallback(error::method_not_allowed),
+        };
+
+        // Inside tracing so body logs are attributed to the request span
+        let router = match self.config.log_bodies.clone() {
+            Some(log_bodies) => {
+                middleware::apply_body_logging(router, log_bodies, excluded_paths.clone())
+            }
+            None => router,
+        };
+
+        let router = match self.config.rate_limits.clone() {
+            Some(rules) => rate_limit::apply(
+                router,
+                rules,
+                excluded_paths.clone(),
+                #[cfg(feature = "auth")]
+                self.auth.clone(),
+            ),
+            None => router,
+        };
+
+        // Outside rate limiting so its buckets are keyed by the resolved client
+        let router = client_ip::apply(
+            router,
+            client_ip::Resolver::new(self.config.client_ip.as_ref()),
+        );
+
+        // Outside the body limit so preflights and rejections still carry CORS headers
+        let router = match self.config.body_limit() {
+            Some(max) => middleware::apply_body_limit(router, max),
+            None => router,
+        };
+
+        let router = match &self.config.cors {
+            Some(cors) => router.layer(middleware::cors_layer(cors)?),
+            None if self.config.is_hardened() => router,
+            None => router.layer(CorsLayer::very_permissive()),
+        };
+
+        #[cfg(feature = "otel")]
+        let router = if self.config.otel.is_some() {
+            otel::apply_layers(router, excluded_paths.clone())
+        } else {
+            router
+        };
+
+        // Outermost so overloaded requests are shed before doing any work
+        let router = match self.config.max_concurrent_requests {
+            Some(max) => middleware::apply_concurrency_limit(router, max, excluded_paths),
+            None => router,
+        };
+
+        // Around everything, so any error response is rendered by this service's
+        // renderer and can echo the request ID
+        let router = router.layer(axum::middleware::from_fn_with_state(
+            self.error_renderer.clone(),
+            error::scope_request,
+        ));
+
+        Ok((router, links))
     }
 }
 
@@ -227,12 +771,20 @@ impl MicroKitBuilder {
             enable_router: false,
             routes: Vec::new(),
             endpoint_initializer: None,
+            fallbacks: None,
+            error_renderer: None,
+            shutdown_hooks: Vec::new(),
             #[cfg(feature = "tracing")]
             enable_logging: false,
             #[cfg(feature = "database")]
             enable_database: false,
             #[cfg(feature = "database")]
             migrator: None,
+            #[cfg(feature = "database")]
+            enable_migrations_endpoint: false,
+            enable_maintenance_endpoint: false,
+            #[cfg(feature = "sqlite")]
+            test_database: false,
             #[cfg(feature = "otel")]
             enable_otel: false,
             #[cfg(feature = "health-checks")]
@@ -258,6 +810,27 @@ impl MicroKitBuilder {
         self
     }
 
+    /// Use a fresh in-memory SQLite database instead of `database_url`
+    ///
+    /// For integration tests: pending migrations are always applied, whatever
+    /// `migrations` says, so each test starts from a clean schema without a
+    /// database server. Pair it with `into_router` to drive handlers directly
+    #[cfg(feature = "sqlite")]
+    pub fn with_test_database(mut self) -> Self {
+        self.enable_database = true;
+        self.test_database = true;
+        self
+    }
+
+    /// Use the hardened profile unless `profile` is set in microkit.yml
+    ///
+    /// Drops permissive CORS, hides the documentors, caps request bodies and fails
+    /// startup when auth is enabled without config. Individual settings still win
+    pub fn hardened(mut self) -> Self {
+        self.config.profile.get_or_insert(config::Profile::Hardened);
+        self
+    }
+
     /// Enable router (required for serving HTTP)
     pub fn with_router(mut self) -> Self {
         self.enable_router = true;
@@ -299,11 +872,31 @@ impl MicroKitBuilder {
         self
     }
 
-    /// Configure database migrations to run during build
+    /// Configure database migrations to run during build, according to `migrations` in config
+    ///
+    /// Combine several migrator crates with [`migrations::Chain`], applied in order
     #[cfg(feature = "database")]
     pub fn with_migrations<M: MigratorTrait + Send + Sync + 'static>(mut self) -> Self {
         self.enable_database = true;
-        self.migrator = Some(Box::new(TypedMigrator::<M>(std::marker::PhantomData)));
+        self.migrator = Some(std::sync::Arc::new(migrations::TypedMigrator::<M>::new()));
+        self
+    }
+
+    /// Serve applied vs pending migrations at `/admin/migrations`
+    ///
+    /// Needs the `auth` feature, an `auth` section and `admin_roles`, otherwise build fails
+    #[cfg(feature = "database")]
+    pub fn with_migrations_endpoint(mut self) -> Self {
+        self.enable_migrations_endpoint = true;
+        self
+    }
+
+    /// Serve `/admin/maintenance` to read (GET) and switch maintenance mode on (PUT) or off (DELETE)
+    ///
+    /// Needs the `auth` feature, an `auth` section and `admin_roles`, otherwise build fails
+    /// rather than letting anyone who can reach it take the service down
+    pub fn with_maintenance_endpoint(mut self) -> Self {
+        self.enable_maintenance_endpoint = true;
         self
     }
 
@@ -316,37 +909,107 @@ impl MicroKitBuilder {
         self
     }
 
+    /// Run `hook` when the service shuts down, e.g. to stop a worker or close a file
+    ///
+    /// Hooks run once the server has stopped accepting connections and in-flight
+    /// requests have drained, newest first, each abandoned after
+    /// `shutdown_hook_timeout_secs`. Telemetry is flushed after the last hook, so
+    /// their logs and spans are still exported. Not run for `into_router`
+    pub fn on_shutdown<F>(mut self, hook: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_hooks.push(Box::pin(hook));
+        self
+    }
+
+    /// Replace the default JSON 404/405 fallbacks
+    ///
+    /// The hook receives the router after all routes are merged, e.g.
+    /// `|router| router.fallback(my_not_found)`
+    pub fn with_fallbacks<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(axum::Router) -> axum::Router + Send + 'static,
+    {
+        self.fallbacks = Some(Box::new(f));
+        self
+    }
+
+    /// Render error responses with `renderer` instead of the `error_format` in config
+    ///
+    /// Applies to every error MicroKit produces as well as [`AppError`](error::AppError)
+    pub fn with_error_renderer(mut self, renderer: impl error::ErrorRenderer) -> Self {
+        self.error_renderer = Some(Box::new(renderer));
+        self
+    }
+
+    /// Turn off subsystems disabled under `features` in config, returning their names
+    fn apply_feature_toggles(&mut self) -> Vec<&'static str> {
+        #[allow(unused_variables)]
+        let Some(features) = self.config.features.clone() else {
+            return Vec::new();
+        };
+
+        #[allow(unused_mut)]
+        let mut disabled = Vec::new();
+
+        #[allow(unused_macros)]
+        macro_rules! toggle {
+            ($flag:ident, $toggle:ident, $name:literal) => {
+                if self.$flag && features.$toggle == Some(false) {
+                    self.$flag = false;
+                    disabled.push($name);
+                }
+            };
+        }
+
+        #[cfg(feature = "tracing")]
+        toggle!(enable_logging, logging, "logging");
+        #[cfg(feature = "database")]
+        toggle!(enable_database, database, "database");
+        #[cfg(feature = "otel")]
+        toggle!(enable_otel, otel, "otel");
+        #[cfg(feature = "health-checks")]
+        toggle!(enable_health_checks, health_checks, "health-checks");
+        #[cfg(feature = "dapr")]
+        toggle!(enable_dapr, dapr, "dapr");
+        #[cfg(feature = "auth")]
+        toggle!(enable_auth, auth, "auth");
+
+        disabled
+    }
+
     /// Build the MicroKit instance with all configured features
-    pub async fn build(self) -> Result<MicroKit> {
+    pub async fn build(mut self) -> Result<MicroKit> {
+        // Before anything connects or spawns, so a bad config fails fast
+        self.config.validate()?;
+
+        #[allow(unused_variables)]
+        let disabled = self.apply_feature_toggles();
+
         #[cfg(feature = "otel")]
-        let otel_providers = if self.enable_otel {
-            otel::init_providers(&self.config.service_name, &self.config.otel)?
+        let otel_guard = if self.enable_otel {
+            otel::init_providers(
+                &self.config.service_name,
+                &self.config.otel,
+                self.config.sample_ratio(),
+            )?
         } else {
             None
         };
 
         #[cfg(feature = "tracing")]
         if self.enable_logging {
-            // Build filter that respects RUST_LOG environment variable first,
-            // then falls back to config, then defaults to "info"
-            let filter = match std::env::var("RUST_LOG") {
-                Ok(_) => {
-                    // RUST_LOG is set, use it
-                    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
-                }
-                Err(_) => {
-                    // RUST_LOG not set, check config
-                    if let Some(log_level) = &self.config.log_level {
-                        EnvFilter::new(log_level)
-                    } else {
-                        EnvFilter::new("info")
-                    }
-                }
+            let (directives, source) = self.config.log_filter();
+            let json = self.config.log_format() == config::LogFormat::Json;
+            let (filter, invalid) = match EnvFilter::try_new(&directives) {
+                Ok(filter) => (filter, None),
+                Err(e) => (EnvFilter::new(config::DEFAULT_LOG_LEVEL), Some(e)),
             };
 
             #[cfg(all(feature = "otel", feature = "tracing"))]
             if self.enable_otel
-                && let Some((tracer_provider, logger_provider)) = otel_providers
+                && let Some(guard) = &otel_guard
             {
                 use opentelemetry::trace::TracerProvider;
                 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
@@ -354,44 +1017,120 @@ impl MicroKitBuilder {
                 use tracing_subscriber::Registry;
                 use tracing_subscriber::layer::SubscriberExt;
 
-                let tracer = tracer_provider.tracer("microkit");
+                let tracer = guard.tracer_provider.tracer("microkit");
                 let otel_layer = OpenTelemetryLayer::new(tracer);
-                let log_layer = OpenTelemetryTracingBridge::new(&logger_provider);
+                let log_layer = OpenTelemetryTracingBridge::new(&guard.logger_provider);
 
                 let subscriber = Registry::default()
                     .with(filter)
-                    .with(fmt::layer())
+                    .with((!json).then(fmt::layer))
+                    .with(json.then(|| fmt::layer().json()))
                     .with(otel_layer)
                     .with(log_layer);
 
                 let _ = tracing::subscriber::set_global_default(subscriber);
             } else {
-                let subscriber = fmt().with_env_filter(filter).finish();
-                let _ = tracing::subscriber::set_global_default(subscriber);
+                init_fmt(filter, json);
             }
 
             #[cfg(not(all(feature = "otel", feature = "tracing")))]
             {
-                let subscriber = fmt().with_env_filter(filter).finish();
-                let _ = tracing::subscriber::set_global_default(subscriber);
+                init_fmt(filter, json);
             }
+
+            match invalid {
+                None => tracing::info!("logging: filter '{}' from {}", directives, source),
+                Some(e) => tracing::warn!(
+                    "logging: invalid filter '{}' from {} ({}), using {}",
+                    directives,
+                    source,
+                    e,
+                    config::DEFAULT_LOG_LEVEL
+                ),
+            }
+        }
+
+        // An invalid MICROKIT_ENV was already rejected by validate
+        #[cfg(feature = "tracing")]
+        match config::Environment::from_env() {
+            Ok(Some(environment)) => tracing::info!(
+                "config: environment {} from {}",
+                environment,
+                config::ENVIRONMENT_ENV
+            ),
+            Ok(None) => tracing::info!(
+                "config: environment {} ({} not set)",
+                config::Environment::default(),
+                config::ENVIRONMENT_ENV
+            ),
+            Err(_) => {}
+        }
+
+        #[cfg(feature = "tracing")]
+        if !disabled.is_empty() {
+            tracing::info!("config: disabled by features: {}", disabled.join(", "));
+        }
+
+        retry::init(self.config.retry.as_ref());
+        let error_renderer: std::sync::Arc<dyn error::ErrorRenderer> = self
+            .error_renderer
+            .take()
+            .unwrap_or_else(|| self.config.error_format.unwrap_or_default().renderer())
+            .into();
+
+        #[cfg(feature = "http-client")]
+        http::init(&self.config.service_name);
+
+        for (name, breaker) in self.config.circuit_breakers.iter().flatten() {
+            circuit::CircuitBreaker::from_config(name, breaker);
         }
 
         // Initialize database if enabled
         #[cfg(feature = "database")]
         let database = if self.enable_database {
-            Some(
-                database::setup_database(
-                    &self.config.database_url,
-                    &self.config.database_name,
-                    &self.config.database_drop,
-                )
-                .await?,
-            )
+            #[cfg(feature = "sqlite")]
+            if self.test_database {
+                Some(database::setup_test_database().await?)
+            } else {
+                Some(database::setup_pools(&self.config).await?)
+            }
+            #[cfg(not(feature = "sqlite"))]
+            Some(database::setup_pools(&self.config).await?)
         } else {
             None
         };
 
+        #[cfg(feature = "database")]
+        let database_health = database.as_ref().and_then(|pools| {
+            let pool = self.config.database_pool.clone().unwrap_or_default();
+            match pool
+                .health_check_secs
+                .unwrap_or(config::DEFAULT_DB_HEALTH_CHECK_SECS)
+            {
+                0 => None,
+                secs => Some(database::DbHealth::monitor(
+                    pools.write.clone(),
+                    std::time::Duration::from_secs(secs),
+                    pool.unhealthy_after
+                        .unwrap_or(config::DEFAULT_DB_UNHEALTHY_AFTER),
+                )),
+            }
+        });
+
+        #[cfg(feature = "database")]
+        let tenants = match (&database, &self.config.tenancy) {
+            #[cfg(feature = "sqlite")]
+            (Some(pools), Some(_)) if self.test_database => {
+                Some(tenant::TenantDatabases::shared(&self.config, &pools.write))
+            }
+            (Some(_), Some(_)) => Some(tenant::TenantDatabases::connect(&self.config).await?),
+            _ => None,
+        };
+
+        if self.enable_router {
+            documentors::check_features(&self.config);
+        }
+
         // Initialize router if enabled
         let mut router = if self.enable_router {
             #[cfg(feature = "auth")]
@@ -400,7 +1139,9 @@ impl MicroKitBuilder {
                     Some(router::generate_router_with_auth(
                         &self.config.service_name,
                         &self.config.service_desc,
-                        Some(auth_yaml.issuer.clone()),
+                        // HS256 issuers have no discovery document to log in with
+                        (auth_yaml.mode() == auth::AuthMode::Oidc)
+                            .then(|| auth_yaml.issuer.clone()),
                     ))
                 } else {
                     Some(router::generate_router(
@@ -420,12 +1161,15 @@ impl MicroKitBuilder {
         };
 
         // Add routes
+        let mut conflicts = Vec::new();
         if !self.routes.is_empty() {
-            for route in self.routes {
-                match &mut router {
-                    Some(r) => router = Some(r.clone().merge(route)),
-                    None => router = Some(route),
-                }
+            let strategy = self.config.operation_ids.unwrap_or_default();
+            for mut route in self.routes {
+                router::name_operations(&mut route, strategy);
+                router = Some(match router {
+                    Some(r) => router::merge(r, route, &mut conflicts)?,
+                    None => route,
+                });
             }
         }
 
@@ -441,18 +1185,37 @@ impl MicroKitBuilder {
         // Initialize Dapr if enabled
         #[cfg(feature = "dapr")]
         let dapr = if self.enable_dapr {
-            Some(dapr::Dapr::new().await?)
+            Some(dapr::Dapr::connect(self.config.dapr_wait()).await?)
         } else {
             None
         };
 
+        #[cfg(feature = "dapr")]
+        if let Some(domain_events) = &self.config.domain_events {
+            match &dapr {
+                Some(dapr) => events::install(
+                    dapr.clone(),
+                    domain_events.pubsub.clone(),
+                    self.config.service_name.clone(),
+                ),
+                None => tracing::warn!("events: domain_events configured but Dapr is disabled"),
+            }
+        }
+
         // Initialize auth if enabled
         #[cfg(feature = "auth")]
         let auth = if self.enable_auth {
             let auth_config = self.config.create_auth_config()?;
             if let Some(auth) = auth_config {
                 tracing::info!("Authentication initialized");
+                // In the background, so a slow provider doesn't hold up startup
+                if self.config.auth.as_ref().and_then(|a| a.prefetch_jwks) != Some(false) {
+                    let auth = auth.clone();
+                    tokio::spawn(async move { auth.prefetch_jwks().await });
+                }
                 Some(auth)
+            } else if self.config.auth_required() {
+                bail!("Authentication enabled but no auth config in microkit.yml");
             } else {
                 tracing::warn!("Authentication feature enabled but no auth config in microkit.yml");
                 None
@@ -461,23 +1224,76 @@ impl MicroKitBuilder {
             None
         };
 
+        let maintenance_enabled = self
+            .config
+            .maintenance
+            .as_ref()
+            .and_then(|maintenance| maintenance.enabled)
+            == Some(true);
+        if maintenance_enabled {
+            tracing::warn!("maintenance: on at startup, business routes answer 503");
+        }
+
         let mut service = MicroKit {
             config: self.config,
             router,
             #[cfg(feature = "database")]
             database,
+            #[cfg(feature = "database")]
+            tenants,
+            #[cfg(feature = "database")]
+            database_health,
             #[cfg(feature = "dapr")]
             dapr,
             #[cfg(feature = "auth")]
             auth,
+            fallbacks: self.fallbacks,
+            shutdown_hooks: self.shutdown_hooks,
+            conflicts,
+            maintenance: maintenance::Maintenance::new(maintenance_enabled),
+            error_renderer,
+            #[cfg(feature = "otel")]
+            otel: otel_guard,
+            #[cfg(feature = "health-checks")]
+            health_checks: self.enable_health_checks,
         };
 
         // Run migrations if configured
         #[cfg(feature = "database")]
         if let Some(migrator) = self.migrator
-            && let Some(ref db) = service.database
+            && let Some(db) = service.database.clone().map(|pools| pools.write)
         {
-            migrator.run(db).await?;
+            #[allow(unused_mut)]
+            let mut mode = service.config.migrations.unwrap_or_default();
+            #[cfg(feature = "sqlite")]
+            if self.test_database {
+                mode = config::MigrationMode::Auto;
+            }
+            migrations::run_with_mode(migrator.as_ref(), &db, mode).await?;
+
+            // Tenants sharing the test database were migrated with it
+            #[cfg(feature = "sqlite")]
+            let tenants = service.tenants.as_ref().filter(|_| !self.test_database);
+            #[cfg(not(feature = "sqlite"))]
+            let tenants = service.tenants.as_ref();
+            for (id, tenant_db) in tenants.into_iter().flat_map(|tenants| tenants.iter()) {
+                tracing::info!("tenant: migrating '{}'", id);
+                migrations::run_with_mode(migrator.as_ref(), tenant_db, mode).await?;
+            }
+
+            if self.enable_migrations_endpoint && service.router.is_some() {
+                let route = service
+                    .admin_route("/admin/migrations", migrations::router(migrator, db).into())?;
+                service.add_route(route)?;
+            }
+        }
+
+        if self.enable_maintenance_endpoint && service.router.is_some() {
+            let route = service.admin_route(
+                "/admin/maintenance",
+                maintenance::router(service.maintenance()).into(),
+            )?;
+            service.add_route(route)?;
         }
 
         // Initialize endpoints if configured
@@ -485,6 +1301,71 @@ impl MicroKitBuilder {
             initializer(&mut service)?;
         }
 
+        // Every route is merged by now, apart from any added before serving
+        service.report_conflicts()?;
+
         Ok(service)
     }
 }
+
+/// Install the console-only subscriber
+#[cfg(feature = "tracing")]
+fn init_fmt(filter: EnvFilter, json: bool) {
+    let subscriber = fmt().with_env_filter(filter);
+    let _ = if json {
+        tracing::subscriber::set_global_default(subscriber.json().finish())
+    } else {
+        tracing::subscriber::set_global_default(subscriber.finish())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServicePort;
+
+    #[test]
+    fn offset_can_land_on_the_last_port() {
+        for role in ServicePort::ROLES {
+            let offset = u16::MAX - role.get();
+            assert_eq!(role.get_with_offset(offset).unwrap(), u16::MAX, "{}", role);
+        }
+    }
+
+    #[test]
+    fn offset_past_the_last_port_names_the_role_and_offset() {
+        for role in ServicePort::ROLES {
+            let offset = u16::MAX - role.get() + 1;
+            let error = role.get_with_offset(offset).unwrap_err().to_string();
+            assert!(error.contains(&role.to_string()), "{}", error);
+            assert!(error.contains(&offset.to_string()), "{}", error);
+        }
+    }
+
+    #[test]
+    fn ephemeral_stays_zero() {
+        assert_eq!(ServicePort::Ephemeral.get_with_offset(0).unwrap(), 0);
+        assert_eq!(ServicePort::Ephemeral.get_with_offset(u16::MAX).unwrap(), 0);
+    }
+
+    #[test]
+    fn port_map_is_none_only_for_roles_that_overflow() {
+        let offset = u16::MAX - ServicePort::Client.get() + 1;
+        let ports: Vec<_> = ServicePort::port_map(offset)
+            .into_iter()
+            .map(|(role, port)| (role.to_string(), port))
+            .collect();
+        assert_eq!(
+            ports,
+            [
+                ("api".to_string(), Some(ServicePort::Api.get() + offset)),
+                ("client".to_string(), None),
+            ]
+        );
+
+        assert!(
+            ServicePort::port_map(0)
+                .iter()
+                .all(|(role, port)| *port == Some(role.get()))
+        );
+    }
+}
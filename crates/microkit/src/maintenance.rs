@@ -0,0 +1,193 @@
+use crate::error::ErrorBody;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Path of the endpoint added by `with_maintenance_endpoint`, never put in maintenance
+pub const ENDPOINT_PATH: &str = "/admin/maintenance";
+
+/// Message returned while in maintenance unless configured
+pub const DEFAULT_MESSAGE: &str = "Down for maintenance, please try again shortly";
+
+/// Seconds clients are asked to wait via `Retry-After` unless configured
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
+/// Paths kept live in maintenance unless configured
+pub const DEFAULT_EXEMPT_PATHS: &[&str] = &["/status/*", "/metrics"];
+
+/// Maintenance mode settings under `maintenance`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct MaintenanceConfig {
+    /// Start in maintenance (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Message returned with the 503 (default: "Down for maintenance, please try again shortly")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Seconds clients are asked to wait via `Retry-After` (default: 60)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+    /// Paths still served in maintenance, exact or a prefix ending in `*`, on top of
+    /// /status/*, /metrics, the docs and index page, which always are (default: none)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exempt_paths: Option<Vec<String>>,
+    /// Toggle maintenance on SIGUSR1, unix only (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toggle_signal: Option<bool>,
+}
+
+/// Switch for maintenance mode, shared by every clone
+///
+/// Get it from `MicroKit::maintenance` to toggle it from code, or from the request
+/// extensions in a handler. While on, routes that aren't exempt answer 503 with
+/// `Retry-After`
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub fn new(enabled: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Turn maintenance on or off, returning the previous state
+    pub fn set(&self, enabled: bool) -> bool {
+        let previous = self.0.swap(enabled, Ordering::Relaxed);
+        if previous != enabled {
+            tracing::warn!("maintenance: {}", if enabled { "on" } else { "off" });
+        }
+        previous
+    }
+
+    /// Flip maintenance, returning the new state
+    pub fn toggle(&self) -> bool {
+        let enabled = !self.0.fetch_xor(true, Ordering::Relaxed);
+        tracing::warn!("maintenance: {}", if enabled { "on" } else { "off" });
+        enabled
+    }
+}
+
+/// What the layer answers with and which paths it leaves alone
+#[derive(Debug, Clone)]
+pub(crate) struct Gate {
+    maintenance: Maintenance,
+    message: Arc<str>,
+    retry_after: u64,
+    exempt_paths: Arc<[String]>,
+}
+
+impl Gate {
+    pub(crate) fn new(maintenance: Maintenance, config: Option<&MaintenanceConfig>) -> Self {
+        let exempt_paths: Vec<String> = DEFAULT_EXEMPT_PATHS
+            .iter()
+            .map(|path| path.to_string())
+            .chain(
+                config
+                    .and_then(|config| config.exempt_paths.clone())
+                    .into_iter()
+                    .flatten(),
+            )
+            .collect();
+
+        Self {
+            maintenance,
+            message: config
+                .and_then(|config| config.message.as_deref())
+                .unwrap_or(DEFAULT_MESSAGE)
+                .into(),
+            retry_after: config
+                .and_then(|config| config.retry_after_secs)
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECS),
+            exempt_paths: exempt_paths.into(),
+        }
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        path == ENDPOINT_PATH
+            || self
+                .exempt_paths
+                .iter()
+                .any(|exempt| match exempt.strip_suffix('*') {
+                    Some(prefix) => path.starts_with(prefix),
+                    None => path == exempt,
+                })
+    }
+}
+
+/// Answer 503 while in maintenance, unless the path is exempt
+pub(crate) async fn check(
+    State(gate): State<Gate>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if gate.maintenance.is_enabled() && !gate.is_exempt(req.uri().path()) {
+        return ErrorBody::new(gate.message.to_string(), req.headers())
+            .into_retry_response(StatusCode::SERVICE_UNAVAILABLE, gate.retry_after);
+    }
+
+    req.extensions_mut().insert(gate.maintenance.clone());
+    next.run(req).await
+}
+
+/// Current state, as served by `/admin/maintenance`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+}
+
+/// Router serving `/admin/maintenance`: GET reads the state, PUT turns it on and DELETE off
+pub(crate) fn router(maintenance: Maintenance) -> Router {
+    let (read, on, off) = (maintenance.clone(), maintenance.clone(), maintenance);
+
+    Router::new().route(
+        ENDPOINT_PATH,
+        get(move || async move { status(&read) })
+            .put(move || async move {
+                on.set(true);
+                status(&on)
+            })
+            .delete(move || async move {
+                off.set(false);
+                status(&off)
+            }),
+    )
+}
+
+fn status(maintenance: &Maintenance) -> Json<MaintenanceStatus> {
+    Json(MaintenanceStatus {
+        enabled: maintenance.is_enabled(),
+    })
+}
+
+/// Toggle `maintenance` each time the process receives SIGUSR1
+#[cfg(unix)]
+pub(crate) fn listen_for_signal(maintenance: Maintenance) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tracing::error!("maintenance: failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            maintenance.toggle();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub(crate) fn listen_for_signal(_maintenance: Maintenance) {}
@@ -0,0 +1,293 @@
+use crate::config::{BodyLogConfig, CorsConfig};
+use crate::error::ErrorBody;
+use anyhow::{Result, bail};
+use axum::Router;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::{LoadShedLayer, error::Overloaded};
+use tower::{BoxError, Layer, Service, ServiceBuilder, ServiceExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+
+/// Paths excluded from tracing, metrics, auth and load-shedding unless configured otherwise
+pub const DEFAULT_EXCLUDED_PATHS: &[&str] = &["/status/live", "/status/ready", "/metrics"];
+
+/// Seconds clients are asked to wait via `Retry-After` when requests are shed
+pub const SHED_RETRY_AFTER_SECS: u64 = 1;
+
+/// Shed requests with 503 once `max` requests are in flight, instead of queueing them
+pub fn apply_concurrency_limit(
+    router: Router,
+    max: usize,
+    excluded_paths: Arc<[String]>,
+) -> Router {
+    let layer = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(shed_response))
+        .layer(LoadShedLayer::new())
+        // Global so the limit is shared across routes rather than applied per route
+        .layer(GlobalConcurrencyLimitLayer::new(max));
+
+    router.layer(ExcludePaths::new(layer, excluded_paths))
+}
+
+async fn shed_response(err: BoxError) -> Response {
+    if err.is::<Overloaded>() {
+        ErrorBody {
+            error: "Service overloaded".to_string(),
+            request_id: crate::error::current_request_id(),
+            fields: Vec::new(),
+            retry_after_seconds: None,
+        }
+        .into_retry_response(StatusCode::SERVICE_UNAVAILABLE, SHED_RETRY_AFTER_SECS)
+    } else {
+        tracing::error!("Unhandled middleware error: {}", err);
+        ErrorBody {
+            error: crate::error::INTERNAL_ERROR_MESSAGE.to_string(),
+            request_id: crate::error::current_request_id(),
+            fields: Vec::new(),
+            retry_after_seconds: None,
+        }
+        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// Reject request bodies over `max` bytes with 413
+///
+/// Covers streamed bodies as well as extractors, whose own default limit is
+/// raised or lowered to match
+pub fn apply_body_limit(router: Router, max: usize) -> Router {
+    router
+        .layer(DefaultBodyLimit::max(max))
+        .layer(RequestBodyLimitLayer::new(max))
+}
+
+/// CORS for the configured origins, methods and headers
+///
+/// Unset methods and headers allow any, mirroring the request when credentials
+/// are allowed since browsers reject wildcards then
+pub fn cors_layer(config: &CorsConfig) -> Result<CorsLayer> {
+    let credentials = config.allow_credentials.unwrap_or(false);
+    let any_origin = config.allowed_origins.iter().any(|origin| origin == "*");
+    if any_origin && credentials {
+        bail!("cors: allow_credentials can't be combined with allowed_origins \"*\"");
+    }
+
+    let origins = if any_origin {
+        AllowOrigin::any()
+    } else {
+        let origins = config
+            .allowed_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| anyhow::anyhow!("cors: invalid origin {origin:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let methods = match &config.allowed_methods {
+        Some(methods) => AllowMethods::list(
+            methods
+                .iter()
+                .map(|method| {
+                    Method::from_bytes(method.to_uppercase().as_bytes())
+                        .map_err(|_| anyhow::anyhow!("cors: invalid method {method:?}"))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        None if credentials => AllowMethods::mirror_request(),
+        None => AllowMethods::any(),
+    };
+
+    let headers = match &config.allowed_headers {
+        Some(headers) => AllowHeaders::list(
+            headers
+                .iter()
+                .map(|name| {
+                    HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| anyhow::anyhow!("cors: invalid header {name:?}"))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        None if credentials => AllowHeaders::mirror_request(),
+        None => AllowHeaders::any(),
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(credentials))
+}
+
+/// Largest request or response body captured by body logging unless configured
+pub const DEFAULT_LOG_BODY_BYTES: usize = 4096;
+
+/// Log requests and responses with their bodies at DEBUG, for diagnosing contract mismatches
+///
+/// Bodies are only buffered when their exact size is known and within the cap;
+/// `Authorization`, `Cookie` and the configured headers are redacted
+pub fn apply_body_logging(
+    router: Router,
+    config: BodyLogConfig,
+    excluded_paths: Arc<[String]>,
+) -> Router {
+    let config = Arc::new(config);
+    router.layer(ExcludePaths::new(
+        axum::middleware::from_fn(move |req: Request<Body>, next: axum::middleware::Next| {
+            let config = config.clone();
+            async move { log_bodies(&config, req, next).await }
+        }),
+        excluded_paths,
+    ))
+}
+
+async fn log_bodies(
+    config: &BodyLogConfig,
+    req: Request<Body>,
+    next: axum::middleware::Next,
+) -> Response {
+    let max_bytes = config.max_bytes.unwrap_or(DEFAULT_LOG_BODY_BYTES);
+    let (parts, body) = req.into_parts();
+    let (body, captured) = capture(body, max_bytes).await;
+
+    tracing::debug!(
+        method = %parts.method,
+        path = %parts.uri.path(),
+        headers = ?redact(&parts.headers, config),
+        body = %captured,
+        "http: request"
+    );
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = response.into_parts();
+    let (body, captured) = capture(body, max_bytes).await;
+
+    tracing::debug!(
+        status = %parts.status,
+        headers = ?redact(&parts.headers, config),
+        body = %captured,
+        "http: response"
+    );
+
+    Response::from_parts(parts, body)
+}
+
+/// Buffer `body` if its size is known and within `max_bytes`, returning it for reuse
+async fn capture(body: Body, max_bytes: usize) -> (Body, String) {
+    let size = body.size_hint().exact();
+    match size {
+        Some(0) => (body, String::new()),
+        Some(size) if size <= max_bytes as u64 => {
+            match axum::body::to_bytes(body, max_bytes).await {
+                Ok(bytes) => {
+                    let text = String::from_utf8_lossy(&bytes).into_owned();
+                    (Body::from(bytes), text)
+                }
+                Err(e) => (Body::from(Bytes::new()), format!("<unreadable: {}>", e)),
+            }
+        }
+        Some(size) => (body, format!("<{} bytes, not captured>", size)),
+        None => (body, "<streaming, not captured>".to_string()),
+    }
+}
+
+fn redact(headers: &HeaderMap, config: &BodyLogConfig) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let sensitive = name == header::AUTHORIZATION
+                || name == header::COOKIE
+                || name == header::SET_COOKIE
+                || config
+                    .redact_headers
+                    .iter()
+                    .flatten()
+                    .any(|redacted| name.as_str().eq_ignore_ascii_case(redacted));
+            let value = if sensitive {
+                "<redacted>".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Applies the wrapped layer to every request except those for the given paths
+///
+/// Used to keep probes and scrapes out of access logs, traces and auth
+#[derive(Clone)]
+pub struct ExcludePaths<L> {
+    layer: L,
+    paths: Arc<[String]>,
+}
+
+impl<L> ExcludePaths<L> {
+    pub fn new(layer: L, paths: Arc<[String]>) -> Self {
+        Self { layer, paths }
+    }
+}
+
+impl<L, S> Layer<S> for ExcludePaths<L>
+where
+    L: Layer<S>,
+    S: Clone,
+{
+    type Service = ExcludePathsService<L::Service, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ExcludePathsService {
+            layered: self.layer.layer(inner.clone()),
+            bypass: inner,
+            paths: self.paths.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ExcludePathsService<A, S> {
+    layered: A,
+    bypass: S,
+    paths: Arc<[String]>,
+}
+
+impl<A, S> Service<Request<Body>> for ExcludePathsService<A, S>
+where
+    A: Service<Request<Body>, Error = Infallible> + Clone + Send + 'static,
+    A::Response: IntoResponse,
+    A::Future: Send,
+    S: Service<Request<Body>, Error = Infallible> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Readiness is checked per call through oneshot
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if self.paths.iter().any(|path| path == req.uri().path()) {
+            let service = self.bypass.clone();
+            Box::pin(async move { service.oneshot(req).await.map(IntoResponse::into_response) })
+        } else {
+            let service = self.layered.clone();
+            Box::pin(async move { service.oneshot(req).await.map(IntoResponse::into_response) })
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::config::MigrationMode;
+use crate::error::AppError;
+use anyhow::{Result, bail};
+use axum::routing::get;
+use axum::{Json, Router};
+use sea_orm::DatabaseConnection;
+use sea_orm_migration::{MigrationStatus, MigrationTrait, MigratorTrait};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Applied and pending migrations, by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct MigrationReport {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+impl MigrationReport {
+    /// Read the migration status without creating the migration table
+    pub async fn of<M: MigratorTrait>(db: &DatabaseConnection) -> Result<Self> {
+        let mut report = Self::default();
+
+        for migration in M::get_migration_with_status_read_only(db).await? {
+            let name = migration.name().to_string();
+            match migration.status() {
+                MigrationStatus::Applied => report.applied.push(name),
+                MigrationStatus::Pending => report.pending.push(name),
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Migrations of `A` followed by those of `B`, for services that split their
+/// schema across migrator crates
+///
+/// ```ignore
+/// .with_migrations::<Chain<users_migrations::Migrator, billing_migrations::Migrator>>()
+/// ```
+///
+/// Nest for more sets, e.g. `Chain<A, Chain<B, C>>`. Every migration of `A` is
+/// ordered before every migration of `B`, whatever their names, so a set may only
+/// depend on the sets before it. All sets share `A`'s migration table, and
+/// migration names must be unique across them
+pub struct Chain<A, B>(PhantomData<(A, B)>);
+
+impl<A: MigratorTrait, B: MigratorTrait> MigratorTrait for Chain<A, B> {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        let mut migrations = A::migrations();
+        migrations.extend(B::migrations());
+        migrations
+    }
+
+    fn migration_table_name() -> sea_orm::DynIden {
+        A::migration_table_name()
+    }
+}
+
+/// Fail on a name used by two migrations, which the migration table can't tell apart
+fn check_unique<M: MigratorTrait>() -> Result<()> {
+    let mut names = std::collections::HashSet::new();
+    for migration in M::migrations() {
+        if !names.insert(migration.name().to_string()) {
+            bail!(
+                "database: migration '{}' is defined twice; names must be unique across chained migrators",
+                migration.name()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Apply pending migrations, logging each one that ran
+pub async fn apply<M: MigratorTrait>(db: &DatabaseConnection) -> Result<MigrationReport> {
+    check_unique::<M>()?;
+    let before = MigrationReport::of::<M>(db).await?;
+
+    M::up(db, None).await?;
+
+    for name in &before.pending {
+        tracing::info!("database: applied migration '{}'", name);
+    }
+
+    let report = MigrationReport::of::<M>(db).await?;
+    tracing::info!(
+        "database: schema up to date ({} migrations applied)",
+        report.applied.len()
+    );
+
+    Ok(report)
+}
+
+/// Apply, check or skip migrations according to `mode`
+pub(crate) async fn run_with_mode(
+    migrator: &dyn MigratorRunner,
+    db: &DatabaseConnection,
+    mode: MigrationMode,
+) -> Result<()> {
+    match mode {
+        MigrationMode::Auto => {
+            migrator.run(db).await?;
+        }
+        MigrationMode::Check => {
+            let report = migrator.status(db).await?;
+            if !report.is_up_to_date() {
+                for name in &report.pending {
+                    tracing::error!("database: migration '{}' is pending", name);
+                }
+                bail!(
+                    "{} pending migrations and migrations is set to 'check'; apply them or set migrations to 'auto'",
+                    report.pending.len()
+                );
+            }
+            tracing::info!(
+                "database: schema up to date ({} migrations applied)",
+                report.applied.len()
+            );
+        }
+        // Still look, so a schema behind the code shows up before its queries fail
+        MigrationMode::Off => match migrator.status(db).await {
+            Ok(report) if !report.is_up_to_date() => tracing::warn!(
+                "database: migrations are off with {} pending: {}",
+                report.pending.len(),
+                report.pending.join(", ")
+            ),
+            Ok(_) => tracing::info!("database: migrations are off, schema up to date"),
+            Err(e) => tracing::warn!(
+                "database: migrations are off, couldn't read their status: {:#}",
+                e
+            ),
+        },
+    }
+
+    Ok(())
+}
+
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Type-erased migrator so the builder can hold any `MigratorTrait`
+pub(crate) trait MigratorRunner: Send + Sync {
+    fn run<'a>(&'a self, db: &'a DatabaseConnection) -> BoxFuture<'a, Result<MigrationReport>>;
+
+    fn status<'a>(&'a self, db: &'a DatabaseConnection) -> BoxFuture<'a, Result<MigrationReport>>;
+}
+
+pub(crate) struct TypedMigrator<M: MigratorTrait>(PhantomData<M>);
+
+impl<M: MigratorTrait> TypedMigrator<M> {
+    pub(crate) fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<M: MigratorTrait + Send + Sync> MigratorRunner for TypedMigrator<M> {
+    fn run<'a>(&'a self, db: &'a DatabaseConnection) -> BoxFuture<'a, Result<MigrationReport>> {
+        Box::pin(apply::<M>(db))
+    }
+
+    fn status<'a>(&'a self, db: &'a DatabaseConnection) -> BoxFuture<'a, Result<MigrationReport>> {
+        Box::pin(MigrationReport::of::<M>(db))
+    }
+}
+
+/// Router serving the migration report at `/admin/migrations`
+pub(crate) fn router(migrator: Arc<dyn MigratorRunner>, db: DatabaseConnection) -> Router {
+    Router::new().route(
+        "/admin/migrations",
+        get(move || async move { migrator.status(&db).await.map(Json).map_err(AppError::from) }),
+    )
+}
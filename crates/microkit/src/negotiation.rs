@@ -0,0 +1,194 @@
+use crate::error::ErrorBody;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts};
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Body formats supported by `Negotiated`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    Form,
+    Cbor,
+}
+
+impl Format {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Form => "application/x-www-form-urlencoded",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    fn from_mime(mime: &str) -> Option<Self> {
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Format::Json),
+            "application/x-www-form-urlencoded" => Some(Format::Form),
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Pick the format of a request body from its `Content-Type`, defaulting to JSON
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        match headers.get(header::CONTENT_TYPE) {
+            None => Some(Format::Json),
+            Some(value) => value.to_str().ok().and_then(Self::from_mime),
+        }
+    }
+
+    /// Pick the preferred response format from `Accept`, defaulting to JSON
+    pub fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Some(Format::Json);
+        };
+
+        let mut candidates: Vec<(f32, &str)> = accept
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';');
+                let mime = parts.next().unwrap_or_default().trim();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (quality, mime)
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+
+        // Stable sort keeps the client's order for equal weights
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        candidates.into_iter().find_map(|(_, mime)| match mime {
+            "*/*" | "application/*" => Some(Format::Json),
+            mime => Self::from_mime(mime),
+        })
+    }
+}
+
+/// Extractor for the response format requested via `Accept`
+///
+/// Rejects with 406 when none of the accepted types are supported
+#[derive(Debug, Clone, Copy)]
+pub struct Accept(pub Format);
+
+impl<S> FromRequestParts<S> for Accept
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Format::from_accept(&parts.headers)
+            .map(Accept)
+            .ok_or_else(|| {
+                ErrorBody::new(
+                    "None of the accepted content types are supported",
+                    &parts.headers,
+                )
+                .into_response_with(StatusCode::NOT_ACCEPTABLE)
+            })
+    }
+}
+
+/// Body that is read and written as JSON, form or CBOR
+///
+/// As an extractor the format comes from `Content-Type` (415 when unsupported);
+/// as a response the body is serialized in the carried format, typically from `Accept`.
+#[derive(Debug, Clone)]
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T, S> FromRequest<S> for Negotiated<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+
+        let Some(format) = Format::from_content_type(&headers) else {
+            return Err(ErrorBody::new("Unsupported content type", &headers)
+                .into_response_with(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        };
+
+        let value = match format {
+            Format::Json => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                crate::json::parse(&bytes, &headers)
+                    .map_err(|body| body.into_response_with(StatusCode::BAD_REQUEST))?
+            }
+            Format::Form => {
+                Form::<T>::from_request(req, state)
+                    .await
+                    .map_err(|e| {
+                        ErrorBody::new(e.body_text(), &headers).into_response_with(e.status())
+                    })?
+                    .0
+            }
+            Format::Cbor => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                ciborium::from_reader(bytes.as_ref()).map_err(|e| {
+                    ErrorBody::new(
+                        format!("Failed to parse the request body as CBOR: {}", e),
+                        &headers,
+                    )
+                    .into_response_with(StatusCode::UNPROCESSABLE_ENTITY)
+                })?
+            }
+        };
+
+        Ok(Negotiated(format, value))
+    }
+}
+
+impl<T> IntoResponse for Negotiated<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let Negotiated(format, value) = self;
+        match format {
+            Format::Json => Json(value).into_response(),
+            Format::Form => Form(value).into_response(),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                match ciborium::into_writer(&value, &mut bytes) {
+                    Ok(()) => (
+                        [(
+                            header::CONTENT_TYPE,
+                            HeaderValue::from_static(Format::Cbor.mime()),
+                        )],
+                        bytes,
+                    )
+                        .into_response(),
+                    Err(e) => {
+                        tracing::error!("Failed to serialize the response as CBOR: {}", e);
+                        ErrorBody {
+                            error: crate::error::INTERNAL_ERROR_MESSAGE.to_string(),
+                            request_id: crate::error::current_request_id(),
+                            fields: Vec::new(),
+                            retry_after_seconds: None,
+                        }
+                        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                }
+            }
+        }
+    }
+}
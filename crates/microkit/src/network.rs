@@ -1,34 +1,103 @@
 use crate::ServicePort;
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use tokio::net::{TcpListener, lookup_host};
 
+/// Address family to bind when the host resolves to several addresses
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    /// IPv4 when the host has an IPv4 address, otherwise IPv6
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    /// Wildcard host used when none is configured
+    fn unspecified(self) -> &'static str {
+        match self {
+            IpFamily::Ipv6 => "::",
+            IpFamily::Auto | IpFamily::Ipv4 => "0.0.0.0",
+        }
+    }
+
+    fn select(self, addrs: &[SocketAddr]) -> Option<SocketAddr> {
+        match self {
+            IpFamily::Auto => addrs
+                .iter()
+                .find(|addr| addr.is_ipv4())
+                .or_else(|| addrs.first())
+                .copied(),
+            IpFamily::Ipv4 => addrs.iter().find(|addr| addr.is_ipv4()).copied(),
+            IpFamily::Ipv6 => addrs.iter().find(|addr| addr.is_ipv6()).copied(),
+        }
+    }
+}
+
 pub async fn network(
     host: &Option<String>,
+    ip_family: IpFamily,
     port_base: ServicePort,
     port_offset: Option<u16>,
+    public_url: Option<&str>,
 ) -> Result<(SocketAddr, TcpListener)> {
     let host = match host {
         Some(host) => host,
-        None => "0.0.0.0",
+        None => ip_family.unspecified(),
     };
-    let port = match port_offset {
-        Some(port_offset) => port_base.get_with_offset(port_offset),
-        // This is used when hosting remotely for a predictable port
-        None => 80,
+    let port = bind_port(&port_base, port_offset)?;
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .with_context(|| format!("Failed to look up host: {}:{}", host, port))?
+        .collect();
+    let Some(address) = ip_family.select(&addrs) else {
+        if addrs.is_empty() {
+            bail!("Failed to look up host: {}:{}", host, port);
+        }
+        bail!(
+            "Host {} has no {} address (resolved to {}), check ip_family",
+            host,
+            if ip_family == IpFamily::Ipv6 {
+                "IPv6"
+            } else {
+                "IPv4"
+            },
+            addrs
+                .iter()
+                .map(|addr| addr.ip().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
     };
-    let mut addrs = lookup_host((host, port)).await?;
-    let address = addrs
-        .find(|addr| addr.is_ipv4())
-        .or_else(|| {
-            let mut addrs = addrs;
-            addrs.next()
-        })
-        .ok_or_else(|| anyhow!("Failed to look up host: {}:{}", host, port))?;
-    let listener = TcpListener::bind(address).await?;
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind {}", address))?;
     let local_address = listener.local_addr()?;
 
-    tracing::info!("{}: http://{}", port_base, local_address);
+    tracing::info!("{}: {}", port_base, base_url(public_url, &local_address));
 
     Ok((local_address, listener))
 }
+
+/// Port a service with `port_base` binds: the role's port shifted by `port_offset`,
+/// or 80 without an offset
+pub fn bind_port(port_base: &ServicePort, port_offset: Option<u16>) -> Result<u16> {
+    match port_offset {
+        Some(port_offset) => port_base.get_with_offset(port_offset),
+        None if matches!(port_base, ServicePort::Ephemeral) => Ok(0),
+        // This is used when hosting remotely for a predictable port
+        None => Ok(80),
+    }
+}
+
+/// Base of the URLs logged at startup: `public_url` when set, since the bound
+/// address is unreachable behind a proxy or in a container, otherwise the bound address
+pub fn base_url(public_url: Option<&str>, address: &SocketAddr) -> String {
+    match public_url {
+        Some(public_url) => public_url.trim_end_matches('/').to_string(),
+        None => format!("http://{}", address),
+    }
+}
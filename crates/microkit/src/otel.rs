@@ -1,4 +1,5 @@
 use crate::config::OtelConfig;
+use crate::middleware::ExcludePaths;
 use anyhow::{Context, Result, bail};
 use axum::Router;
 use axum_otel::{AxumOtelOnFailure, AxumOtelOnResponse, AxumOtelSpanCreator};
@@ -7,15 +8,73 @@ use opentelemetry::global;
 use opentelemetry_otlp::{LogExporter, MetricExporter, SpanExporter, WithExportConfig};
 use opentelemetry_sdk::logs::SdkLoggerProvider;
 use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
-use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
 use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator};
+use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 
+/// Owns the OTel providers so buffered spans, metrics and logs can be flushed on exit
+///
+/// Flushed and shut down by `shutdown`, or on drop if that was never called
+pub struct OtelGuard {
+    pub(crate) tracer_provider: SdkTracerProvider,
+    pub(crate) meter_provider: SdkMeterProvider,
+    pub(crate) logger_provider: SdkLoggerProvider,
+    done: bool,
+}
+
+impl OtelGuard {
+    /// Flush and shut down all providers; blocks until the exporters finish
+    pub fn shutdown(mut self) {
+        self.flush_and_shutdown();
+    }
+
+    /// Keep the providers running for the rest of the process instead of shutting down on drop
+    pub(crate) fn detach(mut self) {
+        self.done = true;
+    }
+
+    fn flush_and_shutdown(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+
+        if let Err(e) = self.tracer_provider.force_flush() {
+            tracing::warn!("otel: failed to flush traces: {}", e);
+        }
+        if let Err(e) = self.meter_provider.force_flush() {
+            tracing::warn!("otel: failed to flush metrics: {}", e);
+        }
+        if let Err(e) = self.logger_provider.force_flush() {
+            tracing::warn!("otel: failed to flush logs: {}", e);
+        }
+
+        // Logs last so shutdown problems from the others can still be exported
+        if let Err(e) = self.tracer_provider.shutdown() {
+            tracing::warn!("otel: failed to shut down traces: {}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            tracing::warn!("otel: failed to shut down metrics: {}", e);
+        }
+        if let Err(e) = self.logger_provider.shutdown() {
+            tracing::warn!("otel: failed to shut down logs: {}", e);
+        }
+    }
+}
+
+impl Drop for OtelGuard {
+    fn drop(&mut self) {
+        self.flush_and_shutdown();
+    }
+}
+
 // TODO: Get token hooked up to OTEL
 pub fn init_providers(
     service_name: &str,
     config: &Option<OtelConfig>,
-) -> Result<Option<(SdkTracerProvider, SdkLoggerProvider)>> {
+    sample_ratio: f64,
+) -> Result<Option<OtelGuard>> {
     if config.is_none() {
         bail!("otel: init_providers called but no config found");
     }
@@ -39,6 +98,9 @@ pub fn init_providers(
 
     let tracer_provider = SdkTracerProvider::builder()
         .with_resource(resource.clone())
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            sample_ratio,
+        ))))
         .with_batch_exporter(tracer_exporter)
         .build();
 
@@ -54,7 +116,8 @@ pub fn init_providers(
         .with_reader(PeriodicReader::builder(metrics_exporter).build())
         .with_resource(resource.clone())
         .build();
-    global::set_meter_provider(meter_provider);
+    global::set_meter_provider(meter_provider.clone());
+    crate::circuit::register_metrics();
 
     let logger_exporter = LogExporter::builder()
         .with_tonic()
@@ -67,18 +130,29 @@ pub fn init_providers(
         .with_resource(resource.clone())
         .build();
 
-    Ok(Some((tracer_provider, logger_provider)))
+    Ok(Some(OtelGuard {
+        tracer_provider,
+        meter_provider,
+        logger_provider,
+        done: false,
+    }))
 }
 
-pub fn apply_layers(router: Router) -> Router {
+/// Apply the trace and metrics layers, skipping requests for `excluded_paths`
+///
+/// Server spans continue the caller's trace from the W3C `traceparent` and
+/// `tracestate` headers, which Dapr sets from the CloudEvent when delivering
+/// pub/sub events and forwards on service invocation
+pub fn apply_layers(router: Router, excluded_paths: Arc<[String]>) -> Router {
     let metrics = HttpMetricsLayerBuilder::new().build();
 
     router
-        .layer(
+        .layer(ExcludePaths::new(
             TraceLayer::new_for_http()
                 .make_span_with(AxumOtelSpanCreator::new().level(tracing::Level::INFO))
                 .on_response(AxumOtelOnResponse::new().level(tracing::Level::INFO))
                 .on_failure(AxumOtelOnFailure::new()),
-        )
-        .layer(metrics)
+            excluded_paths.clone(),
+        ))
+        .layer(ExcludePaths::new(metrics, excluded_paths))
 }
@@ -0,0 +1,115 @@
+use crate::config::Config;
+use axum::extract::{FromRequestParts, Query, rejection::QueryRejection};
+use axum::http::request::Parts;
+use sea_orm::{ConnectionTrait, DbErr, EntityTrait, PaginatorTrait, QuerySelect, Select};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Page size used when the request doesn't specify a limit
+pub const DEFAULT_LIMIT: u64 = 20;
+
+/// Upper bound for the page size when `page_max_limit` isn't configured
+pub const DEFAULT_MAX_LIMIT: u64 = 100;
+
+/// Pagination query parameters as they appear on the wire
+///
+/// Reference this in `#[utoipa::path(params(PageParams))]` to document the query string
+#[derive(Debug, Clone, Default, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageParams {
+    /// Maximum number of items to return
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Number of items to skip
+    #[serde(default)]
+    pub offset: Option<u64>,
+}
+
+/// Pagination extractor for list endpoints (`?limit=&offset=`)
+///
+/// The limit is clamped to `page_max_limit` from microkit.yml
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub limit: u64,
+    pub offset: u64,
+}
+
+impl Page {
+    /// Build a page from raw parameters, clamping the limit to `max_limit`
+    pub fn new(params: PageParams, max_limit: u64) -> Self {
+        Self {
+            limit: params
+                .limit
+                .unwrap_or(DEFAULT_LIMIT)
+                .clamp(1, max_limit.max(1)),
+            offset: params.offset.unwrap_or(0),
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for Page
+where
+    S: Send + Sync,
+{
+    type Rejection = QueryRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<PageParams>::from_request_parts(parts, state).await?;
+
+        let max_limit = parts
+            .extensions
+            .get::<Config>()
+            .and_then(|config| config.page_max_limit)
+            .unwrap_or(DEFAULT_MAX_LIMIT);
+
+        Ok(Self::new(params, max_limit))
+    }
+}
+
+/// Paginated response wrapper
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Paginated<T> {
+    /// Items in this page
+    pub items: Vec<T>,
+    /// Total number of items across all pages
+    pub total: u64,
+    /// Offset of the next page, absent on the last page
+    pub next_cursor: Option<u64>,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, total: u64, page: Page) -> Self {
+        let next = page.offset + items.len() as u64;
+        Self {
+            next_cursor: (next < total).then_some(next),
+            items,
+            total,
+        }
+    }
+
+    /// Convert the items, e.g. from entity models into response contracts
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Paginated<U> {
+        Paginated {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Apply a page to a select, returning the page of models and the total count
+pub async fn paginate<E, C>(
+    select: Select<E>,
+    db: &C,
+    page: Page,
+) -> Result<Paginated<E::Model>, DbErr>
+where
+    E: EntityTrait,
+    E::Model: Send + Sync,
+    C: ConnectionTrait,
+{
+    let total = select.clone().count(db).await?;
+    let items = select.offset(page.offset).limit(page.limit).all(db).await?;
+
+    Ok(Paginated::new(items, total, page))
+}
@@ -1,3 +1,35 @@
+// Keep the per-feature list in README.md in step with these re-exports
+
+pub use crate::circuit::CircuitBreaker;
+pub use crate::client_ip::ClientIp;
 pub use crate::entity::CreationTracking;
-pub use crate::{MicroKit, ServicePort, auth::AuthenticatedUser, config::Config};
-pub use microkit_macros::*;
+pub use crate::envelope::Envelope;
+pub use crate::error::{ApiResult, AppError};
+pub use crate::events::DomainEvent;
+pub use crate::json::JsonBody;
+pub use crate::query::{Validate, ValidatedQuery};
+pub use crate::response::{Created, CreatedAt, NoContent};
+pub use crate::retry::RetryPolicy;
+pub use crate::router::WebSocketRoute;
+pub use crate::tenant::Tenant;
+pub use crate::{
+    CreationTracked, api_contract, discover_endpoints, event_contract, register_endpoints,
+};
+pub use crate::{MicroKit, ServicePort, config::Config};
+
+#[cfg(feature = "auth")]
+pub use crate::auth::{AuthenticatedUser, AuthenticatedUserOf};
+#[cfg(feature = "auth")]
+pub use crate::require_scopes;
+#[cfg(feature = "auth")]
+pub use crate::router::RequireRoles;
+
+#[cfg(feature = "dapr")]
+pub use crate::{dapr::DaprClient, pubsub::WithConsumerMetrics};
+
+#[cfg(feature = "database")]
+pub use crate::{
+    database::{Db, ReadDb, Txn, WithTransaction},
+    pagination::{Page, PageParams, Paginated},
+    tenant::TenantDb,
+};
@@ -0,0 +1,362 @@
+use crate::config::DeadLetterConfig;
+use crate::dapr::Dapr;
+use crate::error::{AppError, ErrorBody};
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::{HeaderMap, StatusCode, request::Parts};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// CloudEvents envelope attributes delivered alongside the event data
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CloudEventMetadata {
+    pub id: Option<String>,
+    pub source: Option<String>,
+    pub specversion: Option<String>,
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    pub datacontenttype: Option<String>,
+    pub subject: Option<String>,
+    pub time: Option<String>,
+    /// Dapr extension: topic the event was published to
+    pub topic: Option<String>,
+    /// Dapr extension: pub/sub component that delivered the event
+    pub pubsubname: Option<String>,
+    /// Dapr extension: W3C trace context of the publisher
+    pub traceparent: Option<String>,
+}
+
+/// Extractor for Dapr pub/sub deliveries
+///
+/// Unwraps the CloudEvents envelope and deserializes `data` into `T`. Bodies
+/// that aren't wrapped (raw payloads) are deserialized directly, with no metadata.
+/// Malformed envelopes and data are rejected with 400.
+#[derive(Debug, Clone)]
+pub struct CloudEvent<T> {
+    pub data: T,
+    /// `None` when the payload was delivered raw
+    pub metadata: Option<CloudEventMetadata>,
+}
+
+impl<T, S> FromRequest<S> for CloudEvent<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let body: Value = serde_json::from_slice(&bytes).map_err(|e| {
+            bad_request(
+                format!("Failed to parse the event as JSON: {}", e),
+                &headers,
+            )
+        })?;
+
+        let is_envelope = body
+            .as_object()
+            .is_some_and(|object| object.contains_key("specversion"));
+
+        if !is_envelope {
+            let data = serde_json::from_value(body).map_err(|e| {
+                bad_request(format!("Failed to deserialize the event: {}", e), &headers)
+            })?;
+            return Ok(CloudEvent {
+                data,
+                metadata: None,
+            });
+        }
+
+        let mut body = body;
+        let data = body
+            .as_object_mut()
+            .and_then(|object| object.remove("data"))
+            .ok_or_else(|| bad_request("CloudEvent is missing 'data'", &headers))?;
+
+        let metadata: CloudEventMetadata = serde_json::from_value(body)
+            .map_err(|e| bad_request(format!("Malformed CloudEvent envelope: {}", e), &headers))?;
+
+        let data = deserialize_data(data).map_err(|e| {
+            bad_request(
+                format!("Failed to deserialize the CloudEvent data: {}", e),
+                &headers,
+            )
+        })?;
+
+        Ok(CloudEvent {
+            data,
+            metadata: Some(metadata),
+        })
+    }
+}
+
+/// Deserialize `data`, falling back to parsing it as JSON text when published as a string
+fn deserialize_data<T: DeserializeOwned>(data: Value) -> serde_json::Result<T> {
+    match data {
+        Value::String(text) => match serde_json::from_value(Value::String(text.clone())) {
+            Ok(value) => Ok(value),
+            Err(e) => serde_json::from_str(&text).map_err(|_| e),
+        },
+        data => serde_json::from_value(data),
+    }
+}
+
+fn bad_request(error: impl Into<String>, headers: &HeaderMap) -> Response {
+    ErrorBody::new(error, headers).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+/// Outcome reported to Dapr by a pub/sub consumer
+///
+/// Dapr reads the `status` field of a 200 response; any other status code is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ConsumerStatus {
+    /// Processed, acknowledge the message
+    Success,
+    /// Redeliver the message according to the resiliency policy
+    Retry,
+    /// Acknowledge the message without processing it
+    Drop,
+}
+
+impl IntoResponse for ConsumerStatus {
+    fn into_response(self) -> Response {
+        let mut response = Json(serde_json::json!({ "status": self })).into_response();
+        // Read back by `with_consumer_metrics` rather than parsing the body
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Record throughput, outcome and latency for a router's pub/sub consumers
+///
+/// Exported through the otel meter provider, so nothing is recorded without
+/// the `otel` feature and an `otel` section:
+/// - `pubsub.consumer.events`, a counter by `topic` and `outcome`
+/// - `pubsub.consumer.duration`, a histogram in seconds by `topic` and `outcome`
+///
+/// `topic` comes from the CloudEvent envelope, or the route for raw payloads.
+/// `outcome` is what Dapr makes of the response: `success`, `retry` or `drop`.
+/// A 2xx without a [`ConsumerStatus`] counts as `success`, a 404 as `drop` and
+/// anything else as `retry`
+///
+/// ```ignore
+/// let consumers = OpenApiRouter::new()
+///     .routes(routes!(consumer_create_user))
+///     .with_consumer_metrics();
+/// ```
+pub trait WithConsumerMetrics {
+    fn with_consumer_metrics(self) -> Self;
+}
+
+impl<S> WithConsumerMetrics for utoipa_axum::router::OpenApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    #[cfg(feature = "otel")]
+    fn with_consumer_metrics(self) -> Self {
+        self.layer(axum::middleware::from_fn(record_consumer))
+    }
+
+    #[cfg(not(feature = "otel"))]
+    fn with_consumer_metrics(self) -> Self {
+        self
+    }
+}
+
+/// Instruments for consumer deliveries, created on first use so they bind to
+/// the meter provider installed during build
+#[cfg(feature = "otel")]
+struct ConsumerMetrics {
+    events: opentelemetry::metrics::Counter<u64>,
+    duration: opentelemetry::metrics::Histogram<f64>,
+}
+
+#[cfg(feature = "otel")]
+async fn record_consumer(request: Request, next: axum::middleware::Next) -> Response {
+    use axum::extract::MatchedPath;
+    use opentelemetry::KeyValue;
+
+    static METRICS: std::sync::OnceLock<ConsumerMetrics> = std::sync::OnceLock::new();
+    let metrics = METRICS.get_or_init(|| {
+        let meter = opentelemetry::global::meter("microkit");
+        ConsumerMetrics {
+            events: meter
+                .u64_counter("pubsub.consumer.events")
+                .with_description("Pub/sub deliveries handled by consumers, by topic and outcome")
+                .build(),
+            duration: meter
+                .f64_histogram("pubsub.consumer.duration")
+                .with_unit("s")
+                .with_description("Time for a consumer to handle a pub/sub delivery")
+                .build(),
+        }
+    });
+
+    let started = std::time::Instant::now();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    // Buffered within the body limit, the extractor reads the same bytes after
+    let (parts, body) = request.into_parts();
+    let (topic, response) =
+        match Bytes::from_request(Request::from_parts(parts.clone(), body), &()).await {
+            Ok(bytes) => {
+                let topic = serde_json::from_slice::<Delivery>(&bytes)
+                    .ok()
+                    .and_then(|delivery| delivery.topic);
+                let request = Request::from_parts(parts, axum::body::Body::from(bytes));
+                (topic, next.run(request).await)
+            }
+            Err(rejection) => (None, rejection.into_response()),
+        };
+
+    let attributes = [
+        KeyValue::new("topic", topic.unwrap_or(route)),
+        KeyValue::new("outcome", outcome(&response)),
+    ];
+    metrics.events.add(1, &attributes);
+    metrics
+        .duration
+        .record(started.elapsed().as_secs_f64(), &attributes);
+
+    response
+}
+
+/// Envelope attribute naming the topic, the rest is left to [`CloudEvent`]
+#[cfg(feature = "otel")]
+#[derive(Deserialize)]
+struct Delivery {
+    topic: Option<String>,
+}
+
+/// How Dapr treats a consumer response
+#[cfg(feature = "otel")]
+fn outcome(response: &Response) -> &'static str {
+    match response.extensions().get::<ConsumerStatus>() {
+        Some(ConsumerStatus::Success) => "success",
+        Some(ConsumerStatus::Retry) => "retry",
+        Some(ConsumerStatus::Drop) => "drop",
+        None if response.status().is_success() => "success",
+        None if response.status() == StatusCode::NOT_FOUND => "drop",
+        None => "retry",
+    }
+}
+
+/// Event published to the dead-letter topic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterEvent<T> {
+    /// The original event data
+    pub data: T,
+    /// Envelope of the original event, if it was wrapped
+    pub metadata: Option<CloudEventMetadata>,
+    /// Status the consumer failed with
+    pub status: u16,
+    pub error: String,
+}
+
+/// Runs consumer handlers, dead-lettering events that can never succeed
+///
+/// Handler errors decide whether Dapr redelivers the event:
+/// - 5xx is retryable (e.g. the database is down), so the error is returned and Dapr retries
+/// - 4xx is non-retryable (e.g. invalid or duplicate event), so the event is published to
+///   the `dead_letter` topic with the error and Dapr is told to drop it
+///
+/// Without a `dead_letter` topic configured, non-retryable events are logged and dropped.
+/// If publishing to the dead-letter topic fails, the event is retried instead of lost.
+///
+/// ```ignore
+/// pub async fn consume(dead_letter: DeadLetter, event: CloudEvent<MyEvent>) -> Response {
+///     dead_letter.consume(event, async |data| handle(data).await).await
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct DeadLetter {
+    target: Option<(Dapr, DeadLetterConfig)>,
+}
+
+impl DeadLetter {
+    pub fn new(dapr: Dapr, config: DeadLetterConfig) -> Self {
+        Self {
+            target: Some((dapr, config)),
+        }
+    }
+
+    /// Run `handler` on the event data and map its outcome to a Dapr consumer response
+    pub async fn consume<T, F>(self, event: CloudEvent<T>, handler: F) -> Response
+    where
+        T: Serialize,
+        F: AsyncFnOnce(&T) -> Result<(), AppError>,
+    {
+        let error = match handler(&event.data).await {
+            Ok(()) => return ConsumerStatus::Success.into_response(),
+            Err(error) if error.status.is_server_error() => return error.into_response(),
+            Err(error) => error,
+        };
+
+        let Some((mut dapr, config)) = self.target else {
+            tracing::warn!(status = %error.status, "pubsub: dropping event: {}", error.message);
+            return ConsumerStatus::Drop.into_response();
+        };
+
+        let dead_letter = DeadLetterEvent {
+            data: event.data,
+            metadata: event.metadata,
+            status: error.status.as_u16(),
+            error: error.message,
+        };
+
+        match dapr
+            .publish(&config.pubsub, &config.topic, &dead_letter)
+            .await
+        {
+            Ok(()) => {
+                tracing::warn!(
+                    status = %error.status,
+                    "pubsub: event dead-lettered to '{}': {}",
+                    config.topic,
+                    dead_letter.error
+                );
+                ConsumerStatus::Drop.into_response()
+            }
+            Err(e) => {
+                tracing::error!("pubsub: failed to dead-letter event, retrying: {:#}", e);
+                ConsumerStatus::Retry.into_response()
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for DeadLetter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeadLetter")
+            .field("config", &self.target.as_ref().map(|(_, config)| config))
+            .finish()
+    }
+}
+
+impl<S> FromRequestParts<S> for DeadLetter
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<DeadLetter>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::error::{ErrorBody, FieldError};
+use axum::extract::FromRequestParts;
+use axum::http::{StatusCode, request::Parts};
+use axum::response::Response;
+use serde::de::DeserializeOwned;
+
+/// Checks on a request type beyond what deserializing it enforces
+///
+/// ```ignore
+/// impl Validate for UserFilter {
+///     fn validate(&self) -> Result<(), Vec<FieldError>> {
+///         match &self.name {
+///             Some(name) if name.len() > 100 => Err(vec![FieldError {
+///                 field: "name".into(),
+///                 message: "must be at most 100 characters".into(),
+///             }]),
+///             _ => Ok(()),
+///         }
+///     }
+/// }
+/// ```
+pub trait Validate {
+    /// Every failing field, so clients can fix them all at once
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+/// Query string deserialized into `T` and checked with [`Validate`]
+///
+/// Rejects with 400 and the offending parameters in `fields`, both when a
+/// parameter is missing or malformed and when validation fails. Derive
+/// `IntoParams` on `T` and list it in `#[utoipa::path(params(T))]` to document it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+
+        let value: T = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let path = e.path().to_string();
+            let message = e.into_inner().to_string();
+            reject(
+                parts,
+                vec![FieldError {
+                    field: crate::json::field(&path, &message),
+                    message,
+                }],
+            )
+        })?;
+
+        value.validate().map_err(|fields| reject(parts, fields))?;
+
+        Ok(ValidatedQuery(value))
+    }
+}
+
+fn reject(parts: &Parts, fields: Vec<FieldError>) -> Response {
+    ErrorBody::new("Invalid query parameters", &parts.headers)
+        .with_fields(fields)
+        .into_response_with(StatusCode::BAD_REQUEST)
+}
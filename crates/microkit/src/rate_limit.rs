@@ -0,0 +1,214 @@
+use crate::client_ip::ClientIp;
+use crate::error::ErrorBody;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Buckets kept before pruning, which drops full (idle) buckets and then the
+/// least recently used until the map is back under 90% of this
+const MAX_BUCKETS: usize = 10_000;
+
+/// What requests are counted against
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitKey {
+    /// The client's IP address
+    #[default]
+    Ip,
+    /// The verified bearer token's `sub`, falling back to the IP for requests
+    /// without a valid token
+    User,
+}
+
+/// Token bucket limit for the routes matching `path`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimitRule {
+    /// Exact path, or a prefix ending in `*`, e.g. `/api/v1/users*`
+    pub path: String,
+    /// Methods the rule applies to (default: all)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub methods: Option<Vec<String>>,
+    /// Requests per second refilled into the bucket
+    pub rate: f64,
+    /// Requests allowed in a burst (default: `rate`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<f64>,
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+impl RateLimitRule {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        let path_matches = match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == self.path,
+        };
+        let method_matches = self
+            .methods
+            .as_ref()
+            .is_none_or(|methods| methods.iter().any(|m| m.eq_ignore_ascii_case(method)));
+
+        path_matches && method_matches
+    }
+
+    fn burst(&self) -> f64 {
+        self.burst.unwrap_or(self.rate).max(1.0)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Shared token buckets for all rules
+struct Limiter {
+    rules: Vec<RateLimitRule>,
+    buckets: Mutex<HashMap<(usize, String), Bucket>>,
+}
+
+impl Limiter {
+    /// Take a token, or return the seconds until one is available
+    fn acquire(&self, rule_index: usize, key: String) -> Result<(), u64> {
+        let rule = &self.rules[rule_index];
+        let burst = rule.burst();
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (rule_index, key);
+
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            self.prune(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rule.rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / rule.rate;
+            Err(wait.ceil().max(1.0) as u64)
+        }
+    }
+
+    /// Drop full buckets, then the least recently used down to 90% of `MAX_BUCKETS`,
+    /// so a sweep only happens once per tenth of the map's worth of new keys
+    fn prune(&self, buckets: &mut HashMap<(usize, String), Bucket>, now: Instant) {
+        let rules = &self.rules;
+        buckets.retain(|(index, _), bucket| {
+            let rule = &rules[*index];
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * rule.rate < rule.burst()
+        });
+
+        let keep = MAX_BUCKETS * 9 / 10;
+        if buckets.len() > keep {
+            let mut updated: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+            let evicted = buckets.len() - keep;
+            let (_, cutoff, _) = updated.select_nth_unstable(evicted - 1);
+            let cutoff = *cutoff;
+            buckets.retain(|_, bucket| bucket.updated > cutoff);
+        }
+    }
+}
+
+/// Apply the configured rate limits, skipping `excluded_paths`
+///
+/// Requests over the limit get 429 with `Retry-After` set to when the bucket
+/// next has a token, repeated as `retry_after_seconds` in the body. `user` keys
+/// need `auth` to verify the token, without it they fall back to the IP
+pub fn apply(
+    router: Router,
+    rules: Vec<RateLimitRule>,
+    excluded_paths: Arc<[String]>,
+    #[cfg(feature = "auth")] auth: Option<crate::auth::AuthConfig>,
+) -> Router {
+    let limiter = Arc::new(Limiter {
+        rules: rules.into_iter().filter(|rule| rule.rate > 0.0).collect(),
+        buckets: Mutex::new(HashMap::new()),
+    });
+
+    router.layer(crate::middleware::ExcludePaths::new(
+        axum::middleware::from_fn(move |req: Request<Body>, next: axum::middleware::Next| {
+            let limiter = limiter.clone();
+            #[cfg(feature = "auth")]
+            let auth = auth.clone();
+            async move {
+                let method = req.method().as_str();
+                let path = req.uri().path();
+
+                let Some(index) = limiter.rules.iter().position(|r| r.matches(method, path)) else {
+                    return next.run(req).await;
+                };
+
+                #[cfg(feature = "auth")]
+                let (req, key) = key_for(req, limiter.rules[index].key, auth.as_ref()).await;
+                #[cfg(not(feature = "auth"))]
+                let key = ip_key(&req);
+
+                match limiter.acquire(index, key) {
+                    Ok(()) => next.run(req).await,
+                    Err(retry_after) => too_many_requests(&req, retry_after),
+                }
+            }
+        }),
+        excluded_paths,
+    ))
+}
+
+/// The bucket key, with the request handed back after its token is read
+#[cfg(feature = "auth")]
+async fn key_for(
+    req: Request<Body>,
+    key: RateLimitKey,
+    auth: Option<&crate::auth::AuthConfig>,
+) -> (Request<Body>, String) {
+    let Some(auth) = auth.filter(|_| key == RateLimitKey::User) else {
+        let key = ip_key(&req);
+        return (req, key);
+    };
+
+    let (parts, body) = req.into_parts();
+    let sub = match auth.token(&parts) {
+        Some(token) => auth
+            .validate_token(&token)
+            .await
+            .ok()
+            .map(|claims| claims.sub),
+        None => None,
+    };
+    let req = Request::from_parts(parts, body);
+
+    match sub {
+        Some(sub) => (req, format!("sub:{}", sub)),
+        None => {
+            let key = ip_key(&req);
+            (req, key)
+        }
+    }
+}
+
+fn ip_key(req: &Request<Body>) -> String {
+    match req.extensions().get::<ClientIp>() {
+        Some(client_ip) => format!("ip:{}", client_ip),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+fn too_many_requests(req: &Request<Body>, retry_after: u64) -> Response {
+    ErrorBody::new("Too many requests", req.headers())
+        .into_retry_response(StatusCode::TOO_MANY_REQUESTS, retry_after)
+}
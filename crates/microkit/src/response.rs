@@ -0,0 +1,84 @@
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+/// 201 Created around a response body such as `Json`, for handlers that create a resource
+///
+/// Document it as `(status = 201, body = ...)` in `#[utoipa::path]`, with a
+/// `Location` header when using [`with_location`](Self::with_location):
+///
+/// ```ignore
+/// #[utoipa::path(
+///     post,
+///     path = PATH,
+///     responses(
+///         (status = 201, description = "Order created", body = OrderResponse,
+///             headers(("Location" = String, description = "URL of the new order"))),
+///     )
+/// )]
+/// pub async fn create_order(db: Db, JsonBody(order): JsonBody<OrderRequest>)
+///     -> Result<CreatedAt<Json<OrderResponse>>, AppError> {
+///     let order = active_model(order).insert(&*db).await?;
+///     Ok(Created(Json(order.clone().into())).with_location(format!("{}/{}", PATH, order.id)))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Created<T>(pub T);
+
+impl<T> Created<T> {
+    /// Point the `Location` header at the new resource, e.g. `/api/v1/orders/42`
+    ///
+    /// Relative locations resolve against the request's URL as the client sees it,
+    /// so include any path prefix from `public_url`
+    pub fn with_location(self, location: impl Into<String>) -> CreatedAt<T> {
+        CreatedAt {
+            body: self.0,
+            location: location.into(),
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Created<T> {
+    fn into_response(self) -> Response {
+        (StatusCode::CREATED, self.0).into_response()
+    }
+}
+
+/// [`Created`] with a `Location` header
+#[derive(Debug, Clone)]
+pub struct CreatedAt<T> {
+    body: T,
+    location: String,
+}
+
+impl<T: IntoResponse> IntoResponse for CreatedAt<T> {
+    fn into_response(self) -> Response {
+        match HeaderValue::from_str(&self.location) {
+            Ok(location) => (
+                StatusCode::CREATED,
+                [(header::LOCATION, location)],
+                self.body,
+            )
+                .into_response(),
+            Err(_) => {
+                // The resource was still created, so report that rather than failing
+                tracing::error!(
+                    "response: '{}' isn't a valid Location header, leaving it out",
+                    self.location
+                );
+                Created(self.body).into_response()
+            }
+        }
+    }
+}
+
+/// 204 No Content, for deletes and updates that return nothing
+///
+/// Document it as `(status = 204, description = "...")` in `#[utoipa::path]`
+#[derive(Debug, Clone, Copy)]
+pub struct NoContent;
+
+impl IntoResponse for NoContent {
+    fn into_response(self) -> Response {
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Result, bail};
+use axum::http::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Attempts per call, including the first, unless configured
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry unless configured, doubling for each one after
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest delay between attempts unless configured
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// Fraction of each delay randomized away unless configured
+pub const DEFAULT_JITTER: f64 = 0.5;
+
+static CONFIGURED: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Settings for the default policy under `retry`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RetryConfig {
+    /// Attempts per call, including the first, 1 turns retries off (default: 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    /// Milliseconds before the first retry, doubling for each one after (default: 100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_delay_ms: Option<u64>,
+    /// Longest wait between attempts in milliseconds (default: 2000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delay_ms: Option<u64>,
+    /// Fraction of each delay randomized away so callers don't retry in step, 0 to 1 (default: 0.5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<f64>,
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == Some(0) {
+            bail!("retry: max_attempts must be at least 1");
+        }
+        if let Some(jitter) = self.jitter
+            && !(0.0..=1.0).contains(&jitter)
+        {
+            bail!("retry: jitter {} must be between 0 and 1", jitter);
+        }
+        Ok(())
+    }
+}
+
+/// Why an attempt failed, as passed to the retryable predicate
+#[derive(Debug)]
+pub enum Failure<'a> {
+    /// The call was answered with an error status
+    Status(StatusCode),
+    /// The call got no answer, e.g. the connection was refused or reset, it timed
+    /// out, or the Dapr sidecar returned an error. The Dapr client doesn't expose
+    /// gRPC status codes, so its errors all land here
+    Error(&'a (dyn std::error::Error + Send + Sync + 'static)),
+}
+
+impl Failure<'_> {
+    /// The default predicate: errors without an answer, 408, 429, 502, 503 and 504
+    ///
+    /// 500 isn't retried, as it's usually a bug that fails the same way again
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Failure::Status(status) => matches!(
+                *status,
+                StatusCode::REQUEST_TIMEOUT
+                    | StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Failure::Error(_) => true,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Status(status) => write!(f, "{}", status),
+            Failure::Error(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Retries for outbound calls, with exponential backoff and jitter
+///
+/// The shared HTTP client and the Dapr helpers use the policy configured under
+/// `retry`, or the defaults. Calls that aren't idempotent, such as `POST`,
+/// `PATCH` and Dapr publishes, are made once unless the policy opts in with
+/// [`non_idempotent`](Self::non_idempotent), since a retry after a lost answer
+/// repeats the call. Override the policy for one request with an extension:
+///
+/// ```ignore
+/// let response = http::client()
+///     .post(dapr::invoke_url("billing", "invoices"))
+///     .with_extension(RetryPolicy::configured().non_idempotent())
+///     .json(&invoice)
+///     .send()
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    non_idempotent: bool,
+    retryable: fn(&Failure<'_>) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: DEFAULT_JITTER,
+            non_idempotent: false,
+            retryable: |failure| failure.is_transient(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The policy from `retry` in config, or the defaults before build
+    pub fn configured() -> Self {
+        CONFIGURED.get().cloned().unwrap_or_default()
+    }
+
+    /// A single attempt, for calls that must not be repeated
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// The policy described by `config`, unset values use the defaults
+    pub fn from_config(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            base_delay: config
+                .base_delay_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_BASE_DELAY),
+            max_delay: config
+                .max_delay_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_MAX_DELAY),
+            jitter: config.jitter.unwrap_or(DEFAULT_JITTER).clamp(0.0, 1.0),
+            ..Self::default()
+        }
+    }
+
+    /// Attempts per call, including the first
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Fraction of each delay randomized away, clamped to 0 to 1
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Retry calls that aren't idempotent too, for endpoints that deduplicate
+    pub fn non_idempotent(mut self) -> Self {
+        self.non_idempotent = true;
+        self
+    }
+
+    /// Decide which failures are retried (default: [`Failure::is_transient`])
+    pub fn retryable(mut self, retryable: fn(&Failure<'_>) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// Attempts allowed for a call, one when it isn't idempotent and the policy doesn't opt in
+    pub fn attempts(&self, idempotent: bool) -> u32 {
+        if idempotent || self.non_idempotent {
+            self.max_attempts
+        } else {
+            1
+        }
+    }
+
+    pub fn is_retryable(&self, failure: &Failure<'_>) -> bool {
+        (self.retryable)(failure)
+    }
+
+    /// Wait before retry number `retry`, counting from 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        backoff.mul_f64(1.0 - self.jitter * rand::random::<f64>())
+    }
+
+    /// Run `call` until it succeeds, fails in a way that isn't retryable or runs
+    /// out of attempts, returning the last outcome
+    ///
+    /// `failure` describes an error for the predicate
+    pub async fn run<T, E, F>(
+        &self,
+        idempotent: bool,
+        mut call: impl FnMut() -> F,
+        failure: impl Fn(&E) -> Failure<'_>,
+    ) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let attempts = self.attempts(idempotent);
+        let mut attempt = 1;
+        loop {
+            let error = match call().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let failure = failure(&error);
+            if attempt >= attempts || !self.is_retryable(&failure) {
+                return Err(error);
+            }
+
+            let delay = self.delay(attempt);
+            tracing::warn!(
+                "retry: attempt {}/{} failed, retrying in {}ms: {}",
+                attempt,
+                attempts,
+                delay.as_millis(),
+                failure
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether repeating a request with `method` has the same effect as making it once
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Install the policy from config as the default, called during build
+pub(crate) fn init(config: Option<&RetryConfig>) {
+    if let Some(config) = config {
+        let _ = CONFIGURED.set(RetryPolicy::from_config(config));
+    }
+}
@@ -1,8 +1,26 @@
+use crate::config::TagConfig;
+use anyhow::{Result, bail};
+use axum::handler::Handler;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+#[cfg(feature = "auth")]
+use std::sync::Arc;
 use utoipa::OpenApi;
+use utoipa::openapi::{
+    self, RefOr,
+    extensions::ExtensionsBuilder,
+    external_docs::ExternalDocs,
+    path::{HttpMethod, Operation, OperationBuilder, PathItem},
+    response::Response,
+    schema::{AdditionalProperties, ArrayItems, Object, OneOfBuilder, Schema, SchemaType, Type},
+    server::Server,
+    tag::Tag,
+};
 use utoipa_axum::router::OpenApiRouter;
 
 #[cfg(feature = "auth")]
-use utoipa::openapi::security::{OpenIdConnect, SecurityScheme};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, OpenIdConnect, SecurityScheme};
 
 #[cfg(feature = "auth")]
 pub fn generate_router_with_auth(
@@ -13,12 +31,24 @@ pub fn generate_router_with_auth(
     let mut router = generate_router(title, description);
     let mut components = utoipa::openapi::ComponentsBuilder::new();
 
+    // Manually pasted tokens
+    components = components.security_scheme(
+        "bearer",
+        SecurityScheme::Http(
+            HttpBuilder::new()
+                .scheme(HttpAuthScheme::Bearer)
+                .bearer_format("JWT")
+                .build(),
+        ),
+    );
+
+    // Interactive login via the issuer's discovery document
     if let Some(issuer_url) = &issuer {
         components = components.security_scheme(
             "oidc",
             SecurityScheme::OpenIdConnect(OpenIdConnect::new(format!(
                 "{}/.well-known/openid-configuration",
-                issuer_url
+                issuer_url.trim_end_matches('/')
             ))),
         );
     }
@@ -39,3 +69,559 @@ pub fn generate_router(title: &str, description: &Option<String>) -> OpenApiRout
 
     OpenApiRouter::with_openapi(openapi)
 }
+
+/// WebSocket routes for an [`OpenApiRouter`]
+///
+/// utoipa can't describe WebSockets, so the route is listed as a `GET` with a
+/// `101` response and `x-websocket: true`, keeping it visible in the docs.
+///
+/// ```ignore
+/// use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+///
+/// async fn echo(ws: WebSocketUpgrade) -> Response {
+///     ws.on_upgrade(|mut socket: WebSocket| async move {
+///         while let Some(Ok(message)) = socket.recv().await {
+///             if matches!(message, Message::Close(_)) || socket.send(message).await.is_err() {
+///                 break;
+///             }
+///         }
+///     })
+/// }
+///
+/// service.add_route(OpenApiRouter::new().ws_route("/ws/echo", echo, "Echo messages back"))?;
+/// ```
+///
+/// Middleware runs once, for the upgrade request. Auth works as for any handler:
+/// take `AuthenticatedUser` alongside `WebSocketUpgrade` and the upgrade is
+/// rejected with 401 before the socket opens. Browsers can't set headers on a
+/// WebSocket, so browser clients need the token passed another way. Open sockets
+/// aren't counted by `max_concurrent_requests` or `rate_limits`
+pub trait WebSocketRoute<S> {
+    fn ws_route<H, T>(self, path: &str, handler: H, summary: &str) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static;
+}
+
+impl<S> WebSocketRoute<S> for OpenApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn ws_route<H, T>(self, path: &str, handler: H, summary: &str) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        let operation = OperationBuilder::new()
+            .summary(Some(summary))
+            .description(Some("WebSocket endpoint, connect with an HTTP upgrade"))
+            .response("101", Response::new("Switching Protocols"))
+            .extensions(Some(
+                ExtensionsBuilder::new().add("x-websocket", true).build(),
+            ))
+            .build();
+
+        let mut router = self.route(path, axum::routing::get(handler));
+        router
+            .get_openapi_mut()
+            .paths
+            .add_path_operation(path, vec![HttpMethod::Get], operation);
+        router
+    }
+}
+
+/// Role-based access for every route of an [`OpenApiRouter`]
+///
+/// ```ignore
+/// let admin = OpenApiRouter::new()
+///     .routes(routes!(delete_user))
+///     .require_roles(&["admin"]);
+/// ```
+///
+/// Requests without a valid token get 401, and those whose user has none of the
+/// roles get 403. Like any layer it only covers routes added before it.
+///
+/// The operations are documented as needing the `bearer` or `oidc` scheme, with
+/// the roles listed under `x-required-roles`. Operations that already declare
+/// `security`, e.g. through `require_scopes`, keep their own requirement since
+/// it's the more specific one; only the roles are added to them
+#[cfg(feature = "auth")]
+pub trait RequireRoles {
+    fn require_roles(self, roles: &[&str]) -> Self;
+}
+
+#[cfg(feature = "auth")]
+impl<S> RequireRoles for OpenApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn require_roles(mut self, roles: &[&str]) -> Self {
+        use utoipa::openapi::security::SecurityRequirement;
+
+        let roles: Arc<[String]> = roles.iter().map(|role| role.to_string()).collect();
+
+        for item in self.get_openapi_mut().paths.paths.values_mut() {
+            for (_, operation) in operations_mut(item) {
+                operation.security.get_or_insert_with(|| {
+                    vec![
+                        SecurityRequirement::new("bearer", Vec::<String>::new()),
+                        SecurityRequirement::new("oidc", Vec::<String>::new()),
+                    ]
+                });
+                operation
+                    .extensions
+                    .get_or_insert_with(Default::default)
+                    .insert("x-required-roles".to_string(), serde_json::json!(&*roles));
+            }
+        }
+
+        self.layer(axum::middleware::from_fn_with_state(roles, check_roles))
+    }
+}
+
+/// Reject requests whose user has none of `roles`
+#[cfg(feature = "auth")]
+async fn check_roles(
+    axum::extract::State(roles): axum::extract::State<Arc<[String]>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::extract::FromRequestParts;
+    use axum::response::IntoResponse;
+
+    let (mut parts, body) = request.into_parts();
+    let user = match crate::auth::AuthenticatedUser::from_request_parts(&mut parts, &()).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    if !roles.iter().any(|role| user.has_role(role)) {
+        return crate::error::AppError::forbidden("Missing required role").into_response();
+    }
+
+    next.run(axum::extract::Request::from_parts(parts, body))
+        .await
+}
+
+/// Set the OpenAPI `servers` entry so "Try it out" targets the right origin
+///
+/// Prefers the configured public URL (which may include a path prefix),
+/// falling back to the bound address
+pub fn apply_servers(
+    api: &mut openapi::OpenApi,
+    public_url: Option<&str>,
+    address: Option<&SocketAddr>,
+) {
+    let url = match (public_url, address) {
+        (Some(public_url), _) => public_url.trim_end_matches('/').to_string(),
+        (None, Some(address)) => format!("http://{}", address),
+        (None, None) => return,
+    };
+
+    api.servers = Some(vec![Server::new(url)]);
+}
+
+/// Replace the document's tags with the declared ones, in their order
+///
+/// Tags used by operations or already in the document but not declared follow,
+/// with just their name. Declared tags nothing uses are left out so the
+/// documentors don't show empty groups
+pub fn apply_tags(api: &mut openapi::OpenApi, declared: &[TagConfig]) {
+    let mut present: Vec<Tag> = api.tags.take().unwrap_or_default();
+    let used = api
+        .paths
+        .paths
+        .values()
+        .flat_map(|item| operations(item).map(|(_, operation)| operation))
+        .flat_map(|operation| operation.tags.iter().flatten());
+
+    for name in used {
+        if !present.iter().any(|tag| &tag.name == name) {
+            present.push(Tag::new(name));
+        }
+    }
+
+    let mut tags: Vec<Tag> = declared
+        .iter()
+        .filter(|config| present.iter().any(|tag| tag.name == config.name))
+        .map(|config| {
+            let mut tag = Tag::new(&config.name);
+            tag.description = config.description.clone();
+            tag.external_docs = config.external_docs.as_ref().map(|docs| {
+                let mut external_docs = ExternalDocs::new(&docs.url);
+                external_docs.description = docs.description.clone();
+                external_docs
+            });
+            tag
+        })
+        .collect();
+
+    for tag in present {
+        if !tags.iter().any(|known| known.name == tag.name) {
+            tags.push(tag);
+        }
+    }
+
+    api.tags = Some(tags);
+}
+
+/// Normalize the document for client generators, set by `docs.client_conventions`
+///
+/// - Operations without an `operationId` get one from their method and path,
+///   e.g. `get_ws_echo`, as generators otherwise invent unstable names
+/// - Objects sent as request bodies, and the components they reference, get
+///   `additionalProperties: false` so generated types are closed. Maps keep their
+///   value schema and `allOf` members are left open, as each would forbid the
+///   others' fields
+/// - Properties left out of `required` also accept `null`, so generators treat
+///   every optional field alike rather than only those declared as `Option`
+pub fn apply_client_conventions(api: &mut openapi::OpenApi) {
+    let mut referenced = Vec::new();
+
+    for (path, item) in api.paths.paths.iter_mut() {
+        for (method, operation) in operations_mut(item) {
+            if operation.operation_id.is_none() {
+                operation.operation_id = Some(identifier(&format!("{} {}", method, path)));
+            }
+
+            if let Some(body) = &mut operation.request_body {
+                for content in body.content.values_mut() {
+                    if let Some(schema) = &mut content.schema {
+                        close_objects(schema, &mut referenced);
+                        nullable_optionals(schema);
+                    }
+                }
+            }
+
+            for response in operation.responses.responses.values_mut() {
+                if let RefOr::T(response) = response {
+                    for content in response.content.values_mut() {
+                        if let Some(schema) = &mut content.schema {
+                            nullable_optionals(schema);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let Some(components) = &mut api.components else {
+        return;
+    };
+
+    let mut closed = BTreeSet::new();
+    while let Some(name) = referenced.pop() {
+        if closed.insert(name.clone())
+            && let Some(schema) = components.schemas.get_mut(&name)
+        {
+            close_objects(schema, &mut referenced);
+        }
+    }
+
+    for schema in components.schemas.values_mut() {
+        nullable_optionals(schema);
+    }
+}
+
+/// Forbid unknown fields on the objects in `schema`, collecting referenced components
+fn close_objects(schema: &mut RefOr<Schema>, referenced: &mut Vec<String>) {
+    let schema = match schema {
+        RefOr::Ref(reference) => {
+            if let Some(name) = reference.ref_location.strip_prefix("#/components/schemas/") {
+                referenced.push(name.to_string());
+            }
+            return;
+        }
+        RefOr::T(schema) => schema,
+    };
+
+    match schema {
+        Schema::Object(object) => {
+            if object.additional_properties.is_none() && has_type(&object.schema_type, Type::Object)
+            {
+                object.additional_properties =
+                    Some(Box::new(AdditionalProperties::FreeForm(false)));
+            }
+            for property in object.properties.values_mut() {
+                close_objects(property, referenced);
+            }
+        }
+        Schema::Array(array) => {
+            if let ArrayItems::RefOrSchema(items) = &mut array.items {
+                close_objects(items, referenced);
+            }
+        }
+        Schema::OneOf(one_of) => {
+            for item in &mut one_of.items {
+                close_objects(item, referenced);
+            }
+        }
+        Schema::AnyOf(any_of) => {
+            for item in &mut any_of.items {
+                close_objects(item, referenced);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Let properties missing from `required` be `null`, throughout `schema`
+fn nullable_optionals(schema: &mut RefOr<Schema>) {
+    let RefOr::T(schema) = schema else {
+        return;
+    };
+
+    match schema {
+        Schema::Object(object) => {
+            for (name, property) in object.properties.iter_mut() {
+                nullable_optionals(property);
+                if !object.required.contains(name) {
+                    nullable(property);
+                }
+            }
+        }
+        Schema::Array(array) => {
+            if let ArrayItems::RefOrSchema(items) = &mut array.items {
+                nullable_optionals(items);
+            }
+        }
+        Schema::OneOf(one_of) => one_of.items.iter_mut().for_each(nullable_optionals),
+        Schema::AllOf(all_of) => all_of.items.iter_mut().for_each(nullable_optionals),
+        Schema::AnyOf(any_of) => any_of.items.iter_mut().for_each(nullable_optionals),
+        _ => {}
+    }
+}
+
+/// Widen `schema` to accept `null`, as utoipa does for `Option` fields
+fn nullable(schema: &mut RefOr<Schema>) {
+    match schema {
+        RefOr::T(Schema::Object(object)) => {
+            add_null(&mut object.schema_type);
+            if let Some(values) = &mut object.enum_values
+                && !values.contains(&serde_json::Value::Null)
+            {
+                values.push(serde_json::Value::Null);
+            }
+        }
+        RefOr::T(Schema::Array(array)) => add_null(&mut array.schema_type),
+        RefOr::T(Schema::OneOf(one_of)) => {
+            if !one_of.items.iter().any(is_null) {
+                one_of.items.insert(0, Object::with_type(Type::Null).into());
+            }
+        }
+        _ => {
+            let inner = std::mem::replace(schema, Object::with_type(Type::Null).into());
+            *schema = OneOfBuilder::new()
+                .item(Object::with_type(Type::Null))
+                .item(inner)
+                .into();
+        }
+    }
+}
+
+fn add_null(schema_type: &mut SchemaType) {
+    match schema_type {
+        SchemaType::Type(Type::Null) | SchemaType::AnyValue => {}
+        SchemaType::Type(single) => {
+            *schema_type = SchemaType::Array(vec![single.clone(), Type::Null])
+        }
+        SchemaType::Array(types) => {
+            if !types.contains(&Type::Null) {
+                types.push(Type::Null);
+            }
+        }
+    }
+}
+
+fn has_type(schema_type: &SchemaType, wanted: Type) -> bool {
+    match schema_type {
+        SchemaType::Type(single) => *single == wanted,
+        SchemaType::Array(types) => types.contains(&wanted),
+        SchemaType::AnyValue => false,
+    }
+}
+
+fn is_null(schema: &RefOr<Schema>) -> bool {
+    matches!(schema, RefOr::T(Schema::Object(object)) if object.schema_type == SchemaType::Type(Type::Null))
+}
+
+/// How operation ids are named in the generated spec
+///
+/// utoipa uses the handler's function name, so handlers with the same name in
+/// different modules collide. Ids that still collide after naming fail startup
+/// unless `docs.on_conflict` is `warn`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationIdStrategy {
+    /// The function name, e.g. `create_user`
+    #[default]
+    Function,
+    /// The first tag and the function name, e.g. `users_api_create_user`
+    Tag,
+    /// The method and path, unique whenever routes are, e.g. `post_api_v1_users`
+    MethodPath,
+}
+
+/// Rename the operation ids of `route` according to `strategy`
+pub fn name_operations(route: &mut OpenApiRouter, strategy: OperationIdStrategy) {
+    for (path, item) in route.get_openapi_mut().paths.paths.iter_mut() {
+        for (method, operation) in operations_mut(item) {
+            let id = match strategy {
+                OperationIdStrategy::Function => return,
+                OperationIdStrategy::Tag => {
+                    let tag = operation.tags.as_ref().and_then(|tags| tags.first());
+                    match (tag, &operation.operation_id) {
+                        (Some(tag), Some(id)) => format!("{}_{}", identifier(tag), id),
+                        _ => continue,
+                    }
+                }
+                OperationIdStrategy::MethodPath => identifier(&format!("{} {}", method, path)),
+            };
+            operation.operation_id = Some(id);
+        }
+    }
+}
+
+/// Lowercase `text`, joining runs of other characters with single underscores
+fn identifier(text: &str) -> String {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// How build treats conflicts in the merged OpenAPI document
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Fail startup, listing every conflict
+    #[default]
+    Error,
+    /// Log each conflict and serve the document as merged
+    Warn,
+}
+
+/// Merge `route` into `router`, failing on duplicate method + path
+///
+/// Axum would otherwise panic or one route would silently shadow the other.
+/// Duplicate operation ids and schema names are added to `conflicts` instead, as
+/// they only break the document
+pub fn merge(
+    router: OpenApiRouter,
+    route: OpenApiRouter,
+    conflicts: &mut Vec<String>,
+) -> Result<OpenApiRouter> {
+    let duplicates = duplicate_routes(router.get_openapi(), route.get_openapi());
+    if !duplicates.is_empty() {
+        bail!("Duplicate routes: {}", duplicates.join(", "));
+    }
+
+    conflicts.extend(document_conflicts(
+        router.get_openapi(),
+        route.get_openapi(),
+    ));
+    Ok(router.merge(route))
+}
+
+/// Describe the method + path pairs declared by both documents
+pub fn duplicate_routes(existing: &openapi::OpenApi, incoming: &openapi::OpenApi) -> Vec<String> {
+    let mut duplicates = Vec::new();
+
+    for (path, item) in &incoming.paths.paths {
+        let Some(existing_item) = existing.paths.paths.get(path) else {
+            continue;
+        };
+        for (method, _) in operations(item) {
+            if operations(existing_item).any(|(existing_method, _)| existing_method == method) {
+                duplicates.push(format!("{} {}", method, path));
+            }
+        }
+    }
+
+    duplicates
+}
+
+/// Describe the operation ids used by both documents and the schemas they name
+/// alike but define differently
+///
+/// utoipa keeps one operation per id for clients and the last schema of each
+/// name, so either leaves the merged document wrong for one of the routes
+pub fn document_conflicts(existing: &openapi::OpenApi, incoming: &openapi::OpenApi) -> Vec<String> {
+    let mut conflicts = Vec::new();
+
+    let existing_ids: BTreeMap<&str, (&str, &str)> = existing
+        .paths
+        .paths
+        .iter()
+        .flat_map(|(path, item)| {
+            operations(item).filter_map(move |(method, operation)| {
+                Some((operation.operation_id.as_deref()?, (method, path.as_str())))
+            })
+        })
+        .collect();
+
+    for (path, item) in &incoming.paths.paths {
+        for (method, operation) in operations(item) {
+            if let Some(id) = operation.operation_id.as_deref()
+                && let Some((other_method, other_path)) = existing_ids.get(id)
+            {
+                conflicts.push(format!(
+                    "operationId '{}' on {} {} and {} {}",
+                    id, other_method, other_path, method, path
+                ));
+            }
+        }
+    }
+
+    if let (Some(existing_components), Some(incoming_components)) =
+        (&existing.components, &incoming.components)
+    {
+        for (name, schema) in &incoming_components.schemas {
+            if existing_components
+                .schemas
+                .get(name)
+                .is_some_and(|other| other != schema)
+            {
+                let paths = incoming.paths.paths.keys().cloned().collect::<Vec<_>>();
+                conflicts.push(format!(
+                    "schema '{}' defined differently by the routes for {}",
+                    name,
+                    paths.join(", ")
+                ));
+            }
+        }
+    }
+
+    conflicts
+}
+
+fn operations(item: &PathItem) -> impl Iterator<Item = (&'static str, &Operation)> {
+    [
+        ("GET", &item.get),
+        ("PUT", &item.put),
+        ("POST", &item.post),
+        ("DELETE", &item.delete),
+        ("OPTIONS", &item.options),
+        ("HEAD", &item.head),
+        ("PATCH", &item.patch),
+        ("TRACE", &item.trace),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| Some((method, operation.as_ref()?)))
+}
+
+fn operations_mut(item: &mut PathItem) -> impl Iterator<Item = (&'static str, &mut Operation)> {
+    [
+        ("GET", &mut item.get),
+        ("PUT", &mut item.put),
+        ("POST", &mut item.post),
+        ("DELETE", &mut item.delete),
+        ("OPTIONS", &mut item.options),
+        ("HEAD", &mut item.head),
+        ("PATCH", &mut item.patch),
+        ("TRACE", &mut item.trace),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| Some((method, operation.as_mut()?)))
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value, json};
+use std::path::{Path, PathBuf};
+use utoipa::ToSchema;
+
+/// JSON Schema dialect of the exported documents, which OpenAPI 3.1 schemas follow
+pub const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+const COMPONENT_PREFIX: &str = "#/components/schemas/";
+
+/// Standalone JSON Schema for `T`, for consumers that don't read OpenAPI
+///
+/// Schemas `T` refers to are inlined under `$defs`, so the document has no
+/// outside references
+pub fn json_schema<T: ToSchema>() -> Result<Value> {
+    let mut dependencies = Vec::new();
+    T::schemas(&mut dependencies);
+
+    let mut defs = Map::new();
+    for (name, schema) in dependencies {
+        defs.insert(name, serde_json::to_value(schema)?);
+    }
+
+    let mut document = match serde_json::to_value(T::schema())? {
+        Value::Object(object) => object,
+        other => Map::from_iter([("allOf".to_string(), json!([other]))]),
+    };
+    document.insert("$schema".to_string(), json!(JSON_SCHEMA_DIALECT));
+    document.insert("title".to_string(), json!(T::name()));
+    if !defs.is_empty() {
+        document.insert("$defs".to_string(), Value::Object(defs));
+    }
+
+    let mut document = Value::Object(document);
+    rewrite_refs(&mut document);
+    Ok(document)
+}
+
+/// Write [`json_schema`] of `T` to `{dir}/{name}.schema.json`, creating `dir` if needed
+///
+/// ```ignore
+/// microkit::schema::write_json_schema::<UserCreatedEvent>(Path::new("schemas"))?;
+/// ```
+pub fn write_json_schema<T: ToSchema>(dir: &Path) -> Result<PathBuf> {
+    let document = json_schema::<T>()?;
+
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("schema: couldn't create '{}'", dir.display()))?;
+    let path = dir.join(format!("{}.schema.json", T::name()));
+    std::fs::write(&path, serde_json::to_string_pretty(&document)? + "\n")
+        .with_context(|| format!("schema: couldn't write '{}'", path.display()))?;
+
+    Ok(path)
+}
+
+/// Point OpenAPI component references at `$defs`
+fn rewrite_refs(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if key == "$ref"
+                    && let Value::String(reference) = value
+                    && let Some(name) = reference.strip_prefix(COMPONENT_PREFIX)
+                {
+                    *reference = format!("#/$defs/{}", name);
+                } else {
+                    rewrite_refs(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
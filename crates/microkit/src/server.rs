@@ -0,0 +1,285 @@
+use crate::config::ServerConfig;
+use anyhow::Result;
+use axum::Router;
+use axum::extract::{ConnectInfo, Request};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+/// Cleanup run on a blocking thread once the server has stopped
+pub(crate) type StopHook = Box<dyn FnOnce() + Send>;
+
+/// Async cleanup registered with `MicroKitBuilder::on_shutdown`
+pub type ShutdownHook = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Time each shutdown hook gets before it's abandoned, unless configured
+pub const DEFAULT_SHUTDOWN_HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long `start` keeps serving after the shutdown signal with readiness failing,
+/// unless configured
+pub const DEFAULT_PRE_SHUTDOWN_DELAY: Duration = Duration::from_secs(5);
+
+/// Set once shutdown begins, so `/status/ready` answers 503 while requests drain
+///
+/// Found in the request extensions of a server started with `start` or `start_with_handle`,
+/// read it with `context::is_draining`
+#[derive(Debug, Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn begin(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Teardown run once the server has stopped accepting and drained its connections
+pub(crate) struct Cleanup {
+    /// Registered with `on_shutdown`, run newest first
+    pub hooks: Vec<ShutdownHook>,
+    pub hook_timeout: Duration,
+    /// Subsystem teardown run after the hooks, so their telemetry is still flushed
+    pub on_stop: Vec<StopHook>,
+}
+
+impl Cleanup {
+    async fn run(self) -> Result<()> {
+        let count = self.hooks.len();
+        for (index, hook) in self.hooks.into_iter().rev().enumerate() {
+            if tokio::time::timeout(self.hook_timeout, hook).await.is_err() {
+                tracing::warn!(
+                    "server: shutdown hook {} of {} abandoned after {}s",
+                    index + 1,
+                    count,
+                    self.hook_timeout.as_secs()
+                );
+            }
+        }
+
+        for hook in self.on_stop {
+            tokio::task::spawn_blocking(hook).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Handle to a running service, returned by `MicroKit::start_with_handle`
+///
+/// Dropping the handle detaches the server; it keeps running in the background.
+pub struct ServerHandle {
+    address: SocketAddr,
+    draining: Draining,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn spawn(
+        listener: TcpListener,
+        address: SocketAddr,
+        router: Router,
+        settings: ServerConfig,
+        cleanup: Cleanup,
+    ) -> Self {
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let draining = Draining::default();
+        let router = router.layer(axum::Extension(draining.clone()));
+
+        let task = tokio::spawn(async move {
+            serve(listener, router, &settings, async move {
+                // A dropped sender means the handle was detached, keep serving
+                if signal.await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            })
+            .await;
+
+            cleanup.run().await
+        });
+
+        Self {
+            address,
+            draining,
+            shutdown,
+            task,
+        }
+    }
+
+    /// The address the server actually bound to
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The port the server actually bound to, as assigned by the OS for `ServicePort::Ephemeral`
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    /// Wait until the server stops
+    pub async fn wait(self) -> Result<()> {
+        self.task.await?
+    }
+
+    /// Wait until the server stops on its own, e.g. its task panicked, keeping the handle
+    pub(crate) async fn stopped(&mut self) -> Result<()> {
+        (&mut self.task).await?
+    }
+
+    /// Gracefully shut down the server, waiting for in-flight requests to complete
+    pub async fn shutdown(self) -> Result<()> {
+        self.draining.begin();
+        let _ = self.shutdown.send(());
+        self.task.await?
+    }
+
+    /// Fail readiness, keep serving for `delay` so load balancers stop routing here,
+    /// then shut down like `shutdown`
+    ///
+    /// Resolving `skip` ends the delay early, e.g. a second Ctrl+C
+    pub async fn shutdown_after(
+        self,
+        delay: Duration,
+        skip: impl Future<Output = ()>,
+    ) -> Result<()> {
+        self.draining.begin();
+
+        if !delay.is_zero() {
+            tracing::info!(
+                "server: readiness failing, draining in {}s",
+                delay.as_secs_f32()
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = skip => tracing::info!("server: second shutdown signal, draining now"),
+            }
+        }
+
+        self.shutdown().await
+    }
+}
+
+/// Accept connections until `signal` resolves, then wait for open connections to finish
+///
+/// Equivalent to `axum::serve` with connect info, with the connection builder
+/// configured from `settings`
+async fn serve(
+    listener: TcpListener,
+    router: Router,
+    settings: &ServerConfig,
+    signal: impl Future<Output = ()>,
+) {
+    let builder = connection_builder(settings);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+
+    loop {
+        let (stream, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually running out of file descriptors, back off instead of spinning
+                    tracing::error!("server: failed to accept connection: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+
+        let router = router.clone();
+        let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(remote));
+            router.clone().oneshot(request)
+        });
+
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!("server: connection from {} closed: {}", remote, e);
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+}
+
+fn connection_builder(settings: &ServerConfig) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+
+    if let Some(keep_alive) = settings.keep_alive {
+        builder.http1().keep_alive(keep_alive);
+    }
+
+    if let Some(interval) = settings.http2_keep_alive_interval_secs {
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(Duration::from_secs(interval));
+    }
+
+    if let Some(timeout) = settings.http2_keep_alive_timeout_secs {
+        builder
+            .http2()
+            .keep_alive_timeout(Duration::from_secs(timeout));
+    }
+
+    if let Some(max) = settings.http2_max_concurrent_streams {
+        builder.http2().max_concurrent_streams(max);
+    }
+
+    match settings.http2 {
+        Some(false) => builder.http1_only(),
+        _ => builder,
+    }
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on unix
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("server: failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("server: failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
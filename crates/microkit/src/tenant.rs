@@ -0,0 +1,324 @@
+use crate::config::Config;
+use crate::error::ErrorBody;
+use axum::extract::FromRequestParts;
+use axum::http::{StatusCode, header, request::Parts};
+use axum::response::Response;
+#[cfg(feature = "database")]
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "database")]
+use std::collections::HashMap;
+#[cfg(feature = "database")]
+use std::ops::Deref;
+#[cfg(feature = "database")]
+use std::sync::Arc;
+
+/// Header read by the `header` source unless configured
+pub const DEFAULT_TENANT_HEADER: &str = "X-Tenant-Id";
+
+/// Claim read by the `claim` source unless configured
+pub const DEFAULT_TENANT_CLAIM: &str = "tenant";
+
+/// Where the tenant id is read from
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TenantSource {
+    /// A request header, `X-Tenant-Id` unless configured
+    #[default]
+    Header,
+    /// A claim of the validated bearer token, requires the `auth` feature
+    Claim,
+    /// The first label of the `Host` header, e.g. `acme` for `acme.example.com`
+    Subdomain,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TenancyConfig {
+    /// Where the tenant id is read from (default: header)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<TenantSource>,
+    /// Header carrying the tenant id (default: X-Tenant-Id)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    /// Token claim carrying the tenant id (default: tenant)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim: Option<String>,
+    /// Known tenants by id, requests for any other tenant get 404
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+/// Per-tenant settings, unset database values fall back to the service's
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TenantConfig {
+    /// Database server URL (default: database_url)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_url: Option<String>,
+    /// Database name (default: the tenant id)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_name: Option<String>,
+    /// Postgres schema for the tenant's tables (default: database_schema)
+    #[cfg(feature = "database")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_schema: Option<String>,
+}
+
+impl TenancyConfig {
+    /// Fail startup on settings that can't work in this build
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if cfg!(not(feature = "auth")) && self.source == Some(TenantSource::Claim) {
+            anyhow::bail!("tenancy: source 'claim' requires the auth feature");
+        }
+        Ok(())
+    }
+}
+
+/// Tenant the request is for, resolved according to `tenancy` in config
+///
+/// Rejects with 400 when the header or subdomain is missing, 403 when the token
+/// has no tenant claim and 404 when the tenant isn't configured
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<S> FromRequestParts<S> for Tenant
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(tenancy) = parts
+            .extensions
+            .get::<Config>()
+            .and_then(|config| config.tenancy.clone())
+        else {
+            tracing::error!(
+                "Tenancy config not found in request extensions. \
+                     Did you forget to add tenancy to microkit.yml?"
+            );
+            return Err(reject(
+                parts,
+                "Tenancy not configured",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        };
+
+        let id = match tenancy.source.unwrap_or_default() {
+            TenantSource::Header => {
+                let name = tenancy.header.as_deref().unwrap_or(DEFAULT_TENANT_HEADER);
+                parts
+                    .headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .ok_or_else(|| {
+                        reject(
+                            parts,
+                            format!("Missing {} header", name),
+                            StatusCode::BAD_REQUEST,
+                        )
+                    })?
+            }
+            TenantSource::Subdomain => subdomain(parts).ok_or_else(|| {
+                reject(parts, "Missing tenant subdomain", StatusCode::BAD_REQUEST)
+            })?,
+            TenantSource::Claim => claim(parts, state, &tenancy).await?,
+        };
+
+        if !tenancy.tenants.contains_key(&id) {
+            tracing::debug!("tenant: rejected unknown tenant '{}'", id);
+            return Err(reject(parts, "Unknown tenant", StatusCode::NOT_FOUND));
+        }
+
+        Ok(Tenant(id))
+    }
+}
+
+/// First label of the host, when the host has a subdomain
+fn subdomain(parts: &Parts) -> Option<String> {
+    let host = parts
+        .headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| parts.uri.host())?;
+    let host = host.split(':').next().unwrap_or(host);
+
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return None;
+    }
+
+    match host.split_once('.') {
+        Some((label, _)) if !label.is_empty() => Some(label.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "auth")]
+async fn claim<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    tenancy: &TenancyConfig,
+) -> Result<String, Response> {
+    use axum::response::IntoResponse;
+
+    let user =
+        crate::auth::AuthenticatedUserOf::<serde_json::Value>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+    let name = tenancy.claim.as_deref().unwrap_or(DEFAULT_TENANT_CLAIM);
+    user.extra
+        .get(name)
+        .and_then(|value| value.as_str())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            reject(
+                parts,
+                format!("Token has no {} claim", name),
+                StatusCode::FORBIDDEN,
+            )
+        })
+}
+
+#[cfg(not(feature = "auth"))]
+async fn claim<S: Send + Sync>(
+    parts: &mut Parts,
+    _state: &S,
+    _tenancy: &TenancyConfig,
+) -> Result<String, Response> {
+    Err(reject(
+        parts,
+        "Tenancy not configured",
+        StatusCode::INTERNAL_SERVER_ERROR,
+    ))
+}
+
+fn reject(parts: &Parts, error: impl Into<String>, status: StatusCode) -> Response {
+    ErrorBody::new(error, &parts.headers).into_response_with(status)
+}
+
+/// Connections to each configured tenant's database, opened at startup
+#[cfg(feature = "database")]
+#[derive(Debug, Clone, Default)]
+pub struct TenantDatabases(Arc<HashMap<String, DatabaseConnection>>);
+
+#[cfg(feature = "database")]
+impl TenantDatabases {
+    /// Connect to every tenant in `tenancy`, creating missing databases
+    pub async fn connect(config: &Config) -> anyhow::Result<Self> {
+        let mut databases = HashMap::new();
+
+        for (id, tenant) in config
+            .tenancy
+            .iter()
+            .flat_map(|tenancy| tenancy.tenants.iter())
+        {
+            tracing::info!("tenant: connecting database for '{}'", id);
+            let db = crate::database::setup_database(
+                &tenant.database_url.clone().or(config.database_url.clone()),
+                &Some(tenant.database_name.clone().unwrap_or_else(|| id.clone())),
+                &tenant
+                    .database_schema
+                    .clone()
+                    .or(config.database_schema.clone()),
+                &None,
+                &config.database_pool,
+                &crate::database::QueryTracing::from_config(config),
+            )
+            .await?;
+            databases.insert(id.clone(), db);
+        }
+
+        Ok(Self(Arc::new(databases)))
+    }
+
+    /// Every tenant sharing one connection, for tests against a single database
+    pub fn shared(config: &Config, db: &DatabaseConnection) -> Self {
+        Self(Arc::new(
+            config
+                .tenancy
+                .iter()
+                .flat_map(|tenancy| tenancy.tenants.keys())
+                .map(|id| (id.clone(), db.clone()))
+                .collect(),
+        ))
+    }
+
+    pub fn get(&self, tenant: &str) -> Option<&DatabaseConnection> {
+        self.0.get(tenant)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &DatabaseConnection)> {
+        self.0.iter()
+    }
+}
+
+/// Database connection of the request's [`Tenant`]
+///
+/// Rejects like [`Tenant`], and with 500 when tenant databases aren't set up
+#[cfg(feature = "database")]
+#[derive(Debug, Clone)]
+pub struct TenantDb {
+    pub tenant: Tenant,
+    pub db: DatabaseConnection,
+}
+
+#[cfg(feature = "database")]
+impl Deref for TenantDb {
+    type Target = DatabaseConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
+
+#[cfg(feature = "database")]
+impl<S> FromRequestParts<S> for TenantDb
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let tenant = Tenant::from_request_parts(parts, state).await?;
+        if !crate::database::admit(
+            &parts.extensions,
+            crate::database::Pool::Tenant(tenant.id().to_string()),
+        ) {
+            return Err(crate::database::busy(&parts.headers));
+        }
+
+        match parts
+            .extensions
+            .get::<TenantDatabases>()
+            .and_then(|databases| databases.get(tenant.id()))
+        {
+            Some(db) => Ok(TenantDb {
+                db: db.clone(),
+                tenant,
+            }),
+            None => {
+                tracing::error!(
+                    "TenantDatabases not found in request extensions. \
+                         Did you forget to call with_database?"
+                );
+                Err(reject(
+                    parts,
+                    "Tenant database not configured",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ))
+            }
+        }
+    }
+}
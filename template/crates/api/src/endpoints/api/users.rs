@@ -1,9 +1,11 @@
-use axum::{Extension, Json, extract::State};
-use entities::users::{ActiveModel, Entity, Model};
+use axum::{Extension, Json};
+use entities::users::{ActiveModel, Column, Entity, Model};
+use microkit::error::{ErrorBody, FieldError};
+use microkit::pagination::paginate;
 use microkit::prelude::*;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 const GROUP: &str = "Users (API)";
 const PATH: &str = "/api/v1/users";
@@ -13,6 +15,26 @@ pub struct UserRequest {
     pub name: String,
 }
 
+/// Filters for listing users
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserFilter {
+    /// Only users whose name contains this text
+    pub name: Option<String>,
+}
+
+impl Validate for UserFilter {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        match &self.name {
+            Some(name) if name.chars().count() > 100 => Err(vec![FieldError {
+                field: "name".to_string(),
+                message: "must be at most 100 characters".to_string(),
+            }]),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[api_contract]
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserResponse {
@@ -26,22 +48,31 @@ pub struct UserResponse {
     get,
     path = PATH,
     tag = GROUP,
+    params(PageParams, UserFilter),
     responses(
-        (status = 200, description = "List of users", body = [UserResponse])
+        (status = 200, description = "Page of users", body = Paginated<UserResponse>),
+        (status = 400, description = "Invalid query parameters, the failing ones are listed in `fields`", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody)
     )
 )]
-pub async fn api_get_users(State(db): State<DatabaseConnection>) -> Json<Vec<UserResponse>> {
-    let users = Entity::find().all(&db).await.unwrap();
-    let responses = users
-        .into_iter()
-        .map(|u| UserResponse {
-            creation_system: u.creation_system,
-            creation_key: u.creation_key,
-            name: u.name,
-        })
-        .collect();
+pub async fn api_get_users(
+    page: Page,
+    ValidatedQuery(filter): ValidatedQuery<UserFilter>,
+    db: ReadDb,
+) -> ApiResult<Paginated<UserResponse>> {
+    let mut select = Entity::find();
+    if let Some(name) = &filter.name {
+        select = select.filter(Column::Name.contains(name));
+    }
+
+    let users = paginate(select, &*db, page).await?;
+    let responses = users.map(|u| UserResponse {
+        creation_system: u.creation_system,
+        creation_key: u.creation_key,
+        name: u.name,
+    });
 
-    Json(responses)
+    Ok(Json(responses))
 }
 
 /// Create user
@@ -53,34 +84,32 @@ pub async fn api_get_users(State(db): State<DatabaseConnection>) -> Json<Vec<Use
     tag = GROUP,
     request_body = UserRequest,
     responses(
-        (status = 200, description = "User inserted", body = UserResponse),
-        (status = 401, description = "Unauthorized - Invalid or missing bearer token")
+        (status = 201, description = "User created", body = UserResponse),
+        (status = 400, description = "Invalid request body, the failing field is listed in `fields`"),
+        (status = 401, description = "Unauthorized - Invalid or missing bearer token"),
+        (status = 409, description = "User already exists", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody)
     ),
     security(
         ("bearer" = []),
-        ("oauth2" = ["openid", "email", "profile"]),
         ("oidc" = ["openid", "email", "profile"])
     )
 )]
 pub async fn api_create_user(
     auth_user: AuthenticatedUser,
     Extension(config): Extension<Config>,
-    State(db): State<DatabaseConnection>,
-    Json(payload): Json<UserRequest>,
-) -> Json<UserResponse> {
-    tracing::info!(
-        user_id = %auth_user.sub,
-        email = ?auth_user.email,
-        groups = ?auth_user.groups,
-        "User creating new user via API"
-    );
+    db: Db,
+    JsonBody(payload): JsonBody<UserRequest>,
+) -> Result<Created<Json<UserResponse>>, AppError> {
+    tracing::info!(groups = ?auth_user.groups, "User creating new user via API");
 
     let active_model = ActiveModel::from_api(&config, payload.name);
-    let inserted: Model = active_model.insert(&db).await.unwrap();
+    let inserted: Model = active_model.insert(&*db).await?;
 
-    Json(UserResponse {
+    // No single-user route to point a Location header at yet
+    Ok(Created(Json(UserResponse {
         creation_system: inserted.creation_system,
         creation_key: inserted.creation_key,
         name: inserted.name,
-    })
+    })))
 }
@@ -1,11 +1,21 @@
-use axum::{Json, extract::State, http::StatusCode};
+use axum::response::Response;
 use contracts::UserCreatedEvent;
-use entities::users::ActiveModel;
+use entities::users::{ActiveModel, Entity, Model};
+use microkit::database::transaction;
+use microkit::entity::validate_creation_fields;
+use microkit::prelude::*;
+use microkit::pubsub::{CloudEvent, DeadLetter};
 use sea_orm::entity::prelude::*;
+use utoipa_axum::router::OpenApiRouter;
 
 const GROUP: &str = "Users (CONSUMER)";
 const PATH: &str = "/consumer/v1/users";
 
+/// Count deliveries and time them by topic and outcome
+pub(crate) fn layer(router: OpenApiRouter) -> OpenApiRouter {
+    router.with_consumer_metrics()
+}
+
 /// Create user
 // #[tracing::instrument(skip(db))]
 #[tracing::instrument()]
@@ -15,20 +25,32 @@ const PATH: &str = "/consumer/v1/users";
     tag = GROUP,
     request_body = UserCreatedEvent,
     responses(
-        (status = 200, description = "User created"),
-        (status = 400, description = "Bad request - missing required fields"),
-        (status = 409, description = "Conflict - user with this creation_system/creation_key already exists")
+        (status = 200, description = "Event processed or skipped as this service's own, or dead-lettered when its creation tracking fields are invalid or the user already exists"),
+        (status = 400, description = "Bad request - malformed event"),
+        (status = 500, description = "Retryable failure, Dapr redelivers the event")
     )
 )]
 pub async fn consumer_create_user(
-    State(db): State<DatabaseConnection>,
-    Json(event): Json<UserCreatedEvent>,
-) -> Result<(), StatusCode> {
-    if event.creation_system.is_empty() || event.creation_key.is_empty() {
-        tracing::error!("Missing required creation tracking fields");
-        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    db: Db,
+    dead_letter: DeadLetter,
+    event: CloudEvent<UserCreatedEvent>,
+) -> Response {
+    dead_letter
+        .consume(event, async |event| create_user(&db, event.clone()).await)
+        .await
+}
+
+async fn create_user(db: &Db, event: UserCreatedEvent) -> Result<(), AppError> {
+    // This service publishes user.created itself, those users are already stored
+    if microkit::events::is_own_event(&event) {
+        tracing::debug!(creation_key = %event.creation_key, "Skipping own user event");
+        return Ok(());
     }
 
+    // Rejects missing and malformed keys with a 400 before they reach the database
+    validate_creation_fields::<Model>(&event.creation_system, &event.creation_key)
+        .inspect_err(|e| tracing::error!(error = %e, "Invalid creation tracking fields"))?;
+
     tracing::info!(
         creation_system = %event.creation_system,
         creation_key = %event.creation_key,
@@ -37,15 +59,17 @@ pub async fn consumer_create_user(
         "Creating user from Dapr event"
     );
 
-    let active_model = ActiveModel::from_event(event);
-    let inserted = active_model.insert(&db).await.map_err(|e| {
-        tracing::error!(error = %e, "Failed to insert user from event");
-        if e.to_string().contains("duplicate key") {
-            StatusCode::CONFLICT
-        } else {
-            StatusCode::INTERNAL_SERVER_ERROR
+    // Check-then-insert atomically so redelivered events are reported as conflicts
+    let inserted = transaction(db, async |txn| {
+        let key = (event.creation_system.clone(), event.creation_key.clone());
+        if Entity::find_by_id(key).one(txn).await?.is_some() {
+            return Err(AppError::conflict("User already exists"));
         }
-    })?;
+
+        Ok(ActiveModel::from_event(event).insert(txn).await?)
+    })
+    .await
+    .inspect_err(|e| tracing::error!(error = %e, "Failed to insert user from event"))?;
 
     tracing::info!(
         creation_system = %inserted.creation_system,
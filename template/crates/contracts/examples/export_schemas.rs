@@ -0,0 +1,19 @@
+//! Write a JSON Schema for each contract, run by `mk contracts export`
+//!
+//! List new contracts here so consumers in other languages get their schemas
+
+use contracts::UserCreatedEvent;
+use microkit::schema::write_json_schema;
+use std::path::PathBuf;
+
+fn main() -> anyhow::Result<()> {
+    let dir = PathBuf::from(std::env::args().nth(1).unwrap_or_else(|| "schemas".into()));
+
+    let paths = vec![write_json_schema::<UserCreatedEvent>(&dir)?];
+
+    for path in paths {
+        println!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
@@ -5,6 +5,11 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize, CreationTracked)]
 #[sea_orm(table_name = "users")]
+#[creation_tracking(
+    max_key_len = 64,
+    key_charset = "url_safe",
+    event = "contracts::UserCreatedEvent"
+)]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub creation_system: String,
@@ -18,15 +23,38 @@ pub struct Model {
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn after_save<C>(model: Model, _db: &C, insert: bool) -> Result<Model, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        microkit::events::publish_created(&model, insert).await;
+        Ok(model)
+    }
+}
+
+impl DomainEvent for Model {
+    type Event = contracts::UserCreatedEvent;
+    const TOPIC: &'static str = "user.created";
+
+    fn to_event(&self) -> Self::Event {
+        contracts::UserCreatedEvent {
+            creation_system: self.creation_system.clone(),
+            creation_key: self.creation_key.clone(),
+            generated_on: self.generated_on,
+            name: self.name.clone(),
+        }
+    }
+}
 
 impl ActiveModel {
     /// Create an ActiveModel from an API request
     pub fn from_api(config: &microkit::config::Config, name: String) -> Self {
         Self {
             creation_system: Set(config.service_name.clone()),
-            creation_key: Set(uuid::Uuid::new_v4().to_string()),
-            generated_on: Set(chrono::Utc::now()),
+            creation_key: Set(microkit::entity::new_creation_key(config)),
+            generated_on: Set(microkit::clock::now()),
             name: Set(name),
         }
     }
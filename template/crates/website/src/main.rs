@@ -1,18 +1,20 @@
 use api::endpoints::api::users::{UserRequest, UserResponse};
 use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use dioxus::prelude::*;
+use microkit::ServicePort;
+use microkit::config::Config;
 use rand::Rng;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
+use std::sync::LazyLock;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 
 // microkit.yml is two levels up from this crate's manifest dir
 const MICROKIT_YML: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../microkit.yml");
 
-const API_BASE: &str = "http://localhost:50000";
-const CALLBACK_PORT: u16 = 4444;
-const CALLBACK_URI: &str = "http://localhost:4444/callback";
+// Where the API and the login callback live, read from microkit.yml once
+static ENDPOINTS: LazyLock<Result<Endpoints, String>> = LazyLock::new(load_endpoints);
 
 const FAVICON: Asset = asset!("/assets/favicon.ico");
 const TAILWIND_CSS: Asset = asset!("/assets/tailwind.css");
@@ -35,7 +37,7 @@ struct AuthConfigYaml {
     /// For Cognito: https://cognito-idp.{region}.amazonaws.com/{userPoolId}
     pub issuer: String,
     /// OIDC JWKS URI (server-side validation — not used by the website directly).
-    pub jwks_uri: String,
+    pub jwks_uri: Option<String>,
     /// Expected audience / client ID for token validation.
     pub audience: Option<String>,
     /// Default scopes. Falls back to `openid email profile` if absent.
@@ -46,6 +48,64 @@ struct AuthConfigYaml {
     pub client_secret: Option<String>,
 }
 
+/// Port the OIDC redirect is received on unless `website.callback_on_client_port` is set.
+const DEFAULT_CALLBACK_PORT: u16 = 4444;
+
+/// The website's own settings, under `website` in microkit.yml.
+#[derive(Debug, Default, Deserialize)]
+struct WebsiteConfig {
+    /// Receive the OIDC redirect on the Client port + `port_offset` instead of 4444.
+    #[serde(default)]
+    callback_on_client_port: bool,
+}
+
+/// URLs derived from microkit.yml the same way the server binds its ports.
+struct Endpoints {
+    /// The API's base URL: `public_url`, or `host` + the Api port + `port_offset`.
+    api_base: String,
+    /// Where the OIDC redirect is received: 4444, or the Client port + `port_offset`.
+    callback_port: u16,
+}
+
+impl Endpoints {
+    /// Redirect URI to register with the identity provider.
+    fn callback_uri(&self) -> String {
+        format!("http://localhost:{}/callback", self.callback_port)
+    }
+}
+
+fn load_endpoints() -> Result<Endpoints, String> {
+    let contents = std::fs::read_to_string(MICROKIT_YML)
+        .map_err(|e| format!("Could not read microkit.yml: {e}"))?;
+    let config: Config = serde_yaml_ng::from_str(&contents)
+        .map_err(|e| format!("Could not parse microkit.yml: {e}"))?;
+
+    let api_base = config
+        .service_url(ServicePort::Api)
+        .map_err(|e| e.to_string())?;
+    let website: WebsiteConfig = config
+        .extra_as::<Option<WebsiteConfig>>("website")
+        .map_err(|e| format!("{e:#}"))?
+        .unwrap_or_default();
+    // Without a port_offset the server binds 80, which a desktop app can't listen on
+    let callback_port = if website.callback_on_client_port {
+        ServicePort::Client
+            .get_with_offset(config.port_offset.unwrap_or(0))
+            .map_err(|e| e.to_string())?
+    } else {
+        DEFAULT_CALLBACK_PORT
+    };
+
+    Ok(Endpoints {
+        api_base,
+        callback_port,
+    })
+}
+
+fn endpoints() -> Result<&'static Endpoints, String> {
+    ENDPOINTS.as_ref().map_err(Clone::clone)
+}
+
 fn load_auth_config() -> Result<AuthConfigYaml, String> {
     let contents = std::fs::read_to_string(MICROKIT_YML)
         .map_err(|e| format!("Could not read microkit.yml: {e}"))?;
@@ -159,12 +219,13 @@ async fn exchange_code(
     client_id: &str,
     code: &str,
     code_verifier: &str,
+    callback_uri: &str,
 ) -> Result<String, String> {
     let body = url::form_urlencoded::Serializer::new(String::new())
         .append_pair("grant_type", "authorization_code")
         .append_pair("client_id", client_id)
         .append_pair("code", code)
-        .append_pair("redirect_uri", CALLBACK_URI)
+        .append_pair("redirect_uri", callback_uri)
         .append_pair("code_verifier", code_verifier)
         .finish();
     reqwest::Client::new()
@@ -183,6 +244,8 @@ async fn exchange_code(
 /// Full Authorization Code + PKCE login flow.
 async fn oidc_login() -> Result<String, String> {
     let config = load_auth_config()?;
+    let endpoints = endpoints()?;
+    let callback_uri = endpoints.callback_uri();
 
     let client_id = config
         .client_id
@@ -206,7 +269,7 @@ async fn oidc_login() -> Result<String, String> {
         .query_pairs_mut()
         .append_pair("response_type", "code")
         .append_pair("client_id", client_id)
-        .append_pair("redirect_uri", CALLBACK_URI)
+        .append_pair("redirect_uri", &callback_uri)
         .append_pair("scope", &scopes)
         .append_pair("state", &state)
         .append_pair("code_challenge", &challenge)
@@ -214,13 +277,20 @@ async fn oidc_login() -> Result<String, String> {
 
     webbrowser::open(auth_url.as_str()).map_err(|e| format!("Could not open browser: {e}"))?;
 
-    let (code, returned_state) = wait_for_callback(CALLBACK_PORT).await?;
+    let (code, returned_state) = wait_for_callback(endpoints.callback_port).await?;
 
     if returned_state != state {
         return Err("State mismatch — possible CSRF attack, aborting.".to_string());
     }
 
-    exchange_code(&discovery.token_endpoint, client_id, &code, &verifier).await
+    exchange_code(
+        &discovery.token_endpoint,
+        client_id,
+        &code,
+        &verifier,
+        &callback_uri,
+    )
+    .await
 }
 
 // ---------------------------------------------------------------------------
@@ -337,6 +407,12 @@ fn Navbar() -> Element {
 // Users page
 // ---------------------------------------------------------------------------
 
+/// Mirrors `Paginated<T>` in microkit/src/pagination.rs; only the items are shown.
+#[derive(Debug, Deserialize)]
+struct UserPage {
+    items: Vec<UserResponse>,
+}
+
 #[component]
 fn Users() -> Element {
     let token = use_context::<AuthToken>();
@@ -346,14 +422,18 @@ fn Users() -> Element {
     let mut users = use_resource(move || async move {
         // Reading token() here means the resource re-runs if auth state changes.
         let bearer = token().unwrap_or_default();
+        let api_base = match endpoints() {
+            Ok(endpoints) => &endpoints.api_base,
+            Err(e) => return Err(e),
+        };
         match reqwest::Client::new()
-            .get(format!("{API_BASE}/api/v1/users"))
+            .get(format!("{api_base}/api/v1/users"))
             .bearer_auth(bearer)
             .send()
             .await
         {
-            Ok(r) => match r.json::<Vec<UserResponse>>().await {
-                Ok(list) => Ok(list),
+            Ok(r) => match r.json::<UserPage>().await {
+                Ok(page) => Ok(page.items),
                 Err(e) => Err(e.to_string()),
             },
             Err(e) => Err(e.to_string()),
@@ -422,8 +502,15 @@ fn Users() -> Element {
                         onclick: move |_| async move {
                             *status.write() = None;
                             let bearer = token().unwrap_or_default();
+                            let api_base = match endpoints() {
+                                Ok(endpoints) => &endpoints.api_base,
+                                Err(e) => {
+                                    *status.write() = Some(e);
+                                    return;
+                                }
+                            };
                             match reqwest::Client::new()
-                                .post(format!("{API_BASE}/api/v1/users"))
+                                .post(format!("{api_base}/api/v1/users"))
                                 .bearer_auth(bearer)
                                 .json(&UserRequest { name: name() })
                                 .send()